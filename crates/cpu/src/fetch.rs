@@ -12,7 +12,7 @@ const IMPLIED: Operand = Operand { address: 0, data: 0, cycle: 0 };
 impl Cpu {
     pub(crate) fn fetch_u8(&mut self, system: &mut dyn memory::system::SystemBus) -> u8 {
         let v = system.read_u8(self.pc);
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         v
     }
 
@@ -109,10 +109,8 @@ impl Cpu {
                 Operand { address, data: system.read_u8(address), cycle: 4 + additional_cycle }
             }
             AddressingMode::Relative => {
-                let v = i32::from(self.fetch_u8(system)) + (self.pc as i32);
-                debug_assert!(0x0 <= v && v < 0x10000);
-
-                let address = v as u16;
+                let offset = self.fetch_u8(system);
+                let address = self.pc.wrapping_add(u16::from(offset));
                 let additional_cycle = if (address & 0xff00u16) != (self.pc & 0xff00u16) {
                     1
                 } else {
@@ -125,15 +123,16 @@ impl Cpu {
 
     pub(crate) fn stack_push(&mut self, system: &mut dyn memory::system::SystemBus, data: u8) {
         system.write_u8(self.sp, data);
-        self.sp = self.sp - 1;
+        self.sp -= 1;
     }
 
     pub(crate) fn stack_pop(&mut self, system: &mut dyn memory::system::SystemBus) -> u8 {
-        self.sp = self.sp + 1;
+        self.sp += 1;
         system.read_u8(self.sp)
     }
 }
 
+#[cfg(test)]
 mod tests {
     use memory::system::SystemBus;
     use crate::instruction::AddressingMode;
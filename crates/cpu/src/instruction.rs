@@ -1,3 +1,5 @@
+/// Mnemonics are kept in upper case as they are written in assembly.
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum Opcode {
     ADC,
@@ -102,6 +104,8 @@ pub enum Support {
 pub struct Instruction {
     pub opcode: Opcode,
     pub addressing_mode: AddressingMode,
+    /// Only checked by the tests, the CPU runs unofficial opcodes like the others.
+    #[cfg_attr(not(test), allow(dead_code))]
     pub support: Support,
 }
 
@@ -116,7 +120,7 @@ impl Instruction {
 
     #[inline(always)]
     fn make_opcode(op: u8) -> Opcode {
-        return match op {
+        match op {
             0x61|0x65|0x69|0x6d|0x71|0x75|0x79|0x7d         => Opcode::ADC,
             0x21|0x25|0x29|0x2d|0x31|0x35|0x39|0x3d         => Opcode::AND,
             0x06|0x0a|0x0e|0x16|0x1e                        => Opcode::ASL,
@@ -196,7 +200,7 @@ impl Instruction {
 
     #[inline(always)]
     fn make_addressing_mode(op: u8) -> AddressingMode {
-        return match op {
+        match op {
             0x00|0x08|0x18|0x1a|0x28|0x38|0x3a|0x40|0x48|0x58|0x5a|0x60|0x68|0x78|0x7a|
             0x88|0x8a|0x98|0x9a|0xa8|0xaa|0xb8|0xba|0xc8|0xca|0xd8|0xda|0xe8|0xea|0xf8|
             0xfa
@@ -236,7 +240,7 @@ impl Instruction {
 
     #[inline(always)]
     fn make_support(op: u8) -> Support {
-        return match op {
+        match op {
             0x03|0x04|0x07|0x0b|0x0c|0x0f|0x13|0x14|0x17|0x1a|0x1b|0x1c|0x1f|0x23|0x27|0x2f|
             0x33|0x34|0x37|0x3a|0x3b|0x3c|0x3f|0x43|0x44|0x47|0x4b|0x4f|0x53|0x54|0x57|0x5a|
             0x5b|0x5c|0x5f|0x63|0x64|0x67|0x6b|0x6f|0x73|0x74|0x77|0x7a|0x7b|0x7c|0x7f|0x80|
@@ -2,12 +2,9 @@ mod fetch;
 mod instruction;
 mod register;
 
-use memory::Memory;
-use memory::system::SystemBus;
-
 use crate::instruction::{Opcode,AddressingMode,Instruction};
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Cpu {
     // Accumulator
     pub a: u8,
@@ -31,19 +28,6 @@ pub enum Interrupt {
     RESET,
 }
 
-impl Default for Cpu {
-    fn default() -> Self {
-        Self {
-            a:  0,
-            x:  0,
-            y:  0,
-            p:  0,
-            pc: 0,
-            sp: 0,
-        }
-    }
-}
-
 impl Cpu {
     pub fn reset(&mut self) {
         self.a  = 0;
@@ -63,7 +47,7 @@ impl Cpu {
         let address = match request_type {
             Interrupt::BRK => {
                 self.write_break_flag(true);
-                self.pc = self.pc + 1;
+                self.pc = self.pc.wrapping_add(1);
 
                 let lo = (self.pc >> 8) as u8;
                 let hi = (self.pc & 0xff) as u8;
@@ -418,7 +402,7 @@ impl Cpu {
             Opcode::JSR => {
                 let operand = self.fetch(system, mode);
 
-                let address = current_pc + 2;
+                let address = current_pc.wrapping_add(2);
                 self.stack_push(system, (address >> 8) as u8);
                 self.stack_push(system, (address & 0xff) as u8);
                 self.pc = operand.address;
@@ -584,7 +568,7 @@ impl Cpu {
             Opcode::RTS => {
                 let lo = self.stack_pop(system);
                 let hi = self.stack_pop(system);
-                self.pc = u16::from(lo) | (u16::from(hi) << 8) + 1;
+                self.pc = (u16::from(lo) | (u16::from(hi) << 8)).wrapping_add(1);
                 6
             }
             Opcode::SAX => {
@@ -710,8 +694,23 @@ impl Cpu {
 
 #[cfg(test)]
 mod tests {
+    use memory::mapper::{Mapper, Mirroring};
     use memory::system::SystemBus;
 
+    // Flat RAM mapped over the whole cartridge space, so that tests can place vectors.
+    #[derive(Clone)]
+    struct CartridgeRam {
+        ram: Vec<u8>,
+    }
+
+    impl Mapper for CartridgeRam {
+        fn cpu_peek_u8(&self, address: u16) -> Option<u8> { Some(self.ram[usize::from(address)]) }
+        fn cpu_write_u8(&mut self, address: u16, data: u8) { self.ram[usize::from(address)] = data; }
+        fn ppu_read_u8(&mut self, _address: u16) -> u8 { 0 }
+        fn ppu_write_u8(&mut self, _address: u16, _data: u8) {}
+        fn mirroring(&self) -> Mirroring { Mirroring::Horizontal }
+    }
+
     # [test]
     fn reset()
     {
@@ -1090,7 +1089,7 @@ mod tests {
     fn execute_brk_instruction()
     {
         let mut cpu = super::Cpu::default();
-        let mut mem = memory::Memory::default();
+        let mut mem = memory::Memory::new(Box::new(CartridgeRam { ram: vec![0; 0x10000] }));
 
         cpu.p  = 0x82u8;
         cpu.sp = 0x00ffu16;
@@ -1285,6 +1284,7 @@ mod tests {
     }
 
     # [test]
+    #[allow(unused_variables)]
     fn execute_dcp_instruction()
     {
         let mut cpu = super::Cpu::default();
@@ -1711,6 +1711,38 @@ mod tests {
         assert_eq!(cycle, 0x02u8);
     }
 
+    # [test]
+    fn execute_instructions_across_address_space_end()
+    {
+        let mut cpu = super::Cpu::default();
+        let mut mem = memory::Memory::new(Box::new(CartridgeRam { ram: vec![0; 0x10000] }));
+
+        // The program counter wraps from $FFFF to $0000.
+        cpu.pc = 0xffffu16;
+        mem.write_u8(0xffff, 0xeau8);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.pc, 0x0000);
+
+        // JSR at $FFFE takes its high address byte from $0000 and returns to $0001.
+        cpu.sp = 0x01ffu16;
+        cpu.pc = 0xfffeu16;
+        mem.write_u8(0xfffe, 0x20u8);
+        mem.write_u8(0xffff, 0x34u8);
+        mem.write_u8(0x0000, 0x12u8);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(mem.read_u8(0x01ff), 0x00);
+        assert_eq!(mem.read_u8(0x01fe), 0x00);
+
+        // Branches past $FFFF land at the start of the address space.
+        cpu.pc = 0xfffeu16;
+        cpu.write_carry_flag(false);
+        mem.write_u8(0xfffe, 0x90u8);
+        mem.write_u8(0xffff, 0x10u8);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.pc, 0x0010);
+    }
+
     # [test]
     fn execute_ora_instruction()
     {
@@ -1995,6 +2027,25 @@ mod tests {
         assert_eq!(cycle, 6);
     }
 
+    # [test]
+    fn execute_rts_instruction_with_odd_return_address()
+    {
+        let mut cpu = super::Cpu::default();
+        let mut mem = memory::Memory::default();
+
+        // The increment must carry through the whole pulled address.
+        for (lo, pc) in [(0x35u8, 0x1236u16), (0xffu8, 0x1300u16)] {
+            cpu.sp = 0x00fdu16;
+            cpu.pc = 0x0000u16;
+            mem.write_u8(0x0000, 0x60u8);
+            mem.write_u8(0x00ff, 0x12u8);
+            mem.write_u8(0x00fe, lo);
+
+            cpu.step(&mut mem);
+            assert_eq!(cpu.pc, pc);
+        }
+    }
+
 
     # [test]
     fn execute_sax_instruction()
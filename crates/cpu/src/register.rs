@@ -5,6 +5,7 @@ pub const ZERO_FLAG:      u8 = 0x02u8;
 pub const INTERRUPT_FLAG: u8 = 0x04u8;
 pub const DECIMAL_FLAG:   u8 = 0x08u8;
 pub const BREAK_FLAG:     u8 = 0x10u8;
+#[cfg(test)]
 pub const RESERVED_FLAG:  u8 = 0x20u8;
 pub const OVERFLOW_FLAG:  u8 = 0x40u8;
 pub const NEGATIVE_FLAG:  u8 = 0x80u8;
@@ -41,6 +42,7 @@ impl Cpu {
     pub(crate) fn write_decimal_flag(&mut self, is_active: bool) {
         self.write_status_flag(DECIMAL_FLAG, is_active);
     }
+    #[cfg(test)]
     #[inline(always)]
     pub(crate) fn read_decimal_flag(&mut self) -> bool {
         self.read_status_flag(DECIMAL_FLAG)
@@ -50,15 +52,13 @@ impl Cpu {
     pub(crate) fn write_break_flag(&mut self, is_active: bool) {
         self.write_status_flag(BREAK_FLAG, is_active);
     }
+    #[cfg(test)]
     #[inline(always)]
     pub(crate) fn read_break_flag(&mut self) -> bool {
         self.read_status_flag(BREAK_FLAG)
     }
 
-    #[inline(always)]
-    pub(crate) fn write_reserved_flag(&mut self, is_active: bool) {
-        self.write_status_flag(RESERVED_FLAG, is_active);
-    }
+    #[cfg(test)]
     #[inline(always)]
     pub(crate) fn read_reserved_flag(&mut self) -> bool {
         self.read_status_flag(RESERVED_FLAG)
@@ -87,9 +87,9 @@ impl Cpu {
     }
     fn write_status_flag(&mut self, status: u8, is_active: bool) {
         if is_active {
-            self.p = self. p | status;
+            self.p |= status;
         } else {
-            self.p = self.p & (!status);
+            self.p &= !status;
         }
     }
 }
\ No newline at end of file
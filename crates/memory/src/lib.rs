@@ -1,28 +1,56 @@
+pub mod mapper;
 pub mod system;
+pub mod system_hook;
 pub mod system_ppu_registers;
+pub mod system_video;
+
+use crate::mapper::{Mapper, NoCartridge};
+use crate::system_ppu_registers::LoopyRegisters;
 
 pub const CPU_RAM_SIZE: usize = 0x0800;
 pub const PPU_REGISTER_SIZE: usize = 0x0008;
+pub const VRAM_SIZE: usize = 0x1000;
+pub const PALETTE_SIZE: usize = 0x0020;
 
 pub const CPU_RAM_BASE_ADDRESS: u16 = 0x0000;
 pub const PPU_REGISTER_BASE_ADDRESS: u16 = 0x2000;
 pub const APU_IO_REGISTER_BASE_ADDRESS: u16 = 0x4000;
+pub const PALETTE_BASE_ADDRESS: u16 = 0x3f00;
+pub const OAM_DMA_ADDRESS: u16 = 0x4014;
+
+/// Bytes copied into OAM by a write to $4014.
+pub const OAM_DMA_SIZE: usize = 0x0100;
+
+/// CPU cycles after which a bit of the PPU I/O latch that is not driven again decays to 0, about 600ms.
+pub const PPU_IO_LATCH_DECAY_CYCLES: usize = 1_073_864;
 
 #[derive(Clone)]
 pub struct Memory {
     pub ram: [u8; CPU_RAM_SIZE],
     pub ppu_registers: [u8; PPU_REGISTER_SIZE],
+    pub vram: [u8; VRAM_SIZE],
+    /// Palette RAM inside the PPU at $3F00-$3F1F, which $2007 accesses without the read buffer.
+    pub palette: [u8; PALETTE_SIZE],
+    pub mapper: Box<dyn Mapper>,
 
     request_to_read_oam_data: bool,
-    request_to_read_ppu_data: bool,
     request_to_write_oam_data: bool,
-    request_to_write_ppu_scroll: bool,
-    request_to_write_ppu_address: bool,
-    request_to_write_ppu_data: bool,
 
-    is_second_write: bool,
-    ppu_register_scroll_y: u8,
-    ppu_register_address_lower: u8,
+    loopy: LoopyRegisters,
+    /// Data fetched by the last $2007 read, which the next read returns.
+    ppu_data_buffer: u8,
+
+    /// Page written to $4014 that has not been copied into OAM yet.
+    oam_dma_page: Option<u8>,
+    /// Page already read through the bus hooks, which the PPU copies instead of reading the page itself.
+    oam_dma_data: Option<[u8; OAM_DMA_SIZE]>,
+
+    /// Last value driven on the CPU data bus, which reads of unmapped addresses return.
+    cpu_open_bus: u8,
+    /// Value left on the PPU data bus by register accesses, which reads of write-only registers return.
+    ppu_io_latch: u8,
+    /// CPU cycles since each bit of `ppu_io_latch` was last driven.
+    ppu_io_latch_ages: [usize; 8],
 }
 
 impl Default for Memory {
@@ -30,17 +58,57 @@ impl Default for Memory {
         Self {
             ram: [0; CPU_RAM_SIZE],
             ppu_registers: [0; PPU_REGISTER_SIZE],
+            vram: [0; VRAM_SIZE],
+            palette: [0; PALETTE_SIZE],
+            mapper: Box::new(NoCartridge),
 
             request_to_read_oam_data: false,
-            request_to_read_ppu_data: false,
             request_to_write_oam_data: false,
-            request_to_write_ppu_scroll: false,
-            request_to_write_ppu_address: false,
-            request_to_write_ppu_data: false,
 
-            is_second_write: false,
-            ppu_register_scroll_y: 0,
-            ppu_register_address_lower: 0,
+            loopy: LoopyRegisters::default(),
+            ppu_data_buffer: 0,
+
+            oam_dma_page: None,
+            oam_dma_data: None,
+
+            cpu_open_bus: 0,
+            ppu_io_latch: 0,
+            ppu_io_latch_ages: [0; 8],
+        }
+    }
+}
+
+impl Memory {
+    pub fn new(mapper: Box<dyn Mapper>) -> Self {
+        Self {
+            mapper,
+            ..Default::default()
+        }
+    }
+
+    /// Returns whether a write to $4014 is waiting for the PPU to copy the page into OAM.
+    pub fn is_oam_dma_requested(&self) -> bool {
+        self.oam_dma_page.is_some() || self.oam_dma_data.is_some()
+    }
+
+    /// Returns the address of the page a write to $4014 asked to copy, until it is read.
+    pub fn oam_dma_address(&self) -> Option<u16> {
+        self.oam_dma_page.map(|page| u16::from(page) << 8)
+    }
+
+    /// Hands the PPU the requested page when it was read by the caller, so that it is not read twice.
+    pub fn set_oam_dma_data(&mut self, data: [u8; OAM_DMA_SIZE]) {
+        self.oam_dma_page = None;
+        self.oam_dma_data = Some(data);
+    }
+
+    /// Drives the bits of the PPU I/O latch selected by `mask`, which restarts their decay.
+    fn drive_ppu_io_latch(&mut self, mask: u8, data: u8) {
+        self.ppu_io_latch = (self.ppu_io_latch & !mask) | (data & mask);
+        for (bit, age) in self.ppu_io_latch_ages.iter_mut().enumerate() {
+            if (mask & (1 << bit)) != 0 {
+                *age = 0;
+            }
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,181 @@
+pub const CARTRIDGE_BASE_ADDRESS: u16 = 0x4020;
+pub const PATTERN_TABLE_END_ADDRESS: u16 = 0x1fff;
+pub const NAME_TABLE_SIZE: usize = 0x0400;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+impl Mirroring {
+    /// Returns the index of the physical 1KB nametable that backs the logical one (0-3).
+    pub fn nametable_bank(&self, table: usize) -> usize {
+        match self {
+            Mirroring::Horizontal        => (table >> 1) & 0x01,
+            Mirroring::Vertical          => table & 0x01,
+            Mirroring::FourScreen        => table & 0x03,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        }
+    }
+
+    /// Returns the VRAM index that backs a nametable address at $2000-$3EFF.
+    pub fn name_table_index(&self, address: u16) -> usize {
+        let offset = usize::from(address) % (NAME_TABLE_SIZE * 4);
+        self.nametable_bank(offset / NAME_TABLE_SIZE) * NAME_TABLE_SIZE + (offset % NAME_TABLE_SIZE)
+    }
+}
+
+/// What the PPU is fetching in the part of the scan line being rendered.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum FetchPhase {
+    /// Rendering is disabled or the PPU is in vertical blanking.
+    Idle,
+    /// Background tiles of the current line.
+    Background,
+    /// Sprite patterns for the next line.
+    Sprite,
+    /// The first two background tiles of the next line.
+    Prefetch,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct RenderingState {
+    pub line: u16,
+    pub phase: FetchPhase,
+    pub is_8x16_sprites: bool,
+}
+
+/// Cartridge hardware seen by the CPU at $4020-$FFFF and by the PPU at $0000-$1FFF.
+pub trait Mapper: MapperClone {
+    /// Returns what `cpu_read_u8` would, without side effects such as acknowledging an IRQ.
+    /// `None` leaves the CPU data bus open, so the CPU reads the last value on it.
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8>;
+    fn cpu_write_u8(&mut self, address: u16, data: u8);
+    fn ppu_read_u8(&mut self, address: u16) -> u8;
+    fn ppu_write_u8(&mut self, address: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    /// Reads from the CPU bus, mappers whose registers change when read override it.
+    fn cpu_read_u8(&mut self, address: u16) -> Option<u8> {
+        self.cpu_peek_u8(address)
+    }
+
+    /// Notifies the mapper of CPU cycles elapsed since the last call.
+    fn step(&mut self, _cpu_cycles: usize) {
+    }
+
+    /// Returns whether the cartridge is asserting the CPU IRQ line.
+    fn is_irq_pending(&self) -> bool {
+        false
+    }
+
+    /// Reads a nametable at $2000-$3EFF, which is backed by the console VRAM by default.
+    fn read_name_table_u8(&mut self, address: u16, vram: &[u8]) -> u8 {
+        vram[self.mirroring().name_table_index(address)]
+    }
+
+    fn write_name_table_u8(&mut self, address: u16, data: u8, vram: &mut [u8]) {
+        vram[self.mirroring().name_table_index(address)] = data;
+    }
+
+    /// Notifies the mapper of what the PPU is about to fetch.
+    fn set_rendering_state(&mut self, _state: RenderingState) {
+    }
+
+    /// Cartridge PRG-RAM, which is kept across power cycles when the cartridge has a battery.
+    fn prg_ram(&self) -> &[u8] {
+        &[]
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    /// The Famicom Disk System drive, which cartridges do not have.
+    fn disk_drive(&self) -> Option<&dyn DiskDrive> {
+        None
+    }
+
+    fn disk_drive_mut(&mut self) -> Option<&mut dyn DiskDrive> {
+        None
+    }
+}
+
+/// The disk drive attached to the Famicom Disk System RAM adapter.
+pub trait DiskDrive {
+    fn side_count(&self) -> usize;
+
+    /// Returns the inserted side, or `None` while the drive is empty.
+    fn inserted_side(&self) -> Option<usize>;
+
+    /// Inserts a side, or ejects the disk with `None`. Sides past `side_count` eject the disk.
+    fn insert_side(&mut self, side: Option<usize>);
+
+    /// Returns every side in the .fds layout, including what the game wrote.
+    fn sides(&self) -> Vec<Vec<u8>>;
+}
+
+pub trait MapperClone {
+    fn clone_box(&self) -> Box<dyn Mapper>;
+}
+
+impl<T: 'static + Mapper + Clone> MapperClone for T {
+    fn clone_box(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Placeholder used while no cartridge is inserted.
+#[derive(Clone, Default)]
+pub struct NoCartridge;
+
+impl Mapper for NoCartridge {
+    fn cpu_peek_u8(&self, _address: u16) -> Option<u8> {
+        None
+    }
+
+    fn cpu_write_u8(&mut self, _address: u16, _data: u8) {
+    }
+
+    fn ppu_read_u8(&mut self, _address: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write_u8(&mut self, _address: u16, _data: u8) {
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mapper::Mirroring;
+
+    # [test]
+    fn test_nametable_bank() {
+        for (mirroring, banks) in [
+            (Mirroring::Horizontal, [0, 0, 1, 1]),
+            (Mirroring::Vertical, [0, 1, 0, 1]),
+            (Mirroring::FourScreen, [0, 1, 2, 3]),
+            (Mirroring::SingleScreenLower, [0, 0, 0, 0]),
+            (Mirroring::SingleScreenUpper, [1, 1, 1, 1]),
+        ] {
+            for (table, bank) in banks.iter().enumerate() {
+                assert_eq!(mirroring.nametable_bank(table), *bank);
+            }
+        }
+    }
+}
@@ -1,12 +1,56 @@
-use crate::{Memory, PPU_REGISTER_BASE_ADDRESS, APU_IO_REGISTER_BASE_ADDRESS};
+use crate::{Memory, PPU_REGISTER_BASE_ADDRESS, APU_IO_REGISTER_BASE_ADDRESS, OAM_DMA_ADDRESS, PALETTE_BASE_ADDRESS};
+use crate::system_ppu_registers::PpuRegistersController;
+use crate::mapper::CARTRIDGE_BASE_ADDRESS;
 
 pub trait SystemBus {
     fn read_u8(&mut self, address: u16) -> u8;
     fn write_u8(&mut self, address: u16, data: u8);
+
+    /// Returns what `read_u8` would, without changing any state, for debuggers and memory viewers.
+    fn peek_u8(&self, address: u16) -> u8;
+
+    /// Fills `buffer` with `peek_u8` from `address` upwards, wrapping around after $FFFF.
+    fn peek_range(&self, address: u16, buffer: &mut [u8]) {
+        for (offset, data) in buffer.iter_mut().enumerate() {
+            *data = self.peek_u8(address.wrapping_add(offset as u16));
+        }
+    }
 }
 
 impl SystemBus for Memory {
     fn read_u8(&mut self, address: u16) -> u8 {
+        let data = if address < CARTRIDGE_BASE_ADDRESS {
+            self.peek_u8(address)
+        } else {
+            self.mapper.cpu_read_u8(address).unwrap_or(self.cpu_open_bus)
+        };
+
+        if (PPU_REGISTER_BASE_ADDRESS..APU_IO_REGISTER_BASE_ADDRESS).contains(&address) {
+            match usize::from(address - PPU_REGISTER_BASE_ADDRESS) % self.ppu_registers.len() {
+                // Only the status flags are driven, the low 5 bits come from the latch.
+                0x02 => {
+                    self.drive_ppu_io_latch(0xe0, data);
+                    self.on_vblank(false);
+                    self.loopy.w = false;
+                }
+                0x04 => {
+                    self.request_to_read_oam_data = true;
+                    self.drive_ppu_io_latch(0xff, data);
+                }
+                0x07 => {
+                    let mask = if (self.loopy.v & 0x3fff) >= PALETTE_BASE_ADDRESS { 0x3f } else { 0xff };
+                    self.drive_ppu_io_latch(mask, data);
+                    self.fill_ppu_data_buffer();
+                }
+                _ => {}
+            }
+        }
+
+        self.cpu_open_bus = data;
+        data
+    }
+
+    fn peek_u8(&self, address: u16) -> u8 {
         if address < PPU_REGISTER_BASE_ADDRESS {
             let index = usize::from(address) % self.ram.len();
             return self.ram[index];
@@ -14,27 +58,25 @@ impl SystemBus for Memory {
 
         if address < APU_IO_REGISTER_BASE_ADDRESS {
             let index = usize::from(address - PPU_REGISTER_BASE_ADDRESS) % self.ppu_registers.len();
-            let value = match index {
-                0x04 => {
-                    self.request_to_read_oam_data = true;
-                    self.ppu_registers[index]
-                },
-                0x07 => {
-                    self.request_to_read_ppu_data = true;
-                    self.ppu_registers[index]
-                }
-                _ => self.ppu_registers[index],
+            return match index {
+                0x02 => (self.ppu_registers[index] & 0xe0) | (self.ppu_io_latch & 0x1f),
+                0x04 => self.ppu_registers[index],
+                0x07 => self.peek_ppu_data(),
+                // Write-only registers.
+                _ => self.ppu_io_latch,
             };
-            return value;
         }
 
-        // TODO: Read from the contents of Rom.
-        //       BRK test fails if 0 is set as a fixed value
-        let index = usize::from(address) % self.ram.len();
-        return self.ram[index];
+        if address < CARTRIDGE_BASE_ADDRESS {
+            // TODO: Read from APU and I/O registers.
+            return self.cpu_open_bus;
+        }
+
+        self.mapper.cpu_peek_u8(address).unwrap_or(self.cpu_open_bus)
     }
 
     fn write_u8(&mut self, address: u16, data: u8) {
+        self.cpu_open_bus = data;
         if address < PPU_REGISTER_BASE_ADDRESS {
             let index = usize::from(address) % self.ram.len();
             self.ram[index] = data;
@@ -42,52 +84,46 @@ impl SystemBus for Memory {
         }
 
         if address < APU_IO_REGISTER_BASE_ADDRESS {
+            self.drive_ppu_io_latch(0xff, data);
             let index = usize::from(address - PPU_REGISTER_BASE_ADDRESS) % self.ppu_registers.len();
             match index {
+                0x00 => {
+                    self.ppu_registers[index] = data;
+                    self.loopy.write_ctrl(data);
+                },
+                // PPU STATUS is read-only.
+                0x02 => {},
                 0x04 => {
                     self.request_to_write_oam_data = true;
                     self.ppu_registers[index] = data;
                 },
-                0x05 => {
-                    if self.is_second_write {
-                        self.ppu_register_scroll_y = data;
-                        self.request_to_write_ppu_scroll = true;
-                        self.is_second_write = false;
-                    } else {
-                        self.ppu_registers[index] = data;
-                        self.is_second_write = true;
-                    }
-                },
-                0x06 => {
-                    if self.is_second_write {
-                        self.ppu_register_address_lower = data;
-                        self.request_to_write_ppu_address = true;
-                        self.is_second_write = false;
-                    } else {
-                        self.ppu_registers[index] = data;
-                        self.is_second_write = true;
-                    }
-                },
-                0x07 => {
-                    self.ppu_registers[index] = data;
-                    self.request_to_write_ppu_data = true;
-                }
+                0x05 => self.loopy.write_scroll(data),
+                0x06 => self.loopy.write_address(data),
+                0x07 => self.write_ppu_data(data),
                 _ => self.ppu_registers[index] = data
             };
             return;
         }
 
-        // TODO: Write to the contents of Rom.
-        //       If left unimplemented, the BRK test will fail.
-        let index = usize::from(address) % self.ram.len();
-        self.ram[index] = data;
+        if address == OAM_DMA_ADDRESS {
+            self.oam_dma_page = Some(data);
+            return;
+        }
+
+        if address < CARTRIDGE_BASE_ADDRESS {
+            // TODO: Write to APU and I/O registers.
+            return;
+        }
+
+        self.mapper.cpu_write_u8(address, data);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Memory;
+    use crate::{Memory, PPU_IO_LATCH_DECAY_CYCLES};
     use crate::system::SystemBus;
+    use crate::system_ppu_registers::PpuRegistersController;
 
     # [test]
     fn test_read_and_write_to_ram_address() {
@@ -107,43 +143,215 @@ mod tests {
         let mut mem = Memory::default();
 
         // 0x2000-0x2004
-        for code in 0x2000u16..=0x2004u16 {
+        for code in [0x2000u16, 0x2001u16, 0x2003u16, 0x2004u16] {
             mem.write_u8(code, 0xffu8);
             assert_eq!(mem.read_u8(code), 0xffu8);
         }
         assert!(mem.request_to_read_oam_data);
         assert!(mem.request_to_write_oam_data);
+        assert_eq!(mem.loopy().t, 0x0c00u16);
+
+        // PPU STATUS is read-only.
+        mem.write_u8(0x2002u16, 0xffu8);
+        assert!(!mem.is_vblank());
 
         // 0x2005
         {
-            mem.write_u8(0x2005u16, 0x34u8);
-            assert_eq!(mem.read_u8(0x2005u16), 0x34u8);
-            assert_eq!(mem.is_second_write, true);
+            mem.write_u8(0x2005u16, 0x7du8);
+            assert!(mem.loopy().w);
+            assert_eq!(mem.loopy().x, 0x05u8);
 
-            mem.write_u8(0x2005u16, 0x12u8);
-            assert_eq!(mem.ppu_register_scroll_y, 0x12u8);
-            assert_eq!(mem.request_to_write_ppu_scroll, true);
-            assert_eq!(mem.is_second_write, false);
+            mem.write_u8(0x2005u16, 0x5eu8);
+            assert!(!mem.loopy().w);
+            assert_eq!(mem.loopy().t, 0x6d6fu16);
+            assert_eq!((mem.loopy().scroll_x(), mem.loopy().scroll_y()), (0x7du8, 0x5eu8));
         }
 
         // 0x2006
         {
-            mem.write_u8(0x2006u16, 0x34u8);
-            assert_eq!(mem.read_u8(0x2006u16), 0x34u8);
-            assert_eq!(mem.is_second_write, true);
+            mem.write_u8(0x2006u16, 0x7du8);
+            assert!(mem.loopy().w);
+            assert_eq!(mem.loopy().t, 0x3d6fu16);
+            assert_eq!(mem.loopy().v, 0x0000u16);
 
-            mem.write_u8(0x2006u16, 0x12u8);
-            assert_eq!(mem.ppu_register_address_lower, 0x12u8);
-            assert_eq!(mem.request_to_write_ppu_address, true);
-            assert_eq!(mem.is_second_write, false);
+            mem.write_u8(0x2006u16, 0xf0u8);
+            assert!(!mem.loopy().w);
+            assert_eq!(mem.loopy().v, 0x3df0u16);
         }
 
         // 0x2007
         {
+            mem.write_u8(0x2006u16, 0x20u8);
+            mem.write_u8(0x2006u16, 0xffu8);
             mem.write_u8(0x2007u16, 0x56u8);
-            assert_eq!(mem.read_u8(0x2007u16), 0x56u8);
-            assert_eq!(mem.request_to_read_ppu_data, true);
-            assert_eq!(mem.request_to_write_ppu_data, true);
+            // Bit 2 of PPU CTRL is still set.
+            assert_eq!(mem.loopy().v, 0x211fu16);
+
+            mem.write_u8(0x2000u16, 0x00u8);
+            mem.write_u8(0x2006u16, 0x20u8);
+            mem.write_u8(0x2006u16, 0xffu8);
+            mem.write_u8(0x2007u16, 0x78u8);
+            assert_eq!(mem.loopy().v, 0x2100u16);
+        }
+    }
+
+    # [test]
+    fn test_shared_write_toggle() {
+        let mut mem = Memory::default();
+
+        // $2005 and $2006 share the toggle.
+        mem.write_u8(0x2005u16, 0x00u8);
+        mem.write_u8(0x2006u16, 0x12u8);
+        assert!(!mem.loopy().w);
+        assert_eq!(mem.loopy().v, 0x0012u16);
+
+        // Reading $2002 clears the toggle and the vblank flag.
+        mem.on_vblank(true);
+        mem.write_u8(0x2006u16, 0x21u8);
+        assert_eq!(mem.read_u8(0x2002u16) & 0x80u8, 0x80u8);
+        assert!(!mem.is_vblank());
+        assert_eq!(mem.read_u8(0x2002u16) & 0x80u8, 0x00u8);
+        mem.write_u8(0x2006u16, 0x23u8);
+        mem.write_u8(0x2006u16, 0x45u8);
+        assert_eq!(mem.loopy().v, 0x2345u16);
+    }
+
+    // Ported from blargg's ppu_tests/vram_access.
+    # [test]
+    fn test_ppu_data_read_buffer() {
+        let mut mem = Memory::default();
+        for (offset, data) in [0x12u8, 0x34u8, 0x56u8].iter().enumerate() {
+            mem.write_u8(0x2006u16, 0x23u8);
+            mem.write_u8(0x2006u16, offset as u8);
+            mem.write_u8(0x2007u16, *data);
         }
+
+        // Reads are delayed by the buffer.
+        mem.write_u8(0x2006u16, 0x23u8);
+        mem.write_u8(0x2006u16, 0x00u8);
+        assert_eq!(mem.read_u8(0x2007u16), 0x00u8);
+        assert_eq!(mem.read_u8(0x2007u16), 0x12u8);
+        assert_eq!(mem.peek_u8(0x2007u16), 0x34u8);
+        assert_eq!(mem.read_u8(0x2007u16), 0x34u8);
+
+        // Changing the address does not refill the buffer.
+        mem.write_u8(0x2006u16, 0x23u8);
+        mem.write_u8(0x2006u16, 0x00u8);
+        assert_eq!(mem.read_u8(0x2007u16), 0x56u8);
+        assert_eq!(mem.read_u8(0x2007u16), 0x12u8);
+
+        // $3000-$3EFF mirrors the nametables.
+        mem.write_u8(0x2006u16, 0x33u8);
+        mem.write_u8(0x2006u16, 0x01u8);
+        mem.read_u8(0x2007u16);
+        assert_eq!(mem.read_u8(0x2007u16), 0x34u8);
     }
-}
\ No newline at end of file
+
+    // Ported from blargg's ppu_tests/palette_ram and vram_access.
+    # [test]
+    fn test_palette_ram() {
+        let mut mem = Memory::default();
+        for (address, data) in [(0x2f00u16, 0x55u8), (0x3f00u16, 0x01u8), (0x3f01u16, 0x02u8), (0x3f14u16, 0x03u8)] {
+            mem.write_u8(0x2006u16, (address >> 8) as u8);
+            mem.write_u8(0x2006u16, address as u8);
+            mem.write_u8(0x2007u16, data);
+        }
+
+        // Palette reads are not buffered, and fill the buffer from the nametable underneath.
+        for (address, data) in [(0x3f00u16, 0x01u8), (0x3f10u16, 0x01u8), (0x3f04u16, 0x03u8), (0x3f21u16, 0x02u8)] {
+            mem.write_u8(0x2006u16, (address >> 8) as u8);
+            mem.write_u8(0x2006u16, address as u8);
+            assert_eq!(mem.read_u8(0x2007u16), data);
+        }
+        mem.write_u8(0x2006u16, 0x3fu8);
+        mem.write_u8(0x2006u16, 0x00u8);
+        mem.read_u8(0x2007u16);
+        mem.write_u8(0x2006u16, 0x20u8);
+        mem.write_u8(0x2006u16, 0x00u8);
+        assert_eq!(mem.read_u8(0x2007u16), 0x55u8);
+
+        // Entries are 6 bits wide, the top 2 bits come from the I/O latch.
+        mem.write_u8(0x2006u16, 0x3fu8);
+        mem.write_u8(0x2006u16, 0x1fu8);
+        mem.write_u8(0x2007u16, 0xffu8);
+        mem.write_u8(0x2006u16, 0x3fu8);
+        mem.write_u8(0x2006u16, 0x1fu8);
+        assert_eq!(mem.read_u8(0x2007u16), 0x3fu8);
+        assert_eq!(mem.palette[0x1f], 0x3fu8);
+    }
+
+    # [test]
+    fn test_write_oam_dma() {
+        let mut mem = Memory::default();
+        assert!(!mem.is_oam_dma_requested());
+        mem.write_u8(0x4014u16, 0x02u8);
+        assert!(mem.is_oam_dma_requested());
+        assert_eq!(mem.oam_dma_page, Some(0x02u8));
+        // $4014 is write-only.
+        assert_eq!(mem.read_u8(0x4014u16), 0x02u8);
+    }
+
+    # [test]
+    fn test_peek_has_no_side_effects() {
+        let mut mem = Memory::default();
+        mem.write_u8(0x2004u16, 0x34u8);
+        mem.write_u8(0x2006u16, 0x20u8);
+        mem.write_u8(0x2006u16, 0x00u8);
+        mem.write_u8(0x2007u16, 0x56u8);
+        mem.write_u8(0x2006u16, 0x20u8);
+        mem.write_u8(0x2006u16, 0x00u8);
+        mem.read_u8(0x2007u16);
+        mem.on_vblank(true);
+        mem.write_u8(0x2005u16, 0x00u8);
+        mem.write_u8(0x0001u16, 0x12u8);
+        mem.request_to_write_oam_data = false;
+
+        let loopy = mem.loopy();
+        assert_eq!(mem.peek_u8(0x0801u16), 0x12u8);
+        assert_eq!(mem.peek_u8(0x2004u16), 0x34u8);
+        assert_eq!(mem.peek_u8(0x3fffu16), 0x56u8);
+        assert_eq!(mem.peek_u8(0x2002u16) & 0x80u8, 0x80u8);
+        assert!(!mem.request_to_read_oam_data);
+        assert!(mem.is_vblank());
+        assert_eq!(mem.loopy(), loopy);
+
+        let mut buffer = [0u8; 4];
+        mem.peek_range(0xffffu16, &mut buffer);
+        // $FFFF is open bus, which holds the last value written.
+        assert_eq!(buffer, [0x12u8, 0x00u8, 0x12u8, 0x00u8]);
+    }
+
+    # [test]
+    fn test_cpu_open_bus() {
+        let mut mem = Memory::default();
+        mem.write_u8(0x0000u16, 0x12u8);
+        assert_eq!(mem.read_u8(0x4018u16), 0x12u8);
+
+        mem.write_u8(0x0001u16, 0x34u8);
+        mem.write_u8(0x0002u16, 0x56u8);
+        assert_eq!(mem.read_u8(0x0001u16), 0x34u8);
+        assert_eq!(mem.peek_u8(0x401fu16), 0x34u8);
+        // No cartridge is inserted.
+        assert_eq!(mem.read_u8(0x8000u16), 0x34u8);
+    }
+
+    # [test]
+    fn test_ppu_io_latch() {
+        let mut mem = Memory::default();
+        mem.write_u8(0x2000u16, 0x5au8);
+        for address in [0x2001u16, 0x2003u16, 0x2005u16, 0x2006u16, 0x3ff8u16] {
+            assert_eq!(mem.read_u8(address), 0x5au8);
+        }
+
+        mem.ppu_registers[0x02] = 0x80;
+        mem.decay_io_latch(PPU_IO_LATCH_DECAY_CYCLES - 10);
+        assert_eq!(mem.read_u8(0x2002u16), 0x9au8);
+        assert_eq!(mem.read_u8(0x2001u16), 0x9au8);
+
+        // The low 5 bits were last driven by the write and decay first.
+        mem.decay_io_latch(10);
+        assert_eq!(mem.read_u8(0x2001u16), 0x80u8);
+        mem.decay_io_latch(PPU_IO_LATCH_DECAY_CYCLES);
+        assert_eq!(mem.read_u8(0x2001u16), 0x00u8);
+    }
+}
@@ -0,0 +1,232 @@
+use std::ops::RangeInclusive;
+
+use crate::system::SystemBus;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// An opcode fetch, which is not reported as a read.
+    Execute,
+}
+
+/// A CPU bus access seen by hooks.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct BusEvent {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub data: u8,
+    /// CPU cycles since power-on when the instruction making the access started.
+    pub cpu_cycle: u64,
+    /// Address of the instruction making the access.
+    pub pc: u16,
+}
+
+/// What the emulator does after a hook returns.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum HookAction {
+    Continue,
+    /// Stops the emulation once the current instruction completes.
+    Pause,
+}
+
+/// Identifies an installed hook, for removing it.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct HookId(usize);
+
+/// Callback on bus accesses, implemented by closures that can be cloned along with the console.
+pub trait BusHook: BusHookClone {
+    fn on_access(&mut self, event: &BusEvent) -> HookAction;
+}
+
+impl<F> BusHook for F
+where
+    F: FnMut(&BusEvent) -> HookAction + Clone + 'static,
+{
+    fn on_access(&mut self, event: &BusEvent) -> HookAction {
+        self(event)
+    }
+}
+
+pub trait BusHookClone {
+    fn clone_box(&self) -> Box<dyn BusHook>;
+}
+
+impl<T: 'static + BusHook + Clone> BusHookClone for T {
+    fn clone_box(&self) -> Box<dyn BusHook> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn BusHook> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+#[derive(Clone)]
+struct Watch {
+    id: HookId,
+    kind: AccessKind,
+    addresses: RangeInclusive<u16>,
+    hook: Box<dyn BusHook>,
+}
+
+/// Hooks installed on the CPU bus. Reads made by OAM DMA are reported for the instruction that wrote $4014.
+#[derive(Clone, Default)]
+pub struct BusHooks {
+    watches: Vec<Watch>,
+    next_id: usize,
+    is_pause_requested: bool,
+}
+
+impl BusHooks {
+    pub fn add(&mut self, kind: AccessKind, addresses: RangeInclusive<u16>, hook: Box<dyn BusHook>) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.watches.push(Watch { id, kind, addresses, hook });
+        id
+    }
+
+    /// Removes a hook, returning whether it was installed.
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let count = self.watches.len();
+        self.watches.retain(|watch| watch.id != id);
+        self.watches.len() != count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// Returns whether a hook asked to pause since the last call.
+    pub fn take_pause_request(&mut self) -> bool {
+        std::mem::take(&mut self.is_pause_requested)
+    }
+
+    fn fire(&mut self, event: BusEvent) {
+        for watch in self.watches.iter_mut() {
+            if watch.kind == event.kind
+                && watch.addresses.contains(&event.address)
+                && watch.hook.on_access(&event) == HookAction::Pause
+            {
+                self.is_pause_requested = true;
+            }
+        }
+    }
+}
+
+/// Wraps a bus for one instruction or interrupt sequence and reports its accesses to the hooks.
+pub struct HookedBus<'a> {
+    bus: &'a mut dyn SystemBus,
+    hooks: &'a mut BusHooks,
+    pc: u16,
+    cpu_cycle: u64,
+    is_first_access: bool,
+}
+
+impl<'a> HookedBus<'a> {
+    pub fn new(bus: &'a mut dyn SystemBus, hooks: &'a mut BusHooks, pc: u16, cpu_cycle: u64) -> Self {
+        Self { bus, hooks, pc, cpu_cycle, is_first_access: true }
+    }
+
+    /// Wraps a bus for the page copy of OAM DMA, whose accesses are all reads.
+    pub fn for_oam_dma(bus: &'a mut dyn SystemBus, hooks: &'a mut BusHooks, pc: u16, cpu_cycle: u64) -> Self {
+        Self { bus, hooks, pc, cpu_cycle, is_first_access: false }
+    }
+
+    fn fire(&mut self, kind: AccessKind, address: u16, data: u8) {
+        self.hooks.fire(BusEvent { kind, address, data, cpu_cycle: self.cpu_cycle, pc: self.pc });
+    }
+}
+
+impl SystemBus for HookedBus<'_> {
+    fn read_u8(&mut self, address: u16) -> u8 {
+        let data = self.bus.read_u8(address);
+        // An instruction starts by fetching its opcode at PC, interrupts start by pushing to the stack.
+        let kind = if self.is_first_access && address == self.pc { AccessKind::Execute } else { AccessKind::Read };
+        self.is_first_access = false;
+        self.fire(kind, address, data);
+        data
+    }
+
+    fn write_u8(&mut self, address: u16, data: u8) {
+        self.bus.write_u8(address, data);
+        self.is_first_access = false;
+        self.fire(AccessKind::Write, address, data);
+    }
+
+    fn peek_u8(&self, address: u16) -> u8 {
+        self.bus.peek_u8(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::Memory;
+    use crate::system::SystemBus;
+    use crate::system_hook::{AccessKind, BusEvent, BusHooks, HookAction, HookedBus};
+
+    fn recorder(events: &Rc<RefCell<Vec<BusEvent>>>, action: HookAction) -> Box<dyn super::BusHook> {
+        let events = events.clone();
+        Box::new(move |event: &BusEvent| {
+            events.borrow_mut().push(*event);
+            action
+        })
+    }
+
+    # [test]
+    fn test_fire_hooks() {
+        let mut mem = Memory::default();
+        let mut hooks = BusHooks::default();
+        let events = Rc::new(RefCell::new(vec![]));
+        hooks.add(AccessKind::Execute, 0x0000..=0x07ff, recorder(&events, HookAction::Continue));
+        hooks.add(AccessKind::Read, 0x0010..=0x001f, recorder(&events, HookAction::Continue));
+        hooks.add(AccessKind::Write, 0x2000..=0x2007, recorder(&events, HookAction::Continue));
+        mem.write_u8(0x0010, 0x12);
+
+        let mut bus = HookedBus::new(&mut mem, &mut hooks, 0x0010, 100);
+        assert_eq!(bus.read_u8(0x0010), 0x12);
+        assert_eq!(bus.read_u8(0x0010), 0x12);
+        bus.read_u8(0x0020);
+        bus.write_u8(0x2006, 0x34);
+        bus.write_u8(0x0010, 0x56);
+        assert_eq!(bus.peek_u8(0x0010), 0x56);
+
+        let event = |kind, address, data| BusEvent { kind, address, data, cpu_cycle: 100, pc: 0x0010 };
+        assert_eq!(*events.borrow(), [
+            event(AccessKind::Execute, 0x0010, 0x12),
+            event(AccessKind::Read, 0x0010, 0x12),
+            event(AccessKind::Write, 0x2006, 0x34),
+        ]);
+        assert!(!hooks.take_pause_request());
+    }
+
+    # [test]
+    fn test_pause_and_remove() {
+        let mut mem = Memory::default();
+        let mut hooks = BusHooks::default();
+        let events = Rc::new(RefCell::new(vec![]));
+        assert!(hooks.is_empty());
+        let id = hooks.add(AccessKind::Write, 0x0000..=0x0000, recorder(&events, HookAction::Pause));
+        assert!(!hooks.is_empty());
+
+        HookedBus::new(&mut mem, &mut hooks, 0x8000, 0).write_u8(0x0001, 0x12);
+        assert!(!hooks.take_pause_request());
+        HookedBus::new(&mut mem, &mut hooks, 0x8000, 0).write_u8(0x0000, 0x12);
+        assert!(hooks.take_pause_request());
+        assert!(!hooks.take_pause_request());
+
+        // Cloned hooks keep what their closures captured.
+        let mut cloned = hooks.clone();
+        HookedBus::new(&mut mem, &mut cloned, 0x8000, 0).write_u8(0x0000, 0x34);
+        assert!(cloned.take_pause_request());
+        assert_eq!(events.borrow().len(), 2);
+
+        assert!(hooks.remove(id));
+        assert!(!hooks.remove(id));
+        assert!(hooks.is_empty());
+    }
+}
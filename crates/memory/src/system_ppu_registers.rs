@@ -1,15 +1,66 @@
-use crate::Memory;
+use crate::{Memory, OAM_DMA_SIZE, PALETTE_BASE_ADDRESS, PALETTE_SIZE, PPU_IO_LATCH_DECAY_CYCLES};
+use crate::system::SystemBus;
+use crate::system_video::VideoBus;
 
 const PPU_CTRL:   usize = 0x00;
 const PPU_MASK:   usize = 0x01;
 const PPU_STATUS: usize = 0x02;
 const OAM_ADDR:   usize = 0x03;
 const OAM_DATA:   usize = 0x04;
-const PPU_SCROLL: usize = 0x05;
-const PPU_ADDR:   usize = 0x06;
-const PPU_DATA:   usize = 0x07;
 
-pub trait PpuRegistersController {
+/// The internal scroll and address registers of the PPU, named as in loopy's "The skinny on NES scrolling".
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct LoopyRegisters {
+    /// Current VRAM address (15 bits), which $2007 accesses.
+    pub v: u16,
+    /// Temporary VRAM address (15 bits), the top left tile of the screen.
+    pub t: u16,
+    /// Fine X scroll (3 bits).
+    pub x: u8,
+    /// Write toggle shared by $2005 and $2006, cleared by reading $2002.
+    pub w: bool,
+}
+
+impl LoopyRegisters {
+    /// $2000 selects the nametable through bits 10-11 of t.
+    pub fn write_ctrl(&mut self, data: u8) {
+        self.t = (self.t & !0x0c00) | (u16::from(data & 0x03) << 10);
+    }
+
+    pub fn write_scroll(&mut self, data: u8) {
+        if self.w {
+            // Fine Y in bits 12-14, coarse Y in bits 5-9.
+            self.t = (self.t & !0x73e0) | (u16::from(data & 0x07) << 12) | (u16::from(data >> 3) << 5);
+        } else {
+            self.t = (self.t & !0x001f) | u16::from(data >> 3);
+            self.x = data & 0x07;
+        }
+        self.w = !self.w;
+    }
+
+    pub fn write_address(&mut self, data: u8) {
+        if self.w {
+            self.t = (self.t & 0xff00) | u16::from(data);
+            self.v = self.t;
+        } else {
+            // The high byte is 6 bits wide, so bit 14 is cleared.
+            self.t = (self.t & 0x00ff) | (u16::from(data & 0x3f) << 8);
+        }
+        self.w = !self.w;
+    }
+
+    /// Horizontal scroll in pixels within the nametable selected by t.
+    pub fn scroll_x(&self) -> u8 {
+        (((self.t & 0x001f) as u8) << 3) | self.x
+    }
+
+    /// Vertical scroll in pixels within the nametable selected by t.
+    pub fn scroll_y(&self) -> u8 {
+        ((((self.t >> 5) & 0x001f) as u8) << 3) | ((self.t >> 12) & 0x0007) as u8
+    }
+}
+
+pub trait PpuRegistersController: VideoBus {
     // 0x2000: PPU CTRL.
     fn name_table_base_address(&self) -> u16;
     fn address_increment(&self) -> u8;
@@ -37,22 +88,20 @@ pub trait PpuRegistersController {
 
     // 0x2003: OAM ADDR
     fn read_oam_address(&mut self) -> u8;
+    fn write_oam_address(&mut self, address: u8);
 
     // 0x2004: OAM DATA
     fn read_oam_data(&mut self) -> (u8, bool, bool);
     fn write_oam_data(&mut self, data: u8);
 
-    // 0x2005: PPU SCROLL
-    fn read_ppu_scroll(&mut self) -> (u8, u8, bool);
+    // 0x2005-0x2007: PPU SCROLL, PPU ADDR and PPU DATA
+    fn loopy(&self) -> LoopyRegisters;
 
-    // 0x2006: PPU ADDR.
-    fn read_ppu_address(&mut self) -> (u16, bool);
+    // 0x4014: OAM DMA
+    fn read_oam_dma(&mut self) -> Option<[u8; OAM_DMA_SIZE]>;
 
-    // $2007: PPU DATA
-    fn read_ppu_data(&mut self) -> (u8, bool, bool);
-    fn write_ppu_data(&mut self, data: u8);
-
-    fn increment_ppu_address(&mut self);
+    // PPU I/O latch
+    fn decay_io_latch(&mut self, cpu_cycles: usize);
 }
 
 impl PpuRegistersController for Memory {
@@ -160,6 +209,11 @@ impl PpuRegistersController for Memory {
         self.ppu_registers[OAM_ADDR]
     }
 
+    #[inline(always)]
+    fn write_oam_address(&mut self, address: u8) {
+        self.ppu_registers[OAM_ADDR] = address;
+    }
+
     fn read_oam_data(&mut self) -> (u8, bool, bool) {
         let r = self.request_to_read_oam_data;
         let w = self.request_to_write_oam_data;
@@ -183,58 +237,76 @@ impl PpuRegistersController for Memory {
         self.ppu_registers[OAM_DATA] = data;
     }
 
-    fn read_ppu_scroll(&mut self) -> (u8, u8, bool) {
-        let is_request = self.request_to_write_ppu_scroll;
-
-        self.request_to_write_ppu_scroll = false;
-        (self.ppu_registers[PPU_SCROLL], self.ppu_register_scroll_y, is_request)
+    #[inline(always)]
+    fn loopy(&self) -> LoopyRegisters {
+        self.loopy
     }
 
-    fn read_ppu_address(&mut self) -> (u16, bool) {
-        let hi = u16::from(self.ppu_registers[PPU_ADDR]) << 8;
-        let lo = u16::from(self.ppu_register_address_lower);
-        let is_request = self.request_to_write_ppu_address;
-
-        self.request_to_write_ppu_address = false;
-        (lo|hi, is_request)
+    fn read_oam_dma(&mut self) -> Option<[u8; OAM_DMA_SIZE]> {
+        if let Some(data) = self.oam_dma_data.take() {
+            return Some(data);
+        }
+        let base = u16::from(self.oam_dma_page.take()?) << 8;
+        let mut data = [0; OAM_DMA_SIZE];
+        for (offset, byte) in (0..).zip(data.iter_mut()) {
+            *byte = self.read_u8(base + offset);
+        }
+        Some(data)
     }
 
-    fn read_ppu_data(&mut self) -> (u8, bool, bool) {
-        let r = self.request_to_read_ppu_data;
-        let w = self.request_to_write_ppu_data;
-        let v = self.ppu_registers[PPU_DATA];
+    fn decay_io_latch(&mut self, cpu_cycles: usize) {
+        for (bit, age) in self.ppu_io_latch_ages.iter_mut().enumerate() {
+            *age = age.saturating_add(cpu_cycles);
+            if *age >= PPU_IO_LATCH_DECAY_CYCLES {
+                self.ppu_io_latch &= !(1 << bit);
+            }
+        }
+    }
+}
 
-        match (r,w) {
-            (_, true) => {
-                self.request_to_write_ppu_data = false;
-                (v, false, true)
-            },
-            (true, _) => {
-                self.request_to_read_ppu_data = false;
-                (v, true, false)
-            },
-            _ => (v, false, false)
+impl Memory {
+    /// Returns what a $2007 read returns: the read buffer, or palette RAM which is not buffered.
+    pub(crate) fn peek_ppu_data(&self) -> u8 {
+        let address = self.loopy.v & 0x3fff;
+        if address >= PALETTE_BASE_ADDRESS {
+            // Palette entries are 6 bits wide, the top 2 bits come from the I/O latch.
+            return (self.ppu_io_latch & 0xc0) | (self.palette[palette_index(address)] & 0x3f);
         }
+        self.ppu_data_buffer
     }
 
-    #[inline(always)]
-    fn write_ppu_data(&mut self, data: u8) {
-        self.ppu_registers[PPU_DATA] = data;
+    /// Refills the read buffer after a $2007 read, palette reads fill it from the nametable underneath.
+    pub(crate) fn fill_ppu_data_buffer(&mut self) {
+        let address = self.loopy.v & 0x3fff;
+        self.ppu_data_buffer = self.read_video_u8(if address >= PALETTE_BASE_ADDRESS { address - 0x1000 } else { address });
+        self.increment_ppu_address();
     }
 
-    fn increment_ppu_address(&mut self) {
-        let current = u16::from(self.ppu_registers[PPU_ADDR]) << 8;
-        let address = current.wrapping_add(u16::from(self.address_increment()));
+    pub(crate) fn write_ppu_data(&mut self, data: u8) {
+        let address = self.loopy.v & 0x3fff;
+        if address >= PALETTE_BASE_ADDRESS {
+            self.palette[palette_index(address)] = data & 0x3f;
+        } else {
+            self.write_video_u8(address, data);
+        }
+        self.increment_ppu_address();
+    }
 
-        self.ppu_register_address_lower = (address & 0xff) as u8;
-        self.ppu_registers[PPU_ADDR] = (address >> 8) as u8;
+    fn increment_ppu_address(&mut self) {
+        self.loopy.v = self.loopy.v.wrapping_add(u16::from(self.address_increment())) & 0x7fff;
     }
 }
 
+/// Returns the index in palette RAM of an address at $3F00-$3FFF.
+fn palette_index(address: u16) -> usize {
+    let index = usize::from(address) % PALETTE_SIZE;
+    // $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C.
+    if (index & 0x13) == 0x10 { index & 0x0f } else { index }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::Memory;
-    use crate::system::SystemBus;
+    use crate::{Memory, OAM_DMA_SIZE};
     use crate::system_ppu_registers::PpuRegistersController;
 
     # [test]
@@ -307,6 +379,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::field_reassign_with_default)]
     fn test_oam_data() {
         let mut mem = Memory::default();
         mem.request_to_read_oam_data  = true;
@@ -316,4 +389,27 @@ mod tests {
         assert_eq!(mem.read_oam_data(), (0xff, true, false));
         assert_eq!(mem.read_oam_data(), (0xff, false, false));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_oam_dma() {
+        let mut mem = Memory::default();
+        assert_eq!(mem.read_oam_dma(), None);
+
+        for (index, byte) in mem.ram[0x0200..0x0300].iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        mem.oam_dma_page = Some(0x02);
+        let data = mem.read_oam_dma().unwrap();
+        assert!(data.iter().enumerate().all(|(index, &byte)| byte == index as u8));
+        assert!(!mem.is_oam_dma_requested());
+
+        // A page read by the caller is copied as it was handed over.
+        mem.oam_dma_page = Some(0x02);
+        assert_eq!(mem.oam_dma_address(), Some(0x0200));
+        mem.set_oam_dma_data([0x55; OAM_DMA_SIZE]);
+        assert_eq!(mem.oam_dma_address(), None);
+        assert!(mem.is_oam_dma_requested());
+        assert_eq!(mem.read_oam_dma(), Some([0x55; OAM_DMA_SIZE]));
+        assert!(!mem.is_oam_dma_requested());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::Memory;
+use crate::mapper::{RenderingState, PATTERN_TABLE_END_ADDRESS};
+
+/// PPU address space at $0000-$3EFF (pattern tables and nametables).
+pub trait VideoBus {
+    fn read_video_u8(&mut self, address: u16) -> u8;
+    fn write_video_u8(&mut self, address: u16, data: u8);
+    fn set_rendering_state(&mut self, state: RenderingState);
+}
+
+impl VideoBus for Memory {
+    fn read_video_u8(&mut self, address: u16) -> u8 {
+        let address = address & 0x3fff;
+        if address <= PATTERN_TABLE_END_ADDRESS {
+            return self.mapper.ppu_read_u8(address);
+        }
+
+        self.mapper.read_name_table_u8(address, &self.vram)
+    }
+
+    fn write_video_u8(&mut self, address: u16, data: u8) {
+        let address = address & 0x3fff;
+        if address <= PATTERN_TABLE_END_ADDRESS {
+            self.mapper.ppu_write_u8(address, data);
+            return;
+        }
+
+        self.mapper.write_name_table_u8(address, data, &mut self.vram);
+    }
+
+    fn set_rendering_state(&mut self, state: RenderingState) {
+        self.mapper.set_rendering_state(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Memory;
+    use crate::mapper::{Mapper, Mirroring};
+    use crate::system_video::VideoBus;
+
+    #[derive(Clone)]
+    struct FixedMirroring(Mirroring);
+
+    impl Mapper for FixedMirroring {
+        fn cpu_peek_u8(&self, _address: u16) -> Option<u8> { None }
+        fn cpu_write_u8(&mut self, _address: u16, _data: u8) {}
+        fn ppu_read_u8(&mut self, address: u16) -> u8 { (address & 0xff) as u8 }
+        fn ppu_write_u8(&mut self, _address: u16, _data: u8) {}
+        fn mirroring(&self) -> Mirroring { self.0 }
+    }
+
+    # [test]
+    fn test_read_pattern_table_from_mapper() {
+        let mut mem = Memory::new(Box::new(FixedMirroring(Mirroring::Vertical)));
+        assert_eq!(mem.read_video_u8(0x0012), 0x12);
+        assert_eq!(mem.read_video_u8(0x1fff), 0xff);
+    }
+
+    # [test]
+    fn test_name_table_mirroring() {
+        let mut mem = Memory::new(Box::new(FixedMirroring(Mirroring::Vertical)));
+        mem.write_video_u8(0x2001, 0x12);
+        mem.write_video_u8(0x2402, 0x34);
+        assert_eq!(mem.read_video_u8(0x2801), 0x12);
+        assert_eq!(mem.read_video_u8(0x2c02), 0x34);
+        assert_eq!(mem.read_video_u8(0x3001), 0x12);
+
+        let mut mem = Memory::new(Box::new(FixedMirroring(Mirroring::Horizontal)));
+        mem.write_video_u8(0x2001, 0x12);
+        mem.write_video_u8(0x2802, 0x34);
+        assert_eq!(mem.read_video_u8(0x2401), 0x12);
+        assert_eq!(mem.read_video_u8(0x2c02), 0x34);
+    }
+}
@@ -1,6 +1,4 @@
-use crate::video::Video;
-
-mod video;
+use memory::mapper::{FetchPhase, RenderingState};
 
 /// CPU cycles per line.
 pub const CPU_CYCLES_PER_LINE: usize = 341 / 3;
@@ -10,11 +8,23 @@ pub const RENDER_SCREEN_AREA_WIDTH: usize = 256;
 /// Render screen area (height).
 pub const RENDER_SCREEN_AREA_HEIGHT: usize = 240;
 
-/// CPU cycles per draw frame.
-pub const CPU_CYCLES_PER_DRAW_FRAME: usize = CPU_CYCLES_PER_LINE * ((RENDER_SCREEN_AREA_HEIGHT + 1) as usize);
+/// CPU cycles per draw frame, including vertical blanking and the pre-render line.
+pub const CPU_CYCLES_PER_DRAW_FRAME: usize = CPU_CYCLES_PER_LINE * LINES_PER_FRAME as usize;
 
 pub const OAM_SIZE: usize = 0x0100;
 
+/// Scan lines per frame, including the pre-render line.
+pub const LINES_PER_FRAME: u16 = 262;
+
+/// Line at whose start the vblank flag is set.
+const VBLANK_LINE: u16 = 241;
+/// Line at whose start the status flags are cleared.
+const PRE_RENDER_LINE: u16 = 261;
+
+const BG_TILES_PER_LINE: usize = 32;
+const BG_PREFETCH_TILES: usize = 2;
+const SPRITES_PER_LINE: usize = 8;
+
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 enum ScanLineMode {
     Visible,
@@ -38,21 +48,24 @@ impl ScanLineMode {
 #[derive(Clone)]
 pub struct Ppu {
     oam: [u8; OAM_SIZE],
-    video: Video,
 
     fetch_scroll_x: u8,
     fetch_scroll_y: u8,
     cumulative_cpu_cycles: usize,
+    line: u16,
+    /// Whether vblank and NMI enable were both set at the last check, as NMI triggers on their rising edge.
+    is_nmi_asserted: bool,
 }
 
 impl Default for Ppu {
     fn default() -> Self {
         Self {
             oam: [0; OAM_SIZE],
-            video: Default::default(),
             fetch_scroll_x: 0,
             fetch_scroll_y: 0,
             cumulative_cpu_cycles: 0,
+            line: 0,
+            is_nmi_asserted: false,
         }
     }
 }
@@ -62,6 +75,9 @@ impl Ppu {
         self.oam = [0; OAM_SIZE];
         self.fetch_scroll_x = 0;
         self.fetch_scroll_y = 0;
+        self.cumulative_cpu_cycles = 0;
+        self.line = 0;
+        self.is_nmi_asserted = false;
     }
 
     pub fn step(
@@ -69,44 +85,134 @@ impl Ppu {
         cpu_cycles: usize,
         registers: &mut dyn memory::system_ppu_registers::PpuRegistersController
     ) -> Option<cpu::Interrupt> {
-        let (scroll_x, scroll_y, _) = registers.read_ppu_scroll();
-        self.fetch_scroll_x = scroll_x;
-        self.fetch_scroll_y = scroll_y;
+        let loopy = registers.loopy();
+        self.fetch_scroll_x = loopy.scroll_x();
+        self.fetch_scroll_y = loopy.scroll_y();
 
-        let (ppu_address, _) = registers.read_ppu_address();
-        let (ppu_data, is_read_ppu_data, is_write_ppu_data) = registers.read_ppu_data();
-        if is_write_ppu_data {
-            self.video.write(ppu_address, ppu_data);
-            registers.increment_ppu_address();
+        let address = registers.read_oam_address();
+        let (data, _, writing_requested) = registers.read_oam_data();
+        if writing_requested {
+            self.write_oam(address, data);
+            registers.write_oam_address(address.wrapping_add(1));
         }
-        if is_read_ppu_data {
-            registers.write_ppu_data(self.video.read(ppu_address));
-            registers.increment_ppu_address();
+        if let Some(data) = registers.read_oam_dma() {
+            // The DMA writes through $2004, so the copy starts at OAMADDR and wraps around.
+            for (offset, byte) in data.iter().enumerate() {
+                self.write_oam(address.wrapping_add(offset as u8), *byte);
+            }
         }
-
+        // $2004 reads return the byte at OAMADDR without incrementing it.
         let address = registers.read_oam_address();
-        let (data, reading_requested, writing_requested) = registers.read_oam_data();
-        if writing_requested {
-            self.oam[usize::from(address)] = data;
+        registers.write_oam_data(self.oam[usize::from(address)]);
+
+        // NMI enabled during vblank triggers immediately.
+        let mut interrupt = self.check_nmi(registers);
+
+        registers.decay_io_latch(cpu_cycles);
+        self.cumulative_cpu_cycles += cpu_cycles;
+        while self.cumulative_cpu_cycles >= CPU_CYCLES_PER_LINE {
+            self.cumulative_cpu_cycles -= CPU_CYCLES_PER_LINE;
+            self.render_line(registers);
+            self.line = (self.line + 1) % LINES_PER_FRAME;
+            match self.line {
+                VBLANK_LINE => registers.on_vblank(true),
+                PRE_RENDER_LINE => registers.clear_ppu_status(),
+                _ => {}
+            }
+            interrupt = interrupt.or(self.check_nmi(registers));
         }
-        if reading_requested {
-            let data = self.oam[usize::from(address)];
-            registers.write_oam_data(data);
+
+        interrupt
+    }
+
+    fn write_oam(&mut self, address: u8, data: u8) {
+        // Bits 2-4 of the sprite attributes do not exist.
+        self.oam[usize::from(address)] = if (address & 0x03) == 0x02 { data & 0xe3 } else { data };
+    }
+
+    fn check_nmi(&mut self, registers: &dyn memory::system_ppu_registers::PpuRegistersController) -> Option<cpu::Interrupt> {
+        let is_nmi_asserted = registers.is_vblank() && registers.is_nmi_enable();
+        let is_rising_edge = is_nmi_asserted && !self.is_nmi_asserted;
+        self.is_nmi_asserted = is_nmi_asserted;
+        if is_rising_edge { Some(cpu::Interrupt::NMI) } else { None }
+    }
+
+    fn render_line(&mut self, registers: &mut dyn memory::system_ppu_registers::PpuRegistersController) {
+        let is_rendering = registers.is_write_bg() || registers.is_write_sprite();
+        match ScanLineMode::from(self.line) {
+            ScanLineMode::Visible | ScanLineMode::PreRender if is_rendering => self.fetch_line(registers),
+            _ => self.set_fetch_phase(registers, FetchPhase::Idle),
         }
+    }
 
-        let current_cup_cycles = self.cumulative_cpu_cycles + cpu_cycles;
-        if current_cup_cycles >= CPU_CYCLES_PER_LINE {
-            self.cumulative_cpu_cycles = current_cup_cycles - CPU_CYCLES_PER_LINE;
+    fn set_fetch_phase(&self, registers: &mut dyn memory::system_ppu_registers::PpuRegistersController, phase: FetchPhase) {
+        registers.set_rendering_state(RenderingState {
+            line: self.line,
+            phase,
+            is_8x16_sprites: registers.sprite_height() == 16,
+        });
+    }
+
+    /// Performs the memory fetches of a rendered line in hardware order, so that the cartridge
+    /// observes the same address sequence (PPU A12 edges, repeated nametable reads) as on hardware.
+    fn fetch_line(&mut self, registers: &mut dyn memory::system_ppu_registers::PpuRegistersController) {
+        let bg = registers.bg_pattern_table_address();
+        // Unused sprite slots fetch tile $FF, which is in the $1000 table for 8x16 sprites.
+        let sprite = if registers.sprite_height() == 16 {
+            0x1000u16
         } else {
-            self.cumulative_cpu_cycles = current_cup_cycles;
+            registers.sprite_pattern_table_address()
+        };
+        let y = if self.line < RENDER_SCREEN_AREA_HEIGHT as u16 { self.line } else { 0 };
+        let next_y = if self.line + 1 < RENDER_SCREEN_AREA_HEIGHT as u16 { self.line + 1 } else { 0 };
+
+        self.set_fetch_phase(registers, FetchPhase::Background);
+        for x in BG_PREFETCH_TILES..(BG_TILES_PER_LINE + BG_PREFETCH_TILES) {
+            Ppu::fetch_bg_tile(registers, bg, x as u16, y);
         }
 
-        None
+        self.set_fetch_phase(registers, FetchPhase::Sprite);
+        let garbage = Ppu::name_table_address(registers, 0, y);
+        for _ in 0..SPRITES_PER_LINE {
+            registers.read_video_u8(garbage);
+            registers.read_video_u8(garbage);
+            registers.read_video_u8(sprite + 0xff * 16);
+            registers.read_video_u8(sprite + 0xff * 16 + 8);
+        }
+
+        self.set_fetch_phase(registers, FetchPhase::Prefetch);
+        for x in 0..BG_PREFETCH_TILES {
+            Ppu::fetch_bg_tile(registers, bg, x as u16, next_y);
+        }
+        // Two unused nametable fetches of the next tile end the line.
+        let next = Ppu::name_table_address(registers, BG_PREFETCH_TILES as u16, next_y);
+        registers.read_video_u8(next);
+        registers.read_video_u8(next);
+    }
+
+    fn name_table_address(registers: &mut dyn memory::system_ppu_registers::PpuRegistersController, x: u16, y: u16) -> u16 {
+        // Tiles past the right edge come from the horizontally adjacent nametable.
+        let base = registers.name_table_base_address() ^ if x >= 32 { 0x0400 } else { 0x0000 };
+        base | ((y / 8) << 5) | (x % 32)
+    }
+
+    fn fetch_bg_tile(registers: &mut dyn memory::system_ppu_registers::PpuRegistersController, bg: u16, x: u16, y: u16) {
+        let name_table = Ppu::name_table_address(registers, x, y);
+        let tile = u16::from(registers.read_video_u8(name_table));
+        registers.read_video_u8((name_table & 0x2c00) | 0x03c0 | ((y / 32) << 3) | ((x % 32) / 4));
+
+        let pattern = bg + tile * 16 + (y % 8);
+        registers.read_video_u8(pattern);
+        registers.read_video_u8(pattern + 8);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use memory::mapper::{Mapper, Mirroring};
+    use cpu::Interrupt;
     use memory::system::SystemBus;
     use memory::system_ppu_registers::PpuRegistersController;
     use crate::{OAM_SIZE, ScanLineMode};
@@ -121,6 +227,7 @@ mod tests {
         assert_eq!(ppu.fetch_scroll_x, 0);
         assert_eq!(ppu.fetch_scroll_y, 0);
         assert_eq!(ppu.cumulative_cpu_cycles, 0);
+        assert_eq!(ppu.line, 0);
     }
 
     # [test]
@@ -175,4 +282,170 @@ mod tests {
             assert_eq!(mem.ppu_registers[0x04], 0x0f);
         }
     }
+
+    // Ported from blargg's ppu_tests/sprite_ram.
+    # [test]
+    fn execute_step_to_access_sprite_ram()
+    {
+        let mut ppu = super::Ppu::default();
+        let mut mem = memory::Memory::default();
+
+        // Writes increment OAMADDR, reads do not.
+        mem.write_u8(0x2003, 0x00);
+        for data in [0x12, 0x34, 0xff, 0x56] {
+            mem.write_u8(0x2004, data);
+            ppu.step(0, &mut mem);
+        }
+        assert_eq!(mem.ppu_registers[0x03], 0x04);
+
+        mem.write_u8(0x2003, 0x01);
+        ppu.step(0, &mut mem);
+        for _ in 0..2 {
+            assert_eq!(mem.read_u8(0x2004), 0x34);
+            ppu.step(0, &mut mem);
+        }
+
+        // Bits 2-4 of the sprite attributes read back as 0.
+        mem.write_u8(0x2003, 0x02);
+        ppu.step(0, &mut mem);
+        assert_eq!(mem.read_u8(0x2004), 0xe3);
+        assert_eq!(ppu.oam[0x03], 0x56);
+    }
+
+    # [test]
+    fn execute_step_to_copy_oam_dma()
+    {
+        let mut ppu = super::Ppu::default();
+        let mut mem = memory::Memory::default();
+
+        for (index, byte) in mem.ram[0x0300..0x0400].iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        mem.ppu_registers[0x03] = 0x10;
+        mem.write_u8(0x4014, 0x03);
+        ppu.step(0, &mut mem);
+
+        assert_eq!(ppu.oam[0x10], 0x00);
+        assert_eq!(ppu.oam[0xff], 0xef);
+        assert_eq!(ppu.oam[0x0f], 0xff);
+        assert!(!mem.is_oam_dma_requested());
+    }
+
+    # [test]
+    fn execute_step_to_advance_lines()
+    {
+        let mut ppu = super::Ppu::default();
+        let mut mem = memory::Memory::default();
+
+        ppu.step(super::CPU_CYCLES_PER_LINE - 1, &mut mem);
+        assert_eq!(ppu.line, 0);
+        ppu.step(super::CPU_CYCLES_PER_LINE * 2, &mut mem);
+        assert_eq!(ppu.line, 2);
+        assert_eq!(ppu.cumulative_cpu_cycles, super::CPU_CYCLES_PER_LINE - 1);
+
+        ppu.step(super::CPU_CYCLES_PER_LINE * 260, &mut mem);
+        assert_eq!(ppu.line, 0);
+    }
+
+    // Ported from blargg's vbl_nmi_timing/1.frame_basics and ppu_tests/vbl_clear_time.
+    # [test]
+    fn execute_step_to_set_and_clear_vblank()
+    {
+        let mut ppu = super::Ppu::default();
+        let mut mem = memory::Memory::default();
+
+        ppu.step(super::CPU_CYCLES_PER_LINE * 240, &mut mem);
+        assert_eq!(mem.read_u8(0x2002) & 0x80, 0x00);
+        ppu.step(super::CPU_CYCLES_PER_LINE, &mut mem);
+        assert_eq!(ppu.line, super::VBLANK_LINE);
+
+        // Reading $2002 returns the flag and clears it.
+        assert_eq!(mem.read_u8(0x2002) & 0x80, 0x80);
+        assert_eq!(mem.read_u8(0x2002) & 0x80, 0x00);
+
+        // The flag is set again once per frame.
+        ppu.step(super::CPU_CYCLES_PER_LINE * (super::LINES_PER_FRAME as usize - 1), &mut mem);
+        assert_eq!(mem.read_u8(0x2002) & 0x80, 0x00);
+        ppu.step(super::CPU_CYCLES_PER_LINE, &mut mem);
+        assert_eq!(mem.peek_u8(0x2002) & 0x80, 0x80);
+
+        // The pre-render line clears every status flag.
+        ppu.step(super::CPU_CYCLES_PER_LINE, &mut mem);
+        mem.on_hit_sprite0(true);
+        mem.on_sprite_overflow(true);
+        ppu.step(super::CPU_CYCLES_PER_LINE * 18, &mut mem);
+        assert_eq!(mem.peek_u8(0x2002) & 0xe0, 0xe0);
+        ppu.step(super::CPU_CYCLES_PER_LINE, &mut mem);
+        assert_eq!(ppu.line, super::PRE_RENDER_LINE);
+        assert_eq!(mem.read_u8(0x2002) & 0xe0, 0x00);
+    }
+
+    // Ported from blargg's vbl_nmi_timing/6.nmi_disable and the NMI control tests.
+    # [test]
+    fn execute_step_to_trigger_nmi()
+    {
+        let mut ppu = super::Ppu::default();
+        let mut mem = memory::Memory::default();
+
+        // No NMI while it is disabled.
+        assert_eq!(ppu.step(super::CPU_CYCLES_PER_LINE * 241, &mut mem), None);
+
+        // Enabling NMI during vblank triggers it immediately, but only once.
+        mem.write_u8(0x2000, 0x80);
+        assert_eq!(ppu.step(0, &mut mem), Some(Interrupt::NMI));
+        mem.write_u8(0x2000, 0x80);
+        assert_eq!(ppu.step(0, &mut mem), None);
+
+        // Disabling and enabling it again triggers another one.
+        mem.write_u8(0x2000, 0x00);
+        assert_eq!(ppu.step(0, &mut mem), None);
+        mem.write_u8(0x2000, 0x80);
+        assert_eq!(ppu.step(0, &mut mem), Some(Interrupt::NMI));
+
+        // Not after the vblank flag was cleared by reading $2002.
+        mem.write_u8(0x2000, 0x00);
+        mem.read_u8(0x2002);
+        mem.write_u8(0x2000, 0x80);
+        assert_eq!(ppu.step(0, &mut mem), None);
+
+        // NMI at the start of the next vblank.
+        assert_eq!(ppu.step(super::CPU_CYCLES_PER_LINE * 261, &mut mem), None);
+        assert_eq!(ppu.step(super::CPU_CYCLES_PER_LINE, &mut mem), Some(Interrupt::NMI));
+    }
+
+    # [test]
+    fn execute_step_to_fetch_patterns()
+    {
+        #[derive(Clone, Default)]
+        struct FetchCounter {
+            low: Rc<Cell<usize>>,
+            high: Rc<Cell<usize>>,
+        }
+
+        impl Mapper for FetchCounter {
+            fn cpu_peek_u8(&self, _address: u16) -> Option<u8> { None }
+            fn cpu_write_u8(&mut self, _address: u16, _data: u8) {}
+            fn ppu_read_u8(&mut self, address: u16) -> u8 {
+                let counter = if (address & 0x1000) == 0x1000 { &self.high } else { &self.low };
+                counter.set(counter.get() + 1);
+                0
+            }
+            fn ppu_write_u8(&mut self, _address: u16, _data: u8) {}
+            fn mirroring(&self) -> Mirroring { Mirroring::Horizontal }
+        }
+
+        let counter = FetchCounter::default();
+        let mut ppu = super::Ppu::default();
+        let mut mem = memory::Memory::new(Box::new(counter.clone()));
+
+        // Nothing is fetched while rendering is disabled.
+        ppu.step(super::CPU_CYCLES_PER_LINE, &mut mem);
+        assert_eq!((counter.low.get(), counter.high.get()), (0, 0));
+
+        // Background at $0000 and 8x8 sprites at $1000.
+        mem.write_u8(0x2000, 0x08);
+        mem.write_u8(0x2001, 0x18);
+        ppu.step(super::CPU_CYCLES_PER_LINE, &mut mem);
+        assert_eq!((counter.low.get(), counter.high.get()), (68, 16));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,296 @@
+use std::sync::OnceLock;
+
+use crate::{Mirroring, Rom, TvSystem};
+
+/// The bundled table of known-good headers.
+const GAMES: &str = include_str!("../data/games.txt");
+
+/// Checksums of the PRG-ROM followed by the CHR-ROM.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Checksums {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+}
+
+impl Checksums {
+    pub fn of(rom: &Rom) -> Self {
+        let mut crc32 = crc32fast::Hasher::new();
+        let mut sha1 = sha1_smol::Sha1::new();
+        for data in [&rom.prg_rom, &rom.chr_rom] {
+            crc32.update(data);
+            sha1.update(data);
+        }
+        Self {
+            crc32: crc32.finalize(),
+            sha1: sha1.digest().bytes(),
+        }
+    }
+}
+
+/// Known-good header values of one game.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GameEntry {
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+    pub mapper: u16,
+    pub submapper: u8,
+    /// `None` when the mapper controls mirroring.
+    pub mirroring: Option<Mirroring>,
+    pub has_battery: bool,
+    pub prg_ram_bytes: usize,
+    pub prg_nvram_bytes: usize,
+    pub chr_ram_bytes: usize,
+    pub chr_nvram_bytes: usize,
+    pub tv_system: TvSystem,
+}
+
+/// A header value replaced by the database, with the value from the file and the corrected one.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum HeaderFixup {
+    Mapper { from: u16, to: u16 },
+    Submapper { from: u8, to: u8 },
+    Mirroring { from: Mirroring, to: Mirroring },
+    Battery { from: bool, to: bool },
+    PrgRamBytes { from: usize, to: usize },
+    PrgNvramBytes { from: usize, to: usize },
+    ChrRamBytes { from: usize, to: usize },
+    ChrNvramBytes { from: usize, to: usize },
+    TvSystem { from: TvSystem, to: TvSystem },
+}
+
+/// A database line that could not be parsed. Lines are counted from 1.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct DatabaseError {
+    pub line: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct GameDatabase {
+    entries: Vec<GameEntry>,
+}
+
+impl GameDatabase {
+    /// Returns the database bundled with the crate, parsed on first use.
+    pub fn bundled() -> &'static Self {
+        static BUNDLED: OnceLock<GameDatabase> = OnceLock::new();
+        // The bundled table is checked by the tests, so it always parses.
+        BUNDLED.get_or_init(|| Self::parse(GAMES).unwrap())
+    }
+
+    /// Parses a table in the format described at the top of `data/games.txt`.
+    pub fn parse(text: &str) -> Result<Self, DatabaseError> {
+        let mut entries = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let entry = GameDatabase::parse_entry(line).ok_or(DatabaseError { line: index + 1 })?;
+            entries.push(entry);
+        }
+        Ok(Self { entries })
+    }
+
+    fn parse_entry(line: &str) -> Option<GameEntry> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 10 {
+            return None;
+        }
+        let sha1 = match fields[1] {
+            "-" => None,
+            hex => Some(GameDatabase::parse_sha1(hex)?),
+        };
+        let (mapper, submapper) = fields[2].split_once('.').unwrap_or((fields[2], "0"));
+        Some(GameEntry {
+            crc32: u32::from_str_radix(fields[0], 16).ok()?,
+            sha1,
+            mapper: mapper.parse().ok()?,
+            submapper: submapper.parse().ok()?,
+            mirroring: match fields[3] {
+                "H" => Some(Mirroring::Horizontal),
+                "V" => Some(Mirroring::Vertical),
+                "4" => Some(Mirroring::FourScreen),
+                "-" => None,
+                _ => return None,
+            },
+            has_battery: match fields[4] {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            },
+            prg_ram_bytes: fields[5].parse().ok()?,
+            prg_nvram_bytes: fields[6].parse().ok()?,
+            chr_ram_bytes: fields[7].parse().ok()?,
+            chr_nvram_bytes: fields[8].parse().ok()?,
+            tv_system: match fields[9] {
+                "N" => TvSystem::Ntsc,
+                "P" => TvSystem::Pal,
+                "M" => TvSystem::MultipleRegion,
+                "D" => TvSystem::Dendy,
+                _ => return None,
+            },
+        })
+    }
+
+    fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+        if hex.len() != 40 || !hex.is_ascii() {
+            return None;
+        }
+        let mut sha1 = [0u8; 20];
+        for (i, byte) in sha1.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(sha1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Looks a game up by SHA-1 when the entry has one, by CRC32 otherwise.
+    pub fn find(&self, checksums: &Checksums) -> Option<&GameEntry> {
+        self.entries.iter().find(|entry| match entry.sha1 {
+            Some(sha1) => sha1 == checksums.sha1,
+            None => entry.crc32 == checksums.crc32,
+        })
+    }
+}
+
+impl Rom {
+    /// Overrides the header with the database entry for this PRG/CHR data and returns what changed.
+    pub fn apply_database(&mut self, database: &GameDatabase) -> Vec<HeaderFixup> {
+        let entry = match database.find(&Checksums::of(self)) {
+            Some(entry) => entry.clone(),
+            None => return vec![],
+        };
+
+        let mut fixups = vec![];
+        if self.mapper != entry.mapper {
+            fixups.push(HeaderFixup::Mapper { from: self.mapper, to: entry.mapper });
+            self.mapper = entry.mapper;
+        }
+        if self.submapper != entry.submapper {
+            fixups.push(HeaderFixup::Submapper { from: self.submapper, to: entry.submapper });
+            self.submapper = entry.submapper;
+        }
+        if let Some(mirroring) = entry.mirroring.filter(|&mirroring| mirroring != self.mirroring) {
+            fixups.push(HeaderFixup::Mirroring { from: self.mirroring, to: mirroring });
+            self.mirroring = mirroring;
+        }
+        if self.has_battery != entry.has_battery {
+            fixups.push(HeaderFixup::Battery { from: self.has_battery, to: entry.has_battery });
+            self.has_battery = entry.has_battery;
+        }
+        if self.prg_ram_bytes != entry.prg_ram_bytes {
+            fixups.push(HeaderFixup::PrgRamBytes { from: self.prg_ram_bytes, to: entry.prg_ram_bytes });
+            self.prg_ram_bytes = entry.prg_ram_bytes;
+        }
+        if self.prg_nvram_bytes != entry.prg_nvram_bytes {
+            fixups.push(HeaderFixup::PrgNvramBytes { from: self.prg_nvram_bytes, to: entry.prg_nvram_bytes });
+            self.prg_nvram_bytes = entry.prg_nvram_bytes;
+        }
+        if self.chr_ram_bytes != entry.chr_ram_bytes {
+            fixups.push(HeaderFixup::ChrRamBytes { from: self.chr_ram_bytes, to: entry.chr_ram_bytes });
+            self.chr_ram_bytes = entry.chr_ram_bytes;
+        }
+        if self.chr_nvram_bytes != entry.chr_nvram_bytes {
+            fixups.push(HeaderFixup::ChrNvramBytes { from: self.chr_nvram_bytes, to: entry.chr_nvram_bytes });
+            self.chr_nvram_bytes = entry.chr_nvram_bytes;
+        }
+        if self.tv_system != entry.tv_system {
+            fixups.push(HeaderFixup::TvSystem { from: self.tv_system, to: entry.tv_system });
+            self.tv_system = entry.tv_system;
+        }
+        fixups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Mirroring, Rom, TvSystem};
+    use crate::database::{Checksums, DatabaseError, GameDatabase, HeaderFixup, GAMES};
+
+    // 16KB of PRG-ROM filled with $EA and 8KB of CHR-ROM filled with $00.
+    fn make_rom(flags6: u8) -> Rom {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01, flags6, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(vec![0xeau8; 0x4000]);
+        data.extend(vec![0x00u8; 0x2000]);
+        Rom::new(&data).unwrap()
+    }
+
+    # [test]
+    fn test_bundled_database_parses() {
+        assert!(GameDatabase::parse(GAMES).is_ok());
+        assert!(!GameDatabase::bundled().is_empty());
+    }
+
+    # [test]
+    fn test_checksums() {
+        let rom = make_rom(0x00);
+        let checksums = Checksums::of(&rom);
+        let mut data = vec![0xeau8; 0x4000];
+        data.extend(vec![0x00u8; 0x2000]);
+        assert_eq!(checksums.crc32, crc32fast::hash(&data));
+        assert_eq!(checksums.sha1, sha1_smol::Sha1::from(&data).digest().bytes());
+    }
+
+    # [test]
+    fn test_parse_errors() {
+        let cases = [
+            ("0badf00d - 0 H 0 8192 0 0 0", 1),
+            ("\n# comment\n0badf00d - 0 X 0 8192 0 0 0 N", 3),
+            ("0badf00d 1234 0 H 0 8192 0 0 0 N", 1),
+            ("zzzzzzzz - 0 H 0 8192 0 0 0 N", 1),
+        ];
+        for (text, line) in cases {
+            assert_eq!(GameDatabase::parse(text).err(), Some(DatabaseError { line }));
+        }
+    }
+
+    # [test]
+    fn test_apply_database() {
+        let mut rom = make_rom(0x00);
+        let checksums = Checksums::of(&rom);
+        let text = format!(
+            "# Test cartridge\n{:08x} - 1.0 V 1 0 8192 0 0 P # Test\n",
+            checksums.crc32,
+        );
+        let database = GameDatabase::parse(&text).unwrap();
+        assert_eq!(database.len(), 1);
+
+        let fixups = rom.apply_database(&database);
+        assert_eq!(fixups, vec![
+            HeaderFixup::Mapper { from: 0, to: 1 },
+            HeaderFixup::Mirroring { from: Mirroring::Horizontal, to: Mirroring::Vertical },
+            HeaderFixup::Battery { from: false, to: true },
+            HeaderFixup::PrgRamBytes { from: 0x2000, to: 0 },
+            HeaderFixup::PrgNvramBytes { from: 0, to: 0x2000 },
+            HeaderFixup::TvSystem { from: TvSystem::Ntsc, to: TvSystem::Pal },
+        ]);
+        assert_eq!(rom.mapper, 1);
+        assert!(rom.has_battery);
+
+        // A correct header needs no fix-ups.
+        assert!(rom.apply_database(&database).is_empty());
+    }
+
+    # [test]
+    fn test_sha1_takes_precedence() {
+        let mut rom = make_rom(0x00);
+        let checksums = Checksums::of(&rom);
+        let wrong_sha1 = "00".repeat(20);
+        let text = format!("{:08x} {} 4 - 0 8192 0 0 0 N\n", checksums.crc32, wrong_sha1);
+        let database = GameDatabase::parse(&text).unwrap();
+        assert!(rom.apply_database(&database).is_empty());
+
+        let sha1: String = checksums.sha1.iter().map(|v| format!("{:02x}", v)).collect();
+        let text = format!("00000000 {} 4 - 0 8192 0 0 0 N\n", sha1);
+        let database = GameDatabase::parse(&text).unwrap();
+        assert_eq!(rom.apply_database(&database), vec![HeaderFixup::Mapper { from: 0, to: 4 }]);
+        assert_eq!(rom.mirroring, Mirroring::Horizontal);
+    }
+}
@@ -0,0 +1,56 @@
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum RomError {
+    /// The file is shorter than its header, 16 bytes for iNES and 32 for UNIF. Carries the file size.
+    TruncatedHeader(usize),
+    /// The file starts with neither "NES\x1A" nor "UNIF", or a disk image with neither "FDS\x1A" nor a disk info block.
+    /// Carries the first four bytes.
+    BadMagic([u8; 4]),
+    /// The trainer flag is set but fewer than 512 bytes follow the header. Carries the bytes left.
+    TruncatedTrainer(usize),
+    /// The header declares no PRG-ROM.
+    EmptyPrgRom,
+    /// The header declares more PRG-ROM than the file holds.
+    PrgRomOutOfBounds { expected: usize, available: usize },
+    /// The header declares more CHR-ROM than the file holds.
+    ChrRomOutOfBounds { expected: usize, available: usize },
+    /// A NES 2.0 header byte holds a value that cannot be decoded.
+    CorruptNes20Field { offset: usize, value: u8 },
+    /// A UNIF chunk at `offset` declares more bytes than the file holds.
+    TruncatedUnifChunk { offset: usize, length: usize, available: usize },
+    /// The UNIF image has no MAPR chunk naming its board.
+    MissingUnifBoard,
+    /// The UNIF board name does not map onto a known mapper.
+    UnsupportedUnifBoard(String),
+    /// The Famicom Disk System BIOS is not 8KB. Carries its size.
+    BadBiosSize(usize),
+    /// The disk data after the optional header is not a whole number of 65500-byte sides. Carries its size.
+    BadDiskSize(usize),
+    /// The given disk side does not start with a disk info block.
+    BadDiskSide(usize),
+    /// The NSF declares no tracks.
+    NoNsfTracks,
+    /// The NSF data loads below $8000, which only the FDS expansion allows.
+    BadNsfLoadAddress(u16),
+    /// An NSFe chunk at `offset` declares more bytes than the file holds.
+    TruncatedNsfeChunk { offset: usize, length: usize, available: usize },
+    /// The NSFe file has no chunk with the given id.
+    MissingNsfeChunk([u8; 4]),
+    /// The NSFe file has a required chunk this player does not know.
+    UnsupportedNsfeChunk([u8; 4]),
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum PatchError {
+    /// The patch starts with none of the IPS, UPS or BPS magic numbers.
+    UnknownFormat,
+    /// The patch ends in the middle of the record at the given offset.
+    Truncated(usize),
+    /// The number at the given patch offset does not fit in `usize`.
+    Corrupt(usize),
+    /// The patch reads or writes past the end of the source or target at the given offset.
+    OutOfBounds(usize),
+    SourceSizeMismatch { expected: usize, actual: usize },
+    SourceChecksumMismatch { expected: u32, actual: u32 },
+    TargetChecksumMismatch { expected: u32, actual: u32 },
+    PatchChecksumMismatch { expected: u32, actual: u32 },
+}
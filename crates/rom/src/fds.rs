@@ -0,0 +1,126 @@
+use crate::{ConsoleType, HeaderFormat, Mirroring, Rom, RomError, TvSystem};
+
+/// Magic number of the optional fwNES header.
+pub const FDS_MAGIC: [u8; 4] = *b"FDS\x1a";
+pub const FDS_HEADER_SIZE: usize = 0x0010;
+pub const DISK_SIDE_SIZE: usize = 65500;
+pub const BIOS_SIZE: usize = 0x2000;
+/// The iNES mapper number reserved for the Famicom Disk System.
+pub const FDS_MAPPER: u16 = 20;
+
+/// Every side starts with a disk info block: the block code 1 followed by "*NINTENDO-HVC*".
+const DISK_INFO_BLOCK: &[u8] = b"\x01*NINTENDO-HVC*";
+const PRG_RAM_SIZE: usize = 0x8000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+/// Returns whether the data looks like a .fds image, with or without the fwNES header.
+pub fn is_disk_image(data: &[u8]) -> bool {
+    data.starts_with(&FDS_MAGIC) || data.starts_with(DISK_INFO_BLOCK)
+}
+
+/// Returns the length of the fwNES header at the start of a .fds image, 0 when there is none.
+pub fn header_size(image: &[u8]) -> usize {
+    if image.starts_with(&FDS_MAGIC) { FDS_HEADER_SIZE } else { 0 }
+}
+
+impl Rom {
+    /// Parses a .fds image, with or without the fwNES header, and the 8KB BIOS mapped at $E000.
+    /// The RAM adapter gives the disk 32KB of PRG-RAM at $6000 and 8KB of CHR-RAM.
+    pub fn from_disk(image: &[u8], bios: &[u8]) -> Result<Self, RomError> {
+        if bios.len() != BIOS_SIZE {
+            return Err(RomError::BadBiosSize(bios.len()));
+        }
+        if !is_disk_image(image) {
+            return match image.get(..4) {
+                Some(magic) => Err(RomError::BadMagic([magic[0], magic[1], magic[2], magic[3]])),
+                None => Err(RomError::TruncatedHeader(image.len())),
+            };
+        }
+
+        // The side count in the fwNES header is unreliable, so it is taken from the image size.
+        let data = image.get(header_size(image)..).unwrap_or(&[]);
+        if data.is_empty() || data.len() % DISK_SIDE_SIZE != 0 {
+            return Err(RomError::BadDiskSize(data.len()));
+        }
+        let disk_sides: Vec<Vec<u8>> = data.chunks(DISK_SIDE_SIZE).map(|side| side.to_vec()).collect();
+        if let Some(side) = disk_sides.iter().position(|side| !side.starts_with(DISK_INFO_BLOCK)) {
+            return Err(RomError::BadDiskSide(side));
+        }
+
+        Ok(Rom {
+            format: HeaderFormat::Fds,
+            prg_rom_bytes: BIOS_SIZE,
+            chr_rom_bytes: 0,
+            prg_ram_bytes: PRG_RAM_SIZE,
+            prg_nvram_bytes: 0,
+            chr_ram_bytes: CHR_RAM_SIZE,
+            chr_nvram_bytes: 0,
+            mapper: FDS_MAPPER,
+            submapper: 0,
+            // The RAM adapter controls mirroring through $4025.
+            mirroring: Mirroring::Vertical,
+            has_battery: false,
+            has_trainer: false,
+            tv_system: TvSystem::Ntsc,
+            console_type: ConsoleType::Nes,
+            expansion_device: 0,
+            name: None,
+            trainer: vec![],
+            prg_rom: bios.to_vec(),
+            chr_rom: vec![],
+            disk_sides,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HeaderFormat, Rom, RomError};
+    use crate::fds::{BIOS_SIZE, DISK_SIDE_SIZE, FDS_MAPPER};
+
+    fn make_side(fill: u8) -> Vec<u8> {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(DISK_SIDE_SIZE, fill);
+        side
+    }
+
+    # [test]
+    fn test_from_disk() {
+        let bios = vec![0xeau8; BIOS_SIZE];
+        let mut headerless = make_side(0x11);
+        headerless.extend(make_side(0x22));
+        let mut image = b"FDS\x1a\x02".to_vec();
+        image.resize(0x10, 0);
+        image.extend(&headerless);
+
+        for data in [&image, &headerless] {
+            let rom = Rom::from_disk(data, &bios).unwrap();
+            assert_eq!(rom.format, HeaderFormat::Fds);
+            assert_eq!(rom.mapper, FDS_MAPPER);
+            assert_eq!(rom.prg_rom, bios);
+            assert_eq!(rom.prg_ram_bytes, 0x8000);
+            assert_eq!(rom.chr_ram_bytes, 0x2000);
+            assert_eq!(rom.disk_sides.len(), 2);
+            assert_eq!(rom.disk_sides[0][DISK_SIDE_SIZE - 1], 0x11);
+            assert_eq!(rom.disk_sides[1][DISK_SIDE_SIZE - 1], 0x22);
+        }
+    }
+
+    # [test]
+    fn test_errors() {
+        let bios = vec![0xeau8; BIOS_SIZE];
+        let mut bad_side = make_side(0x00);
+        bad_side.extend(vec![0u8; DISK_SIDE_SIZE]);
+        let cases = [
+            (make_side(0x00), vec![0u8; 0x1000], RomError::BadBiosSize(0x1000)),
+            (b"NES\x1a".to_vec(), bios.clone(), RomError::BadMagic(*b"NES\x1a")),
+            (b"FD".to_vec(), bios.clone(), RomError::TruncatedHeader(2)),
+            (b"FDS\x1a".to_vec(), bios.clone(), RomError::BadDiskSize(0)),
+            (make_side(0x00)[..0x1000].to_vec(), bios.clone(), RomError::BadDiskSize(0x1000)),
+            (bad_side, bios.clone(), RomError::BadDiskSide(1)),
+        ];
+        for (image, bios, error) in cases {
+            assert_eq!(Rom::from_disk(&image, &bios).err(), Some(error));
+        }
+    }
+}
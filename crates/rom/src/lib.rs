@@ -1,22 +1,402 @@
+pub mod database;
+mod errors;
+pub mod fds;
+pub mod mapper;
+pub mod nsf;
+pub mod patch;
+pub mod unif;
 
-use std::io::Read;
-use std::error::Error;
+pub use memory::mapper::Mirroring;
+pub use errors::{PatchError, RomError};
+
+pub const INES_HEADER_SIZE: usize = 0x0010;
+pub const TRAINER_SIZE: usize = 0x0200;
+pub const PRG_ROM_BANK_SIZE: usize = 0x4000;
+pub const CHR_ROM_BANK_SIZE: usize = 0x2000;
+pub const PRG_RAM_BANK_SIZE: usize = 0x2000;
+
+const FLAGS6_MIRRORING:   u8 = 0x01;
+const FLAGS6_BATTERY:     u8 = 0x02;
+const FLAGS6_TRAINER:     u8 = 0x04;
+const FLAGS6_FOUR_SCREEN: u8 = 0x08;
+const FLAGS7_CONSOLE:     u8 = 0x03;
+const FLAGS7_NES20:       u8 = 0x0c;
+const FLAGS9_TV_SYSTEM:   u8 = 0x01;
+
+const MAGIC: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
+/// Extended console types from $D are reserved.
+const RESERVED_CONSOLE_TYPE: u8 = 0x0d;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum HeaderFormat {
+    INes,
+    Nes20,
+    Unif,
+    /// A Famicom Disk System image, loaded with `Rom::from_disk`.
+    Fds,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum TvSystem {
+    Ntsc,
+    Pal,
+    MultipleRegion,
+    Dendy,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    Extended(u8),
+}
 
 #[derive(Clone)]
 pub struct Rom {
+    pub format: HeaderFormat,
+
     pub prg_rom_bytes: usize,
     pub chr_rom_bytes: usize,
+    pub prg_ram_bytes: usize,
+    pub prg_nvram_bytes: usize,
+    pub chr_ram_bytes: usize,
+    pub chr_nvram_bytes: usize,
+
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub has_battery: bool,
+    pub has_trainer: bool,
+    pub tv_system: TvSystem,
+    pub console_type: ConsoleType,
+    pub expansion_device: u8,
+    /// Game title from the UNIF NAME chunk.
+    pub name: Option<String>,
+
+    /// 512 bytes loaded into $7000-$71FF at power-on, empty when `has_trainer` is false.
+    pub trainer: Vec<u8>,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    /// Famicom Disk System sides in the .fds layout, empty for cartridges.
+    pub disk_sides: Vec<Vec<u8>>,
 }
 
 impl Rom {
-    pub fn is_valid(data: &mut Vec<u8>) -> bool {
-        &data[0..4] == [0x4e,0x45,0x53,0x1a]
+    pub fn is_valid(data: &[u8]) -> bool {
+        data.starts_with(&MAGIC) || data.starts_with(&unif::UNIF_MAGIC)
+    }
+
+    /// Parses an iNES, NES 2.0 or UNIF image.
+    pub fn new(data: &[u8]) -> Result<Self, RomError> {
+        if data.starts_with(&unif::UNIF_MAGIC) {
+            return unif::parse(data);
+        }
+        if data.len() < INES_HEADER_SIZE {
+            return Err(RomError::TruncatedHeader(data.len()));
+        }
+        if !data.starts_with(&MAGIC) {
+            return Err(RomError::BadMagic([data[0], data[1], data[2], data[3]]));
+        }
+
+        let flags6 = data[6];
+        let flags7 = data[7];
+        let format = if (flags7 & FLAGS7_NES20) == 0x08 { HeaderFormat::Nes20 } else { HeaderFormat::INes };
+
+        let mirroring = if (flags6 & FLAGS6_FOUR_SCREEN) == FLAGS6_FOUR_SCREEN {
+            Mirroring::FourScreen
+        } else if (flags6 & FLAGS6_MIRRORING) == FLAGS6_MIRRORING {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        let has_battery = (flags6 & FLAGS6_BATTERY) == FLAGS6_BATTERY;
+        let has_trainer = (flags6 & FLAGS6_TRAINER) == FLAGS6_TRAINER;
+        let console_type = match flags7 & FLAGS7_CONSOLE {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(if format == HeaderFormat::Nes20 { data[13] & 0x0f } else { 0 }),
+        };
+        if let ConsoleType::Extended(RESERVED_CONSOLE_TYPE..) = console_type {
+            return Err(RomError::CorruptNes20Field { offset: 13, value: data[13] });
+        }
+        let mapper = u16::from(flags6 >> 4) | u16::from(flags7 & 0xf0);
+
+        let mut rom = match format {
+            HeaderFormat::Nes20 => Rom {
+                format,
+                prg_rom_bytes: Rom::nes20_rom_size(data[4], data[9] & 0x0f, PRG_ROM_BANK_SIZE)
+                    .ok_or(RomError::CorruptNes20Field { offset: 4, value: data[4] })?,
+                chr_rom_bytes: Rom::nes20_rom_size(data[5], data[9] >> 4, CHR_ROM_BANK_SIZE)
+                    .ok_or(RomError::CorruptNes20Field { offset: 5, value: data[5] })?,
+                prg_ram_bytes: Rom::nes20_ram_size(data[10] & 0x0f),
+                prg_nvram_bytes: Rom::nes20_ram_size(data[10] >> 4),
+                chr_ram_bytes: Rom::nes20_ram_size(data[11] & 0x0f),
+                chr_nvram_bytes: Rom::nes20_ram_size(data[11] >> 4),
+                mapper: mapper | (u16::from(data[8] & 0x0f) << 8),
+                submapper: data[8] >> 4,
+                mirroring,
+                has_battery,
+                has_trainer,
+                tv_system: match data[12] & 0x03 {
+                    0 => TvSystem::Ntsc,
+                    1 => TvSystem::Pal,
+                    2 => TvSystem::MultipleRegion,
+                    _ => TvSystem::Dendy,
+                },
+                console_type,
+                expansion_device: data[15] & 0x3f,
+                name: None,
+                trainer: vec![],
+                prg_rom: vec![],
+                chr_rom: vec![],
+                disk_sides: vec![],
+            },
+            // Headers are either iNES or NES 2.0 here, UNIF images were dispatched above.
+            _ => {
+                let prg_rom_bytes = usize::from(data[4]) * PRG_ROM_BANK_SIZE;
+                let chr_rom_bytes = usize::from(data[5]) * CHR_ROM_BANK_SIZE;
+                Rom {
+                    format,
+                    prg_rom_bytes,
+                    chr_rom_bytes,
+                    // A value of 0 infers 8KB for compatibility.
+                    prg_ram_bytes: usize::from(data[8].max(1)) * PRG_RAM_BANK_SIZE,
+                    prg_nvram_bytes: 0,
+                    chr_ram_bytes: if chr_rom_bytes == 0 { CHR_ROM_BANK_SIZE } else { 0 },
+                    chr_nvram_bytes: 0,
+                    mapper,
+                    submapper: 0,
+                    mirroring,
+                    has_battery,
+                    has_trainer,
+                    tv_system: if (data[9] & FLAGS9_TV_SYSTEM) == FLAGS9_TV_SYSTEM { TvSystem::Pal } else { TvSystem::Ntsc },
+                    console_type,
+                    expansion_device: 0,
+                    name: None,
+                    trainer: vec![],
+                    prg_rom: vec![],
+                    chr_rom: vec![],
+                    disk_sides: vec![],
+                }
+            }
+        };
+
+        if rom.prg_rom_bytes == 0 {
+            return Err(RomError::EmptyPrgRom);
+        }
+
+        let trainer_bytes = if has_trainer { TRAINER_SIZE } else { 0 };
+        let available = data.len() - INES_HEADER_SIZE;
+        if available < trainer_bytes {
+            return Err(RomError::TruncatedTrainer(available));
+        }
+        let prg_rom_offset = INES_HEADER_SIZE + trainer_bytes;
+        rom.trainer = data[INES_HEADER_SIZE..prg_rom_offset].to_vec();
+
+        let available = data.len() - prg_rom_offset;
+        if available < rom.prg_rom_bytes {
+            return Err(RomError::PrgRomOutOfBounds { expected: rom.prg_rom_bytes, available });
+        }
+        let chr_rom_offset = prg_rom_offset + rom.prg_rom_bytes;
+        rom.prg_rom = data[prg_rom_offset..chr_rom_offset].to_vec();
+
+        let available = data.len() - chr_rom_offset;
+        if available < rom.chr_rom_bytes {
+            return Err(RomError::ChrRomOutOfBounds { expected: rom.chr_rom_bytes, available });
+        }
+        rom.chr_rom = data[chr_rom_offset..chr_rom_offset + rom.chr_rom_bytes].to_vec();
+        Ok(rom)
     }
 
-    pub fn new(data: &mut Vec<u8>) -> Self {
-        Rom {
-            prg_rom_bytes: usize::from(data[4]),
-            chr_rom_bytes: usize::from(data[5]),
+    /// Decodes a NES 2.0 PRG/CHR ROM size from its LSB and MSB nibble.
+    /// An MSB nibble of 0xF selects the exponent-multiplier notation, 2^E * (MM*2+1).
+    /// Returns `None` when the size does not fit in `usize`.
+    fn nes20_rom_size(lsb: u8, msb: u8, bank_size: usize) -> Option<usize> {
+        if msb == 0x0f {
+            let exponent = u32::from(lsb >> 2);
+            let multiplier = usize::from(lsb & 0x03) * 2 + 1;
+            1usize.checked_shl(exponent)
+                .and_then(|v| v.checked_mul(multiplier))
+        } else {
+            Some(((usize::from(msb) << 8) | usize::from(lsb)) * bank_size)
         }
     }
-}
\ No newline at end of file
+
+    /// Decodes a NES 2.0 RAM shift count. A shift count of 0 means no RAM.
+    fn nes20_ram_size(shift: u8) -> usize {
+        if shift == 0 { 0 } else { 64usize << shift }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ConsoleType, HeaderFormat, Mirroring, Rom, RomError, TvSystem};
+
+    fn make_ines(prg_banks: u8, chr_banks: u8, flags6: u8, flags7: u8, flags8: u8, flags9: u8) -> Vec<u8> {
+        let mut data = vec![
+            0x4e, 0x45, 0x53, 0x1a, prg_banks, chr_banks, flags6, flags7,
+            flags8, flags9, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        if (flags6 & 0x04) == 0x04 {
+            data.extend(vec![0xeeu8; super::TRAINER_SIZE]);
+        }
+        data.extend(vec![0xaau8; usize::from(prg_banks) * super::PRG_ROM_BANK_SIZE]);
+        data.extend(vec![0x55u8; usize::from(chr_banks) * super::CHR_ROM_BANK_SIZE]);
+        data
+    }
+
+    # [test]
+    fn test_is_valid() {
+        assert!(Rom::is_valid(&make_ines(1, 1, 0x00, 0x00, 0x00, 0x00)));
+        assert!(!Rom::is_valid(&[0x4e, 0x45, 0x53, 0x00]));
+        assert!(!Rom::is_valid(&[0x4e]));
+    }
+
+    # [test]
+    fn test_errors() {
+        let truncated_prg = make_ines(2, 1, 0x00, 0x00, 0x00, 0x00)[..0x4010].to_vec();
+        let truncated_chr = make_ines(1, 1, 0x00, 0x00, 0x00, 0x00)[..0x5010].to_vec();
+        let truncated_trainer = make_ines(1, 0, 0x04, 0x00, 0x00, 0x00)[..0x0110].to_vec();
+        let mut bad_magic = make_ines(1, 1, 0x00, 0x00, 0x00, 0x00);
+        bad_magic[3] = 0x00;
+        // Exponent-multiplier PRG size of 2^63 * 7.
+        let overflowing_size = make_nes20([0xff, 0x00, 0x00, 0x08, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], 0, 0);
+        let reserved_console = make_nes20([0x01, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00], 0x4000, 0);
+
+        let cases = [
+            (vec![], RomError::TruncatedHeader(0)),
+            (vec![0x4e, 0x45, 0x53, 0x1a, 0x01], RomError::TruncatedHeader(5)),
+            (bad_magic, RomError::BadMagic([0x4e, 0x45, 0x53, 0x00])),
+            (make_ines(0, 1, 0x00, 0x00, 0x00, 0x00), RomError::EmptyPrgRom),
+            (truncated_trainer, RomError::TruncatedTrainer(0x0100)),
+            (truncated_prg, RomError::PrgRomOutOfBounds { expected: 0x8000, available: 0x4000 }),
+            (truncated_chr, RomError::ChrRomOutOfBounds { expected: 0x2000, available: 0x1000 }),
+            (overflowing_size, RomError::CorruptNes20Field { offset: 4, value: 0xff }),
+            (reserved_console, RomError::CorruptNes20Field { offset: 13, value: 0x0e }),
+        ];
+        for (data, error) in cases {
+            assert_eq!(Rom::new(&data).err(), Some(error));
+        }
+    }
+
+    # [test]
+    fn test_parse_sizes() {
+        let rom = Rom::new(&make_ines(2, 1, 0x00, 0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(rom.prg_rom_bytes, 0x8000);
+        assert_eq!(rom.chr_rom_bytes, 0x2000);
+        assert_eq!(rom.prg_ram_bytes, 0x2000);
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+        assert!(rom.prg_rom.iter().all(|&v| v == 0xaa));
+        assert!(rom.chr_rom.iter().all(|&v| v == 0x55));
+
+        let rom = Rom::new(&make_ines(1, 0, 0x00, 0x00, 0x04, 0x00)).unwrap();
+        assert_eq!(rom.chr_rom_bytes, 0);
+        assert!(rom.chr_rom.is_empty());
+        assert_eq!(rom.prg_ram_bytes, 0x8000);
+    }
+
+    # [test]
+    fn test_parse_mapper() {
+        let rom = Rom::new(&make_ines(1, 1, 0x10, 0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(rom.mapper, 1);
+        let rom = Rom::new(&make_ines(1, 1, 0x40, 0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(rom.mapper, 4);
+        let rom = Rom::new(&make_ines(1, 1, 0x50, 0x40, 0x00, 0x00)).unwrap();
+        assert_eq!(rom.mapper, 69);
+        let rom = Rom::new(&make_ines(1, 1, 0xf0, 0xf0, 0x00, 0x00)).unwrap();
+        assert_eq!(rom.mapper, 255);
+    }
+
+    # [test]
+    fn test_parse_flags() {
+        let rom = Rom::new(&make_ines(1, 1, 0x00, 0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(rom.mirroring, Mirroring::Horizontal);
+        assert!(!rom.has_battery);
+        assert!(!rom.has_trainer);
+        assert!(rom.trainer.is_empty());
+        assert_eq!(rom.tv_system, TvSystem::Ntsc);
+
+        let rom = Rom::new(&make_ines(1, 1, 0x03, 0x00, 0x00, 0x01)).unwrap();
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.has_battery);
+        assert_eq!(rom.tv_system, TvSystem::Pal);
+
+        let rom = Rom::new(&make_ines(1, 1, 0x09, 0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(rom.mirroring, Mirroring::FourScreen);
+    }
+
+    fn make_nes20(header: [u8; 12], prg_bytes: usize, chr_bytes: usize) -> Vec<u8> {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a];
+        data.extend(header);
+        data.extend(vec![0xaau8; prg_bytes]);
+        data.extend(vec![0x55u8; chr_bytes]);
+        data
+    }
+
+    # [test]
+    fn test_parse_ines_defaults() {
+        let rom = Rom::new(&make_ines(1, 0, 0x00, 0x01, 0x00, 0x00)).unwrap();
+        assert_eq!(rom.format, HeaderFormat::INes);
+        assert_eq!(rom.submapper, 0);
+        assert_eq!(rom.chr_ram_bytes, 0x2000);
+        assert_eq!(rom.console_type, ConsoleType::VsSystem);
+    }
+
+    # [test]
+    fn test_parse_nes20() {
+        let rom = Rom::new(&make_nes20(
+            [0x02, 0x01, 0x41, 0x08, 0x31, 0x00, 0x07, 0x70, 0x03, 0x00, 0x00, 0x01],
+            0x8000, 0x2000,
+        )).unwrap();
+        assert_eq!(rom.format, HeaderFormat::Nes20);
+        assert_eq!(rom.mapper, 0x104);
+        assert_eq!(rom.submapper, 3);
+        assert_eq!(rom.prg_rom_bytes, 0x8000);
+        assert_eq!(rom.chr_rom_bytes, 0x2000);
+        assert_eq!(rom.prg_ram_bytes, 0x2000);
+        assert_eq!(rom.prg_nvram_bytes, 0);
+        assert_eq!(rom.chr_ram_bytes, 0);
+        assert_eq!(rom.chr_nvram_bytes, 0x2000);
+        assert_eq!(rom.tv_system, TvSystem::Dendy);
+        assert_eq!(rom.console_type, ConsoleType::Nes);
+        assert_eq!(rom.expansion_device, 1);
+        assert_eq!(rom.prg_rom.len(), 0x8000);
+        assert_eq!(rom.chr_rom.len(), 0x2000);
+    }
+
+    # [test]
+    fn test_parse_nes20_extended_sizes() {
+        // PRG: MSB 0x1, LSB 0x00 -> 256 banks of 16KB.
+        let rom = Rom::new(&make_nes20(
+            [0x00, 0x00, 0x00, 0x0b, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00],
+            0x40_0000, 0,
+        )).unwrap();
+        assert_eq!(rom.prg_rom_bytes, 0x40_0000);
+        assert_eq!(rom.tv_system, TvSystem::MultipleRegion);
+        assert_eq!(rom.console_type, ConsoleType::Extended(0));
+
+        // Exponent-multiplier notation: 2^10 * 3 for PRG and 2^7 * 1 for CHR.
+        let rom = Rom::new(&make_nes20(
+            [0x29, 0x1c, 0x00, 0x0a, 0x00, 0xff, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00],
+            3 * 1024, 128,
+        )).unwrap();
+        assert_eq!(rom.prg_rom_bytes, 3 * 1024);
+        assert_eq!(rom.chr_rom_bytes, 128);
+        assert_eq!(rom.console_type, ConsoleType::Playchoice10);
+        assert_eq!(rom.tv_system, TvSystem::Pal);
+    }
+
+    # [test]
+    fn test_skip_trainer() {
+        let rom = Rom::new(&make_ines(1, 1, 0x04, 0x00, 0x00, 0x00)).unwrap();
+        assert!(rom.has_trainer);
+        assert_eq!(rom.trainer.len(), 0x200);
+        assert!(rom.trainer.iter().all(|&v| v == 0xee));
+        assert_eq!(rom.prg_rom.len(), 0x4000);
+        assert!(rom.prg_rom.iter().all(|&v| v == 0xaa));
+        assert!(rom.chr_rom.iter().all(|&v| v == 0x55));
+    }
+}
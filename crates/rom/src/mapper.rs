@@ -0,0 +1,176 @@
+pub mod discrete;
+pub mod fds;
+pub mod fme7;
+pub mod mmc1;
+pub mod mmc3;
+pub mod mmc5;
+pub mod multicart;
+pub mod namco163;
+pub mod nrom;
+pub mod nsf;
+pub mod vrc;
+pub mod vrc6;
+pub mod vrc7;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use memory::mapper::Mapper;
+
+use crate::Rom;
+
+const CHR_RAM_MIN_SIZE: usize = 0x2000;
+
+/// Builds a mapper for the cartridge described by the given `Rom`.
+/// Closures may capture state, such as settings of the board they build.
+pub type MapperConstructor = Arc<dyn Fn(&Rom) -> Box<dyn Mapper> + Send + Sync>;
+
+/// Maps iNES mapper numbers to constructors.
+/// `default` holds the mappers of this crate, other crates can add boards or replace them with `register`.
+#[derive(Clone)]
+pub struct MapperRegistry {
+    constructors: HashMap<u16, MapperConstructor>,
+}
+
+impl Default for MapperRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            constructors: HashMap::new(),
+        };
+        registry.register(0, |rom| Box::new(nrom::Nrom::new(rom)));
+        registry.register(1, |rom| Box::new(mmc1::Mmc1::new(rom)));
+        registry.register(4, |rom| Box::new(mmc3::Mmc3::new(rom)));
+        registry.register(5, |rom| Box::new(mmc5::Mmc5::new(rom)));
+        for mapper in [21, 22, 23, 25] {
+            registry.register(mapper, |rom| Box::new(vrc::Vrc::new(rom)));
+        }
+        for mapper in [24, 26] {
+            registry.register(mapper, |rom| Box::new(vrc6::Vrc6::new(rom)));
+        }
+        registry.register(19, |rom| Box::new(namco163::Namco163::new(rom)));
+        registry.register(20, |rom| Box::new(fds::Fds::new(rom)));
+        registry.register(69, |rom| Box::new(fme7::Fme7::new(rom)));
+        registry.register(85, |rom| Box::new(vrc7::Vrc7::new(rom)));
+        for mapper in [2, 3, 7, 11, 34, 66] {
+            registry.register(mapper, |rom| Box::new(discrete::Discrete::new(rom)));
+        }
+        registry.register(155, |rom| Box::new(mmc1::Mmc1::new(rom)));
+        for mapper in [15, 225, 227, 228] {
+            registry.register(mapper, |rom| Box::new(multicart::Multicart::new(rom)));
+        }
+        registry
+    }
+}
+
+impl MapperRegistry {
+    /// Adds a constructor for a mapper number, replacing any constructor already registered for it.
+    pub fn register(&mut self, mapper: u16, constructor: impl Fn(&Rom) -> Box<dyn Mapper> + Send + Sync + 'static) {
+        self.constructors.insert(mapper, Arc::new(constructor));
+    }
+
+    pub fn is_supported(&self, mapper: u16) -> bool {
+        self.constructors.contains_key(&mapper)
+    }
+
+    pub fn create(&self, rom: &Rom) -> Option<Box<dyn Mapper>> {
+        self.constructors.get(&rom.mapper).map(|constructor| constructor(rom))
+    }
+}
+
+/// Returns the cartridge CHR memory and whether it is writable CHR-RAM.
+pub(crate) fn chr_memory(rom: &Rom) -> (Vec<u8>, bool) {
+    if rom.chr_rom.is_empty() {
+        let bytes = (rom.chr_ram_bytes + rom.chr_nvram_bytes).max(CHR_RAM_MIN_SIZE);
+        (vec![0; bytes], true)
+    } else {
+        (rom.chr_rom.clone(), false)
+    }
+}
+
+/// PRG-RAM given to cartridges with a trainer, which is loaded at $7000-$71FF.
+const TRAINER_PRG_RAM_SIZE: usize = 0x2000;
+
+/// Returns zero-filled PRG-RAM sized from the header, and large enough for the trainer if there is one.
+pub(crate) fn prg_ram(rom: &Rom) -> Vec<u8> {
+    let size = rom.prg_ram_bytes + rom.prg_nvram_bytes;
+    vec![0; if rom.has_trainer { size.max(TRAINER_PRG_RAM_SIZE) } else { size }]
+}
+
+/// Builds an NES 2.0 image for the mapper tests whose PRG and CHR banks are filled with their bank number.
+/// Without CHR banks the image has 8KB of CHR-RAM, `prg_ram_shift` sizes PRG-RAM as in header byte 10.
+#[cfg(test)]
+pub(crate) fn make_rom(
+    mapper: u16,
+    submapper: u8,
+    prg_bank_size: usize,
+    prg_banks: usize,
+    chr_bank_size: usize,
+    chr_banks: usize,
+    prg_ram_shift: u8,
+) -> Rom {
+    let prg_units = prg_bank_size * prg_banks / 0x4000;
+    let chr_units = chr_bank_size * chr_banks / 0x2000;
+    let mut data = vec![
+        0x4e, 0x45, 0x53, 0x1a, prg_units as u8, chr_units as u8, ((mapper & 0x0f) << 4) as u8, (mapper & 0xf0) as u8 | 0x08,
+        (submapper << 4) | (mapper >> 8) as u8, (((chr_units >> 8) << 4) | (prg_units >> 8)) as u8, prg_ram_shift,
+        if chr_banks == 0 { 0x07 } else { 0x00 }, 0x00, 0x00, 0x00, 0x00,
+    ];
+    for bank in 0..prg_banks {
+        data.extend(vec![bank as u8; prg_bank_size]);
+    }
+    for bank in 0..chr_banks {
+        data.extend(vec![bank as u8; chr_bank_size]);
+    }
+    Rom::new(&data).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::mapper::{Mapper, Mirroring};
+    use crate::Rom;
+    use crate::mapper::MapperRegistry;
+
+    #[derive(Clone)]
+    struct Dummy(Mirroring);
+
+    impl Mapper for Dummy {
+        fn cpu_peek_u8(&self, _address: u16) -> Option<u8> { None }
+        fn cpu_write_u8(&mut self, _address: u16, _data: u8) {}
+        fn ppu_read_u8(&mut self, _address: u16) -> u8 { 0 }
+        fn ppu_write_u8(&mut self, _address: u16, _data: u8) {}
+        fn mirroring(&self) -> Mirroring { self.0 }
+    }
+
+    # [test]
+    fn test_register_and_create() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0xf1, 0xf0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(vec![0u8; 0x4000]);
+        let rom = Rom::new(&data).unwrap();
+
+        let mut registry = MapperRegistry::default();
+        assert!(registry.is_supported(0));
+        assert!(!registry.is_supported(255));
+        assert!(registry.create(&rom).is_none());
+
+        registry.register(255, |rom| Box::new(Dummy(rom.mirroring)));
+        assert!(registry.is_supported(255));
+        let mapper = registry.create(&rom).unwrap();
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    # [test]
+    fn test_register_capturing_closure() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0xf1, 0xf0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(vec![0u8; 0x4000]);
+        let rom = Rom::new(&data).unwrap();
+
+        let mirroring = Mirroring::SingleScreenUpper;
+        let mut registry = MapperRegistry::default();
+        registry.register(255, move |_| Box::new(Dummy(mirroring)));
+
+        // Clones share the constructor along with what it captured.
+        let registry = registry.clone();
+        let mapper = registry.create(&rom).unwrap();
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+}
@@ -0,0 +1,337 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const NINA_001_PRG_RAM_SIZE: usize = 0x2000;
+
+/// Boards built from discrete logic chips, which only latch a bank number.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Board {
+    /// Mapper 2: 16KB switchable bank at $8000, last bank fixed at $C000.
+    Uxrom,
+    /// Mapper 3: 8KB CHR bank.
+    Cnrom,
+    /// Mapper 7: 32KB PRG bank and single-screen mirroring.
+    Axrom,
+    /// Mapper 11: 32KB PRG bank and 8KB CHR bank.
+    ColorDreams,
+    /// Mapper 34, submapper 2: 32KB PRG bank.
+    Bnrom,
+    /// Mapper 34, submapper 1: 32KB PRG bank and two 4KB CHR banks at $7FFD-$7FFF.
+    Nina001,
+    /// Mapper 66: 32KB PRG bank and 8KB CHR bank.
+    Gxrom,
+}
+
+impl Board {
+    fn from(rom: &Rom) -> Board {
+        match rom.mapper {
+            2 => Board::Uxrom,
+            3 => Board::Cnrom,
+            7 => Board::Axrom,
+            11 => Board::ColorDreams,
+            34 => match rom.submapper {
+                1 => Board::Nina001,
+                2 => Board::Bnrom,
+                // Only NINA-001 has CHR-ROM larger than 8KB.
+                _ => if rom.chr_rom.len() > 0x2000 { Board::Nina001 } else { Board::Bnrom },
+            },
+            _ => Board::Gxrom,
+        }
+    }
+
+    /// Whether the board lets the PRG-ROM drive the data bus during register writes
+    /// when the NES 2.0 submapper does not say.
+    fn has_bus_conflicts(&self) -> bool {
+        !matches!(self, Board::Axrom | Board::Nina001)
+    }
+}
+
+/// Mappers 2, 3, 7, 11, 34 and 66.
+/// For mappers 2, 3 and 7 submapper 1 disables and submapper 2 enables bus conflicts.
+#[derive(Clone)]
+pub struct Discrete {
+    board: Board,
+    has_bus_conflicts: bool,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+    mirroring: Mirroring,
+
+    prg_bank: usize,
+    chr_banks: [usize; 2],
+}
+
+impl Discrete {
+    pub fn new(rom: &Rom) -> Self {
+        let board = Board::from(rom);
+        let has_bus_conflicts = match (rom.mapper, rom.submapper) {
+            (2 | 3 | 7, 1) => false,
+            (2 | 3 | 7, 2) => true,
+            _ => board.has_bus_conflicts(),
+        };
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            board,
+            has_bus_conflicts,
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: match board {
+                Board::Nina001 => vec![0; NINA_001_PRG_RAM_SIZE],
+                // Boards without PRG-RAM get some to hold the trainer.
+                _ if rom.has_trainer => prg_ram(rom),
+                _ => vec![],
+            },
+            chr,
+            is_chr_ram,
+            mirroring: if board == Board::Axrom { Mirroring::SingleScreenLower } else { rom.mirroring },
+            prg_bank: 0,
+            chr_banks: [0, 1],
+        }
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let offset = usize::from(address - 0x8000);
+        let index = if self.board == Board::Uxrom {
+            let bank = if address < 0xc000 { self.prg_bank } else { (self.prg_rom.len() / PRG_BANK_SIZE).saturating_sub(1) };
+            bank * PRG_BANK_SIZE + (offset & (PRG_BANK_SIZE - 1))
+        } else {
+            self.prg_bank * PRG_BANK_SIZE * 2 + offset
+        };
+        index % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = self.chr_banks[usize::from(address >> 12) & 0x01];
+        let offset = usize::from(address) & (CHR_BANK_SIZE - 1);
+        (bank * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+
+    fn select_chr_8k(&mut self, bank: u8) {
+        self.chr_banks = [usize::from(bank) * 2, usize::from(bank) * 2 + 1];
+    }
+
+    fn write_latch(&mut self, data: u8) {
+        match self.board {
+            Board::Uxrom => self.prg_bank = usize::from(data),
+            Board::Cnrom => self.select_chr_8k(data),
+            Board::Axrom => {
+                self.prg_bank = usize::from(data & 0x07);
+                self.mirroring = if (data & 0x10) == 0x10 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+            }
+            Board::ColorDreams => {
+                self.prg_bank = usize::from(data & 0x03);
+                self.select_chr_8k(data >> 4);
+            }
+            Board::Bnrom => self.prg_bank = usize::from(data),
+            Board::Nina001 => {}
+            Board::Gxrom => {
+                self.prg_bank = usize::from((data >> 4) & 0x03);
+                self.select_chr_8k(data & 0x03);
+            }
+        }
+    }
+}
+
+impl Mapper for Discrete {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => Some(self.prg_ram[usize::from(address - 0x6000)]),
+            0x8000..=0xffff if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                self.prg_ram[usize::from(address - 0x6000)] = data;
+                match address {
+                    0x7ffd => self.prg_bank = usize::from(data & 0x01),
+                    0x7ffe => self.chr_banks[0] = usize::from(data & 0x0f),
+                    0x7fff => self.chr_banks[1] = usize::from(data & 0x0f),
+                    _ => {}
+                }
+            }
+            0x8000..=0xffff => {
+                let data = if self.has_bus_conflicts && !self.prg_rom.is_empty() {
+                    data & self.prg_rom[self.prg_rom_index(address)]
+                } else {
+                    data
+                };
+                self.write_latch(data);
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::Rom;
+    use crate::mapper::make_rom;
+    use crate::mapper::discrete::{Board, Discrete};
+
+    // The last byte of each 16KB PRG bank is 0xff for bus conflict tests.
+    fn create(mapper: u16, submapper: u8, prg_banks: usize, chr_banks: usize) -> Memory {
+        let mut rom = make_rom(mapper, submapper, 0x4000, prg_banks, 0x1000, chr_banks * 2, 0);
+        for bank in rom.prg_rom.chunks_mut(0x4000) {
+            bank[0x3fff] = 0xff;
+        }
+        Memory::new(Box::new(Discrete::new(&rom)))
+    }
+
+    # [test]
+    fn test_uxrom() {
+        let mut mem = create(2, 1, 8, 0);
+        assert_eq!(mem.read_u8(0x8000), 0);
+        assert_eq!(mem.read_u8(0xc000), 7);
+        mem.write_u8(0x8000, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 5);
+        assert_eq!(mem.read_u8(0xbffe), 5);
+        assert_eq!(mem.read_u8(0xc000), 7);
+
+        // CHR-RAM
+        mem.write_video_u8(0x0010, 0x12);
+        assert_eq!(mem.read_video_u8(0x0010), 0x12);
+    }
+
+    # [test]
+    fn test_uxrom_small_prg() {
+        // NES 2.0 exponent-multiplier notation allows less PRG-ROM than one 16KB bank, here 2^13 bytes.
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 13 << 2, 0x00, 0x20, 0x08, 0x00, 0x0f, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00];
+        data.extend((0..0x2000).map(|index| (index >> 8) as u8));
+        let mut mem = Memory::new(Box::new(Discrete::new(&Rom::new(&data).unwrap())));
+        assert_eq!(mem.read_u8(0x8000), 0x00);
+        assert_eq!(mem.read_u8(0xc000), 0x00);
+        assert_eq!(mem.read_u8(0xfffc), 0x1f);
+        mem.write_u8(0x8000, 0x01);
+        assert_eq!(mem.read_u8(0x8100), 0x01);
+    }
+
+    # [test]
+    fn test_uxrom_bus_conflicts() {
+        // The ROM at $8000 holds 0, so every write latches 0.
+        let mut mem = create(2, 2, 8, 0);
+        mem.write_u8(0x8000, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 0);
+        // The last byte is 0xff, so the value goes through unchanged.
+        mem.write_u8(0xffff, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 5);
+
+        let mut mem = create(2, 0, 8, 0);
+        mem.write_u8(0x8000, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 0);
+    }
+
+    # [test]
+    fn test_cnrom() {
+        let mut mem = create(3, 1, 2, 4);
+        mem.write_u8(0x8000, 0x02);
+        assert_eq!(mem.read_video_u8(0x0000), 4);
+        assert_eq!(mem.read_video_u8(0x1000), 5);
+        assert_eq!(mem.read_u8(0xc000), 1);
+
+        // CHR-ROM is not writable.
+        mem.write_video_u8(0x0000, 0x12);
+        assert_eq!(mem.read_video_u8(0x0000), 4);
+    }
+
+    # [test]
+    fn test_axrom() {
+        let mut mem = create(7, 0, 8, 0);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::SingleScreenLower);
+        mem.write_u8(0x8000, 0x12);
+        assert_eq!(mem.read_u8(0x8000), 4);
+        assert_eq!(mem.read_u8(0xc000), 5);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::SingleScreenUpper);
+        mem.write_u8(0x8000, 0x03);
+        assert_eq!(mem.read_u8(0x8000), 6);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::SingleScreenLower);
+
+        // AMROM has bus conflicts.
+        let mut mem = create(7, 2, 8, 0);
+        mem.write_u8(0x8000, 0x12);
+        assert_eq!(mem.read_u8(0x8000), 0);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    # [test]
+    fn test_color_dreams() {
+        let mut mem = create(11, 0, 8, 8);
+        mem.write_u8(0xffff, 0x32);
+        assert_eq!(mem.read_u8(0x8000), 4);
+        assert_eq!(mem.read_video_u8(0x0000), 6);
+        assert_eq!(mem.read_video_u8(0x1000), 7);
+    }
+
+    # [test]
+    fn test_bnrom() {
+        let mut mem = create(34, 0, 8, 0);
+        assert_eq!(mem.read_u8(0x8000), 0);
+        mem.write_u8(0xffff, 0x02);
+        assert_eq!(mem.read_u8(0x8000), 4);
+        assert_eq!(mem.read_u8(0xc000), 5);
+    }
+
+    # [test]
+    fn test_nina_001() {
+        let rom = make_rom(34, 0, 0x4000, 4, 0x1000, 8, 0);
+        assert_eq!(Discrete::new(&rom).board(), Board::Nina001);
+
+        let mut mem = Memory::new(Box::new(Discrete::new(&rom)));
+        mem.write_u8(0x7ffd, 0x01);
+        mem.write_u8(0x7ffe, 0x03);
+        mem.write_u8(0x7fff, 0x06);
+        assert_eq!(mem.read_u8(0x8000), 2);
+        assert_eq!(mem.read_video_u8(0x0000), 3);
+        assert_eq!(mem.read_video_u8(0x1000), 6);
+
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+    }
+
+    # [test]
+    fn test_gxrom() {
+        let mut mem = create(66, 0, 8, 8);
+        mem.write_u8(0xffff, 0x21);
+        assert_eq!(mem.read_u8(0x8000), 4);
+        assert_eq!(mem.read_video_u8(0x0000), 2);
+        assert_eq!(mem.read_video_u8(0x1000), 3);
+    }
+}
@@ -0,0 +1,593 @@
+use memory::mapper::{DiskDrive, Mapper, Mirroring};
+
+use crate::Rom;
+use crate::fds::DISK_SIDE_SIZE;
+use crate::mapper::{chr_memory, prg_ram};
+
+/// Gap of 28300 bits before the first block of a side.
+const LEAD_IN_SIZE: usize = 28300 / 8;
+/// Gap of 976 bits after each block.
+const BLOCK_GAP_SIZE: usize = 976 / 8;
+/// Mark that ends a gap and starts a block.
+const BLOCK_START: u8 = 0x80;
+const CRC_SIZE: usize = 2;
+/// CPU cycles from the motor starting to the head reaching the disk.
+const SPIN_UP_CYCLES: usize = 50000;
+/// CPU cycles to transfer one byte.
+const BYTE_CYCLES: usize = 150;
+
+/// Returns the length of the block starting with `code`, or `None` when no block starts there.
+/// `file_size` is taken from the file header block that precedes each file data block.
+fn block_length(code: u8, file_size: usize) -> Option<usize> {
+    match code {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+/// Returns the file size declared by a file header block.
+fn file_size(block: &[u8]) -> usize {
+    usize::from(u16::from_le_bytes([block[13], block[14]]))
+}
+
+/// Converts a side from the .fds layout to the bytes passing the drive head, with gaps and CRCs.
+/// The adapter never reports CRC errors, so blocks carry a placeholder CRC.
+fn raw_side(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEAD_IN_SIZE];
+    let mut offset = 0;
+    let mut size = 0;
+    while let Some(block) = side.get(offset)
+        .and_then(|&code| block_length(code, size))
+        .and_then(|length| side.get(offset..offset + length))
+    {
+        if block[0] == 3 {
+            size = file_size(block);
+        }
+        raw.push(BLOCK_START);
+        raw.extend(block);
+        raw.extend([0; CRC_SIZE]);
+        raw.extend([0; BLOCK_GAP_SIZE]);
+        offset += block.len();
+    }
+    // The unused end of the side stays available for files the game writes.
+    raw.extend(&side[offset.min(side.len())..]);
+    raw
+}
+
+/// Converts the bytes passing the drive head back to the .fds layout.
+fn fds_side(raw: &[u8]) -> Vec<u8> {
+    let mut side = vec![];
+    let mut position = 0;
+    let mut size = 0;
+    while position < raw.len() {
+        if raw[position] != BLOCK_START {
+            position += 1;
+            continue;
+        }
+        position += 1;
+        let block = match raw.get(position)
+            .and_then(|&code| block_length(code, size))
+            .and_then(|length| raw.get(position..position + length))
+        {
+            Some(block) => block,
+            None => break,
+        };
+        if block[0] == 3 {
+            size = file_size(block);
+        }
+        side.extend(block);
+        position += block.len() + CRC_SIZE;
+    }
+    side.resize(DISK_SIDE_SIZE, 0);
+    side
+}
+
+#[derive(Clone)]
+struct Side {
+    /// The side as loaded, returned unchanged until the game writes to it.
+    data: Vec<u8>,
+    raw: Vec<u8>,
+    is_written: bool,
+}
+
+/// Mapper 20: the Famicom Disk System RAM adapter and disk drive.
+/// The expansion audio registers at $4040-$4092 are left to the APU.
+#[derive(Clone)]
+pub struct Fds {
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+
+    sides: Vec<Side>,
+    inserted_side: Option<usize>,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    is_timer_repeat: bool,
+    is_timer_enabled: bool,
+    is_timer_irq: bool,
+    is_disk_io_enabled: bool,
+
+    control: u8,
+    write_data: u8,
+    read_data: u8,
+    is_transfer_complete: bool,
+    is_disk_irq: bool,
+
+    position: usize,
+    delay: usize,
+    is_end_of_head: bool,
+    is_scanning: bool,
+    is_gap_ended: bool,
+}
+
+impl Fds {
+    pub fn new(rom: &Rom) -> Self {
+        let (chr_ram, _) = chr_memory(rom);
+        Self {
+            bios: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr_ram,
+            sides: rom.disk_sides.iter()
+                .map(|side| Side { data: side.clone(), raw: raw_side(side), is_written: false })
+                .collect(),
+            inserted_side: if rom.disk_sides.is_empty() { None } else { Some(0) },
+            timer_reload: 0,
+            timer_counter: 0,
+            is_timer_repeat: false,
+            is_timer_enabled: false,
+            is_timer_irq: false,
+            is_disk_io_enabled: false,
+            control: 0,
+            write_data: 0,
+            read_data: 0,
+            is_transfer_complete: false,
+            is_disk_irq: false,
+            position: 0,
+            delay: 0,
+            is_end_of_head: true,
+            is_scanning: false,
+            is_gap_ended: false,
+        }
+    }
+
+    fn is_motor_on(&self) -> bool {
+        (self.control & 0x01) == 0x01
+    }
+
+    fn is_transfer_reset(&self) -> bool {
+        (self.control & 0x02) == 0x02
+    }
+
+    fn is_read_mode(&self) -> bool {
+        (self.control & 0x04) == 0x04
+    }
+
+    fn is_crc_control(&self) -> bool {
+        (self.control & 0x10) == 0x10
+    }
+
+    fn is_transfer_started(&self) -> bool {
+        (self.control & 0x40) == 0x40
+    }
+
+    fn is_disk_irq_enabled(&self) -> bool {
+        (self.control & 0x80) == 0x80
+    }
+
+    fn step_timer(&mut self) {
+        if !self.is_timer_enabled {
+            return;
+        }
+        if self.timer_counter == 0 {
+            self.is_timer_irq = true;
+            self.timer_counter = self.timer_reload;
+            self.is_timer_enabled = self.is_timer_repeat;
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn step_disk(&mut self) {
+        let index = match self.inserted_side {
+            Some(index) if self.is_motor_on() => index,
+            _ => {
+                self.is_end_of_head = true;
+                self.is_scanning = false;
+                return;
+            }
+        };
+        if self.is_transfer_reset() && !self.is_scanning {
+            return;
+        }
+        if self.is_end_of_head {
+            // The head returns to the start of the side before the transfer begins.
+            self.delay = SPIN_UP_CYCLES;
+            self.is_end_of_head = false;
+            self.position = 0;
+            self.is_gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.is_scanning = true;
+        if self.is_read_mode() {
+            let data = self.sides[index].raw[self.position];
+            let mut is_irq = self.is_disk_irq_enabled();
+            if !self.is_transfer_started() {
+                self.is_gap_ended = false;
+            } else if data != 0 && !self.is_gap_ended {
+                // The block start mark ends the gap without being transferred.
+                self.is_gap_ended = true;
+                is_irq = false;
+            }
+            if self.is_gap_ended {
+                self.is_transfer_complete = true;
+                self.read_data = data;
+                self.is_disk_irq |= is_irq;
+            }
+        } else {
+            let mut data = 0;
+            if !self.is_crc_control() {
+                self.is_transfer_complete = true;
+                self.is_disk_irq |= self.is_disk_irq_enabled();
+                data = self.write_data;
+            }
+            if !self.is_transfer_started() || self.is_crc_control() {
+                data = 0;
+            }
+            let side = &mut self.sides[index];
+            side.raw[self.position] = data;
+            side.is_written = true;
+            self.is_gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= self.sides[index].raw.len() {
+            // The head reached the end of the side, which stops the motor.
+            self.control &= !0x01;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for Fds {
+    fn cpu_read_u8(&mut self, address: u16) -> Option<u8> {
+        let data = self.cpu_peek_u8(address);
+        match address {
+            // Reading the status acknowledges both IRQs.
+            0x4030 => {
+                self.is_timer_irq = false;
+                self.is_transfer_complete = false;
+                self.is_disk_irq = false;
+            }
+            0x4031 => {
+                self.is_transfer_complete = false;
+                self.is_disk_irq = false;
+            }
+            _ => {}
+        }
+        data
+    }
+
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4030 => {
+                let mut status = 0;
+                if self.is_timer_irq {
+                    status |= 0x01;
+                }
+                if self.is_transfer_complete {
+                    status |= 0x02;
+                }
+                if self.is_end_of_head {
+                    status |= 0x40;
+                }
+                Some(status)
+            }
+            0x4031 => Some(self.read_data),
+            0x4032 => {
+                let is_inserted = self.inserted_side.is_some();
+                let mut status = 0;
+                if !is_inserted {
+                    // An empty drive also reports itself as not ready and write protected.
+                    status |= 0x05;
+                }
+                if !is_inserted || !self.is_scanning {
+                    status |= 0x02;
+                }
+                Some(status)
+            }
+            // Bit 7 reports a good battery in the drive.
+            0x4033 => Some(0x80),
+            // Cartridge images using mapper 20 may come with less RAM or BIOS than the adapter has.
+            0x6000..=0xdfff => self.prg_ram.get(usize::from(address - 0x6000)).copied(),
+            0xe000..=0xffff => self.bios.get(usize::from(address - 0xe000)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        if (0x4024..=0x4026).contains(&address) && !self.is_disk_io_enabled {
+            return;
+        }
+        match address {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xff00) | u16::from(data),
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00ff) | (u16::from(data) << 8),
+            0x4022 => {
+                self.is_timer_repeat = (data & 0x01) == 0x01;
+                self.is_timer_enabled = (data & 0x02) == 0x02 && self.is_disk_io_enabled;
+                if self.is_timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.is_timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.is_disk_io_enabled = (data & 0x01) == 0x01;
+                if !self.is_disk_io_enabled {
+                    self.is_timer_enabled = false;
+                    self.is_timer_irq = false;
+                    self.is_disk_irq = false;
+                }
+            }
+            0x4024 => {
+                self.write_data = data;
+                self.is_transfer_complete = false;
+                self.is_disk_irq = false;
+            }
+            0x4025 => {
+                self.control = data;
+                self.is_disk_irq = false;
+            }
+            0x6000..=0xdfff => {
+                if let Some(value) = self.prg_ram.get_mut(usize::from(address - 0x6000)) {
+                    *value = data;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.chr_ram[usize::from(address) % self.chr_ram.len()]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        let index = usize::from(address) % self.chr_ram.len();
+        self.chr_ram[index] = data;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if (self.control & 0x08) == 0x08 { Mirroring::Horizontal } else { Mirroring::Vertical }
+    }
+
+    fn step(&mut self, cpu_cycles: usize) {
+        for _ in 0..cpu_cycles {
+            self.step_timer();
+            self.step_disk();
+        }
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.is_timer_irq || self.is_disk_irq
+    }
+
+    fn disk_drive(&self) -> Option<&dyn DiskDrive> {
+        Some(self)
+    }
+
+    fn disk_drive_mut(&mut self) -> Option<&mut dyn DiskDrive> {
+        Some(self)
+    }
+}
+
+impl DiskDrive for Fds {
+    fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    fn inserted_side(&self) -> Option<usize> {
+        self.inserted_side
+    }
+
+    fn insert_side(&mut self, side: Option<usize>) {
+        self.inserted_side = side.filter(|&side| side < self.sides.len());
+        self.is_end_of_head = true;
+        self.is_scanning = false;
+    }
+
+    fn sides(&self) -> Vec<Vec<u8>> {
+        self.sides.iter()
+            .map(|side| if side.is_written { fds_side(&side.raw) } else { side.data.clone() })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::Rom;
+    use crate::fds::{BIOS_SIZE, DISK_SIDE_SIZE};
+    use crate::mapper::fds::{fds_side, raw_side, Fds, LEAD_IN_SIZE};
+
+    // A side with the disk info block, a file count block, one 3-byte file and trailing zeros.
+    fn make_side(id: u8) -> Vec<u8> {
+        let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+        side.resize(56, id);
+        side.extend([0x02, 0x01]);
+        side.extend([0x03, 0x00, 0x00, b'F', b'I', b'L', b'E', b'-', b'0', b'0', b'0', 0x00, 0x60, 0x03, 0x00, 0x00]);
+        side.extend([0x04, 0xaa, 0xbb, 0xcc]);
+        side.resize(DISK_SIDE_SIZE, 0);
+        side
+    }
+
+    fn make_rom() -> Rom {
+        let mut image = make_side(0x11);
+        image.extend(make_side(0x22));
+        let mut bios = vec![0xeau8; BIOS_SIZE];
+        bios[0x1ffc] = 0x24;
+        bios[0x1ffd] = 0xee;
+        Rom::from_disk(&image, &bios).unwrap()
+    }
+
+    // Steps one cycle at a time until the condition holds, at most a full side.
+    fn step_until(mem: &mut Memory, condition: impl Fn(&mut Memory) -> bool) {
+        for _ in 0..(LEAD_IN_SIZE + DISK_SIDE_SIZE) * 200 {
+            if condition(mem) {
+                return;
+            }
+            mem.mapper.step(1);
+        }
+        panic!("condition never held");
+    }
+
+    # [test]
+    fn test_cartridge_image() {
+        // NES 2.0 header for mapper 20 with 4KB of PRG-ROM and no PRG-RAM.
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 12 << 2, 0x00, 0x40, 0x18, 0x00, 0x0f, 0x00, 0x07, 0, 0, 0, 0];
+        data.extend(vec![0xeau8; 0x1000]);
+        let mut mem = Memory::new(Box::new(Fds::new(&Rom::new(&data).unwrap())));
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x6000), None);
+        assert_eq!(mem.mapper.cpu_peek_u8(0xefff), Some(0xea));
+        assert_eq!(mem.mapper.cpu_peek_u8(0xfffc), None);
+    }
+
+    # [test]
+    fn test_raw_side_round_trip() {
+        let side = make_side(0x11);
+        let raw = raw_side(&side);
+        assert!(raw[..LEAD_IN_SIZE].iter().all(|&v| v == 0));
+        assert_eq!(raw[LEAD_IN_SIZE], 0x80);
+        assert_eq!(raw[LEAD_IN_SIZE + 1..LEAD_IN_SIZE + 16], side[..15]);
+        assert_eq!(fds_side(&raw), side);
+    }
+
+    # [test]
+    fn test_memory_map() {
+        let mut mem = Memory::new(Box::new(Fds::new(&make_rom())));
+        mem.write_u8(0x6000, 0x12);
+        mem.write_u8(0xdfff, 0x34);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+        assert_eq!(mem.read_u8(0xdfff), 0x34);
+        assert_eq!(mem.read_u8(0xfffd), 0xee);
+        // The BIOS is read-only.
+        mem.write_u8(0xe000, 0x00);
+        assert_eq!(mem.read_u8(0xe000), 0xea);
+
+        mem.write_video_u8(0x1fff, 0x56);
+        assert_eq!(mem.read_video_u8(0x1fff), 0x56);
+
+        // $4025 is ignored until disk I/O is enabled through $4023.
+        mem.write_u8(0x4025, 0x28);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Vertical);
+        mem.write_u8(0x4023, 0x01);
+        mem.write_u8(0x4025, 0x28);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    # [test]
+    fn test_timer_irq() {
+        let mut mem = Memory::new(Box::new(Fds::new(&make_rom())));
+        mem.write_u8(0x4023, 0x01);
+        mem.write_u8(0x4020, 0x10);
+        mem.write_u8(0x4021, 0x00);
+        mem.write_u8(0x4022, 0x02);
+        mem.mapper.step(16);
+        assert!(!mem.mapper.is_irq_pending());
+        mem.mapper.step(1);
+        assert!(mem.mapper.is_irq_pending());
+        // Peeking leaves the IRQ pending.
+        assert_eq!(mem.peek_u8(0x4030) & 0x01, 0x01);
+        assert!(mem.mapper.is_irq_pending());
+        assert_eq!(mem.read_u8(0x4030) & 0x01, 0x01);
+        assert!(!mem.mapper.is_irq_pending());
+
+        // Without the repeat flag the timer stops after one IRQ.
+        mem.mapper.step(100);
+        assert!(!mem.mapper.is_irq_pending());
+
+        mem.write_u8(0x4022, 0x03);
+        mem.mapper.step(17);
+        mem.read_u8(0x4030);
+        mem.mapper.step(17);
+        assert!(mem.mapper.is_irq_pending());
+
+        // Disabling disk I/O stops the timer.
+        mem.write_u8(0x4023, 0x00);
+        assert!(!mem.mapper.is_irq_pending());
+        mem.mapper.step(100);
+        assert!(!mem.mapper.is_irq_pending());
+    }
+
+    # [test]
+    fn test_read_disk() {
+        let mut mem = Memory::new(Box::new(Fds::new(&make_rom())));
+        assert_eq!(mem.read_u8(0x4032) & 0x07, 0x02);
+        mem.write_u8(0x4023, 0x01);
+        // Motor on, read mode, start the transfer and raise an IRQ for every byte.
+        mem.write_u8(0x4025, 0xe5);
+
+        let mut data = vec![];
+        for _ in 0..16 {
+            step_until(&mut mem, |mem| mem.mapper.is_irq_pending());
+            data.push(mem.read_u8(0x4031));
+        }
+        assert_eq!(data, make_side(0x11)[..16]);
+        assert_eq!(mem.read_u8(0x4032) & 0x07, 0x00);
+    }
+
+    # [test]
+    fn test_write_disk() {
+        let mut mem = Memory::new(Box::new(Fds::new(&make_rom())));
+        mem.write_u8(0x4023, 0x01);
+
+        // Rewrite the gap and the disk info block of the second side with a new fill byte.
+        let mut block = b"\x01*NINTENDO-HVC*".to_vec();
+        block.resize(56, 0x33);
+        let mut bytes = vec![0u8; LEAD_IN_SIZE];
+        bytes.push(0x80);
+        bytes.extend(&block);
+
+        mem.mapper.disk_drive_mut().unwrap().insert_side(Some(1));
+        mem.write_u8(0x4024, bytes[0]);
+        // Motor on, write mode and start the transfer.
+        mem.write_u8(0x4025, 0x61);
+        for &data in &bytes[1..] {
+            step_until(&mut mem, |mem| (mem.read_u8(0x4030) & 0x02) == 0x02);
+            mem.write_u8(0x4024, data);
+        }
+        step_until(&mut mem, |mem| (mem.read_u8(0x4030) & 0x02) == 0x02);
+        mem.write_u8(0x4025, 0x20);
+
+        let sides = mem.mapper.disk_drive().unwrap().sides();
+        let mut expected = make_side(0x22);
+        expected[..56].copy_from_slice(&block);
+        assert_eq!(sides[0], make_side(0x11));
+        assert_eq!(sides[1], expected);
+    }
+
+    # [test]
+    fn test_insert_side() {
+        let mut mem = Memory::new(Box::new(Fds::new(&make_rom())));
+        let drive = mem.mapper.disk_drive_mut().unwrap();
+        assert_eq!(drive.side_count(), 2);
+        assert_eq!(drive.inserted_side(), Some(0));
+        drive.insert_side(None);
+        assert_eq!(drive.inserted_side(), None);
+        drive.insert_side(Some(2));
+        assert_eq!(drive.inserted_side(), None);
+        assert_eq!(mem.read_u8(0x4032) & 0x07, 0x07);
+    }
+}
@@ -0,0 +1,260 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Mapper 69: Sunsoft FME-7 and the 5A/5B variants.
+/// The 5B audio registers at $C000-$FFFF are left to the APU.
+#[derive(Clone)]
+pub struct Fme7 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    /// Command 8: bits 0-5 select the bank at $6000, bit 6 selects RAM and bit 7 enables it.
+    prg_ram_bank: u8,
+    prg_banks: [u8; 3],
+    mirroring: u8,
+
+    irq_control: u8,
+    irq_counter: u16,
+    is_irq_pending: bool,
+}
+
+impl Fme7 {
+    pub fn new(rom: &Rom) -> Self {
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr,
+            is_chr_ram,
+            command: 0,
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            prg_ram_bank: 0,
+            prg_banks: [0, 1, 2],
+            mirroring: 0,
+            irq_control: 0,
+            irq_counter: 0,
+            is_irq_pending: false,
+        }
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match address {
+            0x6000..=0x7fff => usize::from(self.prg_ram_bank & 0x3f),
+            0x8000..=0xdfff => usize::from(self.prg_banks[usize::from((address - 0x8000) >> 13)]),
+            _ => banks.saturating_sub(1),
+        };
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn prg_ram_index(&self, address: u16) -> usize {
+        let bank = usize::from(self.prg_ram_bank & 0x3f);
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (bank * PRG_BANK_SIZE + offset) % self.prg_ram.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = usize::from(self.chr_banks[usize::from(address >> 10) & 0x07]);
+        let offset = usize::from(address) & (CHR_BANK_SIZE - 1);
+        (bank * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+
+    fn is_prg_ram_selected(&self) -> bool {
+        (self.prg_ram_bank & 0x40) == 0x40
+    }
+
+    fn is_prg_ram_enabled(&self) -> bool {
+        (self.prg_ram_bank & 0xc0) == 0xc0 && !self.prg_ram.is_empty()
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            command @ 0x00..=0x07 => self.chr_banks[usize::from(command)] = data,
+            0x08 => self.prg_ram_bank = data,
+            command @ 0x09..=0x0b => self.prg_banks[usize::from(command - 0x09)] = data & 0x3f,
+            0x0c => self.mirroring = data & 0x03,
+            0x0d => {
+                self.irq_control = data;
+                self.is_irq_pending = false;
+            }
+            0x0e => self.irq_counter = (self.irq_counter & 0xff00) | u16::from(data),
+            _ => self.irq_counter = (self.irq_counter & 0x00ff) | (u16::from(data) << 8),
+        }
+    }
+}
+
+impl Mapper for Fme7 {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.is_prg_ram_enabled() => Some(self.prg_ram[self.prg_ram_index(address)]),
+            // RAM that is selected but disabled leaves the bus open.
+            0x6000..=0x7fff if self.is_prg_ram_selected() => None,
+            0x6000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7fff if self.is_prg_ram_enabled() => {
+                let index = self.prg_ram_index(address);
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0x9fff => self.command = data & 0x0f,
+            0xa000..=0xbfff => self.write_parameter(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn step(&mut self, cpu_cycles: usize) {
+        // Bit 7 of the control register counts and bit 0 raises the IRQ when the counter wraps.
+        if (self.irq_control & 0x80) == 0 {
+            return;
+        }
+        let cycles = (cpu_cycles % 0x10000) as u16;
+        let (counter, is_wrapped) = self.irq_counter.overflowing_sub(cycles);
+        self.irq_counter = counter;
+        if (is_wrapped || cpu_cycles >= 0x10000) && (self.irq_control & 0x01) == 0x01 {
+            self.is_irq_pending = true;
+        }
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.is_irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::fme7::Fme7;
+
+    fn write_command(mem: &mut Memory, command: u8, parameter: u8) {
+        mem.write_u8(0x8000, command);
+        mem.write_u8(0xa000, parameter);
+    }
+
+    # [test]
+    fn test_prg_banking() {
+        let mut mem = Memory::new(Box::new(Fme7::new(&make_rom(69, 0, 0x2000, 16, 0x0400, 64, 9))));
+        write_command(&mut mem, 0x09, 0x03);
+        write_command(&mut mem, 0x0a, 0x04);
+        write_command(&mut mem, 0x0b, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 3);
+        assert_eq!(mem.read_u8(0xa000), 4);
+        assert_eq!(mem.read_u8(0xc000), 5);
+        assert_eq!(mem.read_u8(0xe000), 15);
+
+        // ROM at $6000.
+        write_command(&mut mem, 0x08, 0x07);
+        assert_eq!(mem.read_u8(0x6000), 7);
+    }
+
+    # [test]
+    fn test_prg_ram_banking() {
+        let mut mem = Memory::new(Box::new(Fme7::new(&make_rom(69, 0, 0x2000, 16, 0x0400, 64, 9))));
+        write_command(&mut mem, 0x08, 0xc0);
+        mem.write_u8(0x6000, 0x11);
+        write_command(&mut mem, 0x08, 0xc3);
+        mem.write_u8(0x6000, 0x33);
+        assert_eq!(mem.read_u8(0x6000), 0x33);
+        write_command(&mut mem, 0x08, 0xc0);
+        assert_eq!(mem.read_u8(0x6000), 0x11);
+
+        // Selected but disabled RAM ignores writes and leaves the bus open.
+        write_command(&mut mem, 0x08, 0x40);
+        mem.write_u8(0x6000, 0x22);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x6000), None);
+        write_command(&mut mem, 0x08, 0xc0);
+        assert_eq!(mem.read_u8(0x6000), 0x11);
+        assert_eq!(mem.mapper.prg_ram().len(), 0x8000);
+    }
+
+    # [test]
+    fn test_chr_banking_and_mirroring() {
+        let mut mem = Memory::new(Box::new(Fme7::new(&make_rom(69, 0, 0x2000, 16, 0x0400, 64, 9))));
+        write_command(&mut mem, 0x00, 0x10);
+        write_command(&mut mem, 0x07, 0x17);
+        assert_eq!(mem.read_video_u8(0x0000), 0x10);
+        assert_eq!(mem.read_video_u8(0x1c00), 0x17);
+
+        for (value, mirroring) in [
+            (0, Mirroring::Vertical),
+            (1, Mirroring::Horizontal),
+            (2, Mirroring::SingleScreenLower),
+            (3, Mirroring::SingleScreenUpper),
+        ] {
+            write_command(&mut mem, 0x0c, value);
+            assert_eq!(mem.mapper.mirroring(), mirroring);
+        }
+    }
+
+    # [test]
+    fn test_irq() {
+        let mut mem = Memory::new(Box::new(Fme7::new(&make_rom(69, 0, 0x2000, 16, 0x0400, 64, 9))));
+        write_command(&mut mem, 0x0e, 0x10);
+        write_command(&mut mem, 0x0f, 0x00);
+        write_command(&mut mem, 0x0d, 0x81);
+        mem.mapper.step(16);
+        assert!(!mem.mapper.is_irq_pending());
+        mem.mapper.step(1);
+        assert!(mem.mapper.is_irq_pending());
+
+        // Writing the control register acknowledges the IRQ, the counter keeps running from $FFFF.
+        write_command(&mut mem, 0x0d, 0x81);
+        assert!(!mem.mapper.is_irq_pending());
+        mem.mapper.step(0xffff);
+        assert!(!mem.mapper.is_irq_pending());
+        mem.mapper.step(1);
+        assert!(mem.mapper.is_irq_pending());
+
+        // With counting disabled the counter holds.
+        write_command(&mut mem, 0x0d, 0x01);
+        write_command(&mut mem, 0x0e, 0x00);
+        write_command(&mut mem, 0x0f, 0x00);
+        mem.mapper.step(10);
+        assert!(!mem.mapper.is_irq_pending());
+    }
+}
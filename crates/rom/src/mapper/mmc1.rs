@@ -0,0 +1,400 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+const PRG_RAM_BANK_SIZE: usize = 0x2000;
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+/// MMC1 boards that reuse the CHR bank lines for PRG-RAM or PRG-ROM banking.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Board {
+    /// SxROM boards without extra banking lines (SAROM, SKROM, SLROM, ...).
+    Standard,
+    /// SNROM: CHR A16 disables the PRG-RAM.
+    Snrom,
+    /// SOROM: CHR A15 selects one of two 8KB PRG-RAM banks.
+    Sorom,
+    /// SUROM: CHR A16 selects the 256KB PRG-ROM outer bank.
+    Surom,
+    /// SXROM: CHR A16 selects the PRG-ROM outer bank, CHR A14-A15 the PRG-RAM bank.
+    Sxrom,
+}
+
+impl Board {
+    fn from(rom: &Rom, prg_ram_bytes: usize, is_chr_ram: bool) -> Board {
+        if rom.prg_rom.len() > PRG_OUTER_BANK_SIZE {
+            if prg_ram_bytes >= 0x8000 { Board::Sxrom } else { Board::Surom }
+        } else if prg_ram_bytes >= 0x4000 {
+            Board::Sorom
+        } else if is_chr_ram {
+            Board::Snrom
+        } else {
+            Board::Standard
+        }
+    }
+}
+
+/// Mapper 1: Nintendo MMC1 (SxROM). Mapper 155 is the MMC1A, whose PRG-RAM cannot be disabled.
+#[derive(Clone)]
+pub struct Mmc1 {
+    board: Board,
+    is_mmc1a: bool,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+
+    shift_register: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+
+    cpu_cycles: usize,
+    last_write_cycle: Option<usize>,
+}
+
+impl Mmc1 {
+    pub fn new(rom: &Rom) -> Self {
+        let (chr, is_chr_ram) = chr_memory(rom);
+        let prg_ram = prg_ram(rom);
+        Self {
+            board: Board::from(rom, prg_ram.len(), is_chr_ram),
+            is_mmc1a: rom.mapper == 155,
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram,
+            chr,
+            is_chr_ram,
+            shift_register: 0,
+            shift_count: 0,
+            // PRG mode 3 at power-on, so that the reset vector is in the fixed last bank.
+            control: 0x0c,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+            cpu_cycles: 0,
+            last_write_cycle: None,
+        }
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    fn write_register(&mut self, address: u16, data: u8) {
+        match address {
+            0x8000..=0x9fff => self.control = data,
+            0xa000..=0xbfff => self.chr_bank_0 = data,
+            0xc000..=0xdfff => self.chr_bank_1 = data,
+            _               => self.prg_bank = data,
+        }
+    }
+
+    fn is_prg_ram_enabled(&self) -> bool {
+        if self.prg_ram.is_empty() {
+            return false;
+        }
+        if !self.is_mmc1a && (self.prg_bank & 0x10) == 0x10 {
+            return false;
+        }
+        !(self.board == Board::Snrom && (self.chr_bank_0 & 0x10) == 0x10)
+    }
+
+    fn prg_ram_index(&self, address: u16) -> usize {
+        let bank = match self.board {
+            Board::Sorom => usize::from((self.chr_bank_0 >> 3) & 0x01),
+            Board::Sxrom => usize::from((self.chr_bank_0 >> 2) & 0x03),
+            _ => 0,
+        };
+        (bank * PRG_RAM_BANK_SIZE + usize::from(address - 0x6000)) % self.prg_ram.len()
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let outer = match self.board {
+            Board::Surom | Board::Sxrom => usize::from((self.chr_bank_0 >> 4) & 0x01) * PRG_OUTER_BANK_SIZE,
+            _ => 0,
+        };
+        let inner_banks = self.prg_rom.len().min(PRG_OUTER_BANK_SIZE) / PRG_BANK_SIZE;
+        let last = inner_banks.saturating_sub(1);
+        let bank = usize::from(self.prg_bank & 0x0f);
+        let bank = match ((self.control >> 2) & 0x03, address) {
+            (0 | 1, 0x8000..=0xbfff) => bank & !0x01,
+            (0 | 1, _)               => bank | 0x01,
+            (2, 0x8000..=0xbfff)     => 0,
+            (2, _)                   => bank,
+            (_, 0x8000..=0xbfff)     => bank,
+            (_, _)                   => last,
+        };
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (outer + bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = if (self.control & 0x10) == 0x10 {
+            if address < 0x1000 { self.chr_bank_0 } else { self.chr_bank_1 }
+        } else {
+            (self.chr_bank_0 & !0x01) | ((address >> 12) as u8 & 0x01)
+        };
+        let offset = usize::from(address) & (CHR_BANK_SIZE - 1);
+        (usize::from(bank & 0x1f) * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.is_prg_ram_enabled() => Some(self.prg_ram[self.prg_ram_index(address)]),
+            0x8000..=0xffff if !self.prg_rom.is_empty()  => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        match address {
+            0x6000..=0x7fff if self.is_prg_ram_enabled() => {
+                let index = self.prg_ram_index(address);
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xffff => {
+                // The serial port ignores a write on the cycle right after another one,
+                // such as the dummy write of read-modify-write instructions.
+                let is_consecutive = self.last_write_cycle == Some(self.cpu_cycles);
+                self.last_write_cycle = Some(self.cpu_cycles);
+                if is_consecutive {
+                    return;
+                }
+
+                if (data & 0x80) == 0x80 {
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                    self.control |= 0x0c;
+                    return;
+                }
+
+                self.shift_register |= (data & 0x01) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    self.write_register(address, self.shift_register);
+                    self.shift_register = 0;
+                    self.shift_count = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn step(&mut self, cpu_cycles: usize) {
+        self.cpu_cycles = self.cpu_cycles.wrapping_add(cpu_cycles);
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::mmc1::{Board, Mmc1};
+
+    fn write_serial(mem: &mut Memory, address: u16, value: u8) {
+        for bit in 0..5 {
+            mem.write_u8(address, (value >> bit) & 0x01);
+            mem.mapper.step(2);
+        }
+    }
+
+    # [test]
+    fn test_power_on_state() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 4, 7))));
+        assert_eq!(mem.read_u8(0x8000), 0);
+        assert_eq!(mem.read_u8(0xc000), 7);
+    }
+
+    # [test]
+    fn test_shift_register_and_reset() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 4, 7))));
+
+        // Three bits are discarded by the reset bit.
+        for _ in 0..3 {
+            mem.write_u8(0xe000, 0x01);
+            mem.mapper.step(2);
+        }
+        mem.write_u8(0x8000, 0x80);
+        mem.mapper.step(2);
+
+        write_serial(&mut mem, 0xe000, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 5);
+        assert_eq!(mem.read_u8(0xc000), 7);
+    }
+
+    # [test]
+    fn test_ignore_consecutive_writes() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 4, 7))));
+
+        // The second write in the same cycle window is dropped, so five more bits are needed.
+        mem.write_u8(0xe000, 0x01);
+        mem.write_u8(0xe000, 0x00);
+        mem.mapper.step(2);
+        for bit in [0x00, 0x01, 0x00, 0x00] {
+            mem.write_u8(0xe000, bit);
+            mem.mapper.step(2);
+        }
+        assert_eq!(mem.read_u8(0x8000), 5);
+    }
+
+    # [test]
+    fn test_prg_banking_modes() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 4, 7))));
+
+        // Mode 0/1: 32KB switching ignores the low bit.
+        write_serial(&mut mem, 0x8000, 0x00);
+        write_serial(&mut mem, 0xe000, 0x03);
+        assert_eq!(mem.read_u8(0x8000), 2);
+        assert_eq!(mem.read_u8(0xc000), 3);
+
+        // Mode 2: first bank fixed at $8000.
+        write_serial(&mut mem, 0x8000, 0x08);
+        write_serial(&mut mem, 0xe000, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 0);
+        assert_eq!(mem.read_u8(0xc000), 5);
+
+        // Mode 3: last bank fixed at $C000.
+        write_serial(&mut mem, 0x8000, 0x0c);
+        write_serial(&mut mem, 0xe000, 0x04);
+        assert_eq!(mem.read_u8(0x8000), 4);
+        assert_eq!(mem.read_u8(0xc000), 7);
+    }
+
+    # [test]
+    fn test_chr_banking_modes() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 2, 0x1000, 8, 7))));
+
+        // 8KB mode ignores the low bit of CHR0.
+        write_serial(&mut mem, 0x8000, 0x0c);
+        write_serial(&mut mem, 0xa000, 0x05);
+        assert_eq!(mem.read_video_u8(0x0000), 4);
+        assert_eq!(mem.read_video_u8(0x1000), 5);
+
+        // 4KB mode.
+        write_serial(&mut mem, 0x8000, 0x1c);
+        write_serial(&mut mem, 0xa000, 0x03);
+        write_serial(&mut mem, 0xc000, 0x06);
+        assert_eq!(mem.read_video_u8(0x0000), 3);
+        assert_eq!(mem.read_video_u8(0x1000), 6);
+    }
+
+    # [test]
+    fn test_mirroring() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 2, 0x1000, 4, 7))));
+        for (control, mirroring) in [
+            (0x0c, Mirroring::SingleScreenLower),
+            (0x0d, Mirroring::SingleScreenUpper),
+            (0x0e, Mirroring::Vertical),
+            (0x0f, Mirroring::Horizontal),
+        ] {
+            write_serial(&mut mem, 0x8000, control);
+            assert_eq!(mem.mapper.mirroring(), mirroring);
+        }
+    }
+
+    # [test]
+    fn test_prg_ram_enable() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 2, 0x1000, 4, 7))));
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+
+        write_serial(&mut mem, 0xe000, 0x10);
+        mem.write_u8(0x6000, 0x34);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x6000), None);
+
+        write_serial(&mut mem, 0xe000, 0x00);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+    }
+
+    # [test]
+    fn test_board_detection() {
+        assert_eq!(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 4, 7)).board(), Board::Standard);
+        assert_eq!(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 0, 7)).board(), Board::Snrom);
+        assert_eq!(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 4, 8)).board(), Board::Sorom);
+        assert_eq!(Mmc1::new(&make_rom(1, 0, 0x4000, 32, 0x1000, 0, 7)).board(), Board::Surom);
+        assert_eq!(Mmc1::new(&make_rom(1, 0, 0x4000, 32, 0x1000, 0, 9)).board(), Board::Sxrom);
+    }
+
+    # [test]
+    fn test_snrom_prg_ram_disable() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 0, 7))));
+        mem.write_u8(0x6000, 0x12);
+        write_serial(&mut mem, 0xa000, 0x10);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x6000), None);
+        write_serial(&mut mem, 0xa000, 0x00);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+    }
+
+    # [test]
+    fn test_sorom_prg_ram_banking() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 8, 0x1000, 4, 8))));
+        mem.write_u8(0x6000, 0x12);
+        write_serial(&mut mem, 0xa000, 0x08);
+        assert_eq!(mem.read_u8(0x6000), 0x00);
+        mem.write_u8(0x6000, 0x34);
+        write_serial(&mut mem, 0xa000, 0x00);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+    }
+
+    # [test]
+    fn test_surom_outer_bank() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 32, 0x1000, 0, 7))));
+        assert_eq!(mem.read_u8(0xc000), 15);
+
+        write_serial(&mut mem, 0xa000, 0x10);
+        assert_eq!(mem.read_u8(0x8000), 16);
+        assert_eq!(mem.read_u8(0xc000), 31);
+    }
+
+    # [test]
+    fn test_sxrom_prg_ram_banking() {
+        let mut mem = Memory::new(Box::new(Mmc1::new(&make_rom(1, 0, 0x4000, 32, 0x1000, 0, 9))));
+        for bank in 0..4u8 {
+            write_serial(&mut mem, 0xa000, bank << 2);
+            mem.write_u8(0x6000, bank + 1);
+        }
+        for bank in 0..4u8 {
+            write_serial(&mut mem, 0xa000, bank << 2);
+            assert_eq!(mem.read_u8(0x6000), bank + 1);
+        }
+    }
+}
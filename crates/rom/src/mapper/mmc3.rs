@@ -0,0 +1,382 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const MMC6_PRG_RAM_SIZE: usize = 0x0400;
+
+/// Pattern fetches A12 has to stay low for before a rising edge clocks the counter.
+/// The PPU reports two fetches per tile, so this is roughly the 3 M2 cycles of the real filter.
+const A12_LOW_FETCHES_TO_CLOCK: usize = 6;
+
+/// Mapper 4: Nintendo MMC3 (TxROM) and MMC6 (HKROM, submapper 1).
+/// Submapper 4 selects the MMC3A counter, which does not fire when reloaded with 0.
+#[derive(Clone)]
+pub struct Mmc3 {
+    is_mmc6: bool,
+    is_old_irq: bool,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+    four_screen: bool,
+
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    a12_low_fetches: usize,
+}
+
+impl Mmc3 {
+    pub fn new(rom: &Rom) -> Self {
+        let is_mmc6 = rom.submapper == 1;
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            is_mmc6,
+            is_old_irq: rom.submapper == 4,
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: if is_mmc6 { vec![0; MMC6_PRG_RAM_SIZE] } else { prg_ram(rom) },
+            chr,
+            is_chr_ram,
+            four_screen: rom.mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: if rom.mirroring == Mirroring::Horizontal { Mirroring::Horizontal } else { Mirroring::Vertical },
+            prg_ram_protect: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_low_fetches: A12_LOW_FETCHES_TO_CLOCK,
+        }
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let second_last = banks.saturating_sub(2);
+        let is_swapped = (self.bank_select & 0x40) == 0x40;
+        let bank = match (address >> 13) & 0x03 {
+            0 => if is_swapped { second_last } else { usize::from(self.banks[6]) },
+            1 => usize::from(self.banks[7]),
+            2 => if is_swapped { usize::from(self.banks[6]) } else { second_last },
+            _ => banks.saturating_sub(1),
+        };
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let address = if (self.bank_select & 0x80) == 0x80 { address ^ 0x1000 } else { address };
+        let slot = usize::from(address >> 10) & 0x07;
+        let bank = match slot {
+            0 => usize::from(self.banks[0] & !0x01),
+            1 => usize::from(self.banks[0] | 0x01),
+            2 => usize::from(self.banks[1] & !0x01),
+            3 => usize::from(self.banks[1] | 0x01),
+            _ => usize::from(self.banks[slot - 2]),
+        };
+        let offset = usize::from(address) & (CHR_BANK_SIZE - 1);
+        (bank * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+
+    fn prg_ram_index(&self, address: u16) -> Option<(usize, bool)> {
+        if self.prg_ram.is_empty() {
+            return None;
+        }
+
+        if self.is_mmc6 {
+            // $7000-$7FFF mirrors the 1KB RAM, whose two halves are protected separately.
+            if address < 0x7000 || (self.bank_select & 0x20) == 0 {
+                return None;
+            }
+            let index = usize::from(address) & (MMC6_PRG_RAM_SIZE - 1);
+            let (read, write) = if index < 0x200 { (0x20, 0x10) } else { (0x80, 0x40) };
+            if (self.prg_ram_protect & (read | write)) == 0 {
+                return None;
+            }
+            let is_readable = (self.prg_ram_protect & read) == read;
+            let is_writable = is_readable && (self.prg_ram_protect & write) == write;
+            return if is_readable { Some((index, is_writable)) } else { None };
+        }
+
+        if (self.prg_ram_protect & 0x80) == 0 {
+            return None;
+        }
+        let index = usize::from(address - 0x6000) % self.prg_ram.len();
+        Some((index, (self.prg_ram_protect & 0x40) == 0))
+    }
+
+    fn observe_ppu_address(&mut self, address: u16) {
+        if (address & 0x1000) == 0 {
+            self.a12_low_fetches += 1;
+            return;
+        }
+
+        if self.a12_low_fetches >= A12_LOW_FETCHES_TO_CLOCK {
+            self.clock_irq_counter();
+        }
+        self.a12_low_fetches = 0;
+    }
+
+    fn clock_irq_counter(&mut self) {
+        let previous = self.irq_counter;
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        let is_fired = if self.is_old_irq {
+            self.irq_counter == 0 && (previous != 0 || self.irq_reload)
+        } else {
+            self.irq_counter == 0
+        };
+        self.irq_reload = false;
+        if is_fired && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff => self.prg_ram_index(address).map(|(index, _)| self.prg_ram[index]),
+            0x8000..=0xffff if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        let is_even = (address & 0x01) == 0;
+        match address {
+            0x6000..=0x7fff => {
+                if let Some((index, true)) = self.prg_ram_index(address) {
+                    self.prg_ram[index] = data;
+                }
+            }
+            0x8000..=0x9fff if is_even => self.bank_select = data,
+            0x8000..=0x9fff => self.banks[usize::from(self.bank_select & 0x07)] = data,
+            0xa000..=0xbfff if is_even => {
+                self.mirroring = if (data & 0x01) == 0x01 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+            0xa000..=0xbfff => self.prg_ram_protect = data,
+            0xc000..=0xdfff if is_even => self.irq_latch = data,
+            0xc000..=0xdfff => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xe000..=0xffff if is_even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xe000..=0xffff => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.observe_ppu_address(address);
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        self.observe_ppu_address(address);
+        if self.is_chr_ram {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.four_screen { Mirroring::FourScreen } else { self.mirroring }
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::mmc3::Mmc3;
+
+    // Emulates the pattern fetches of one rendered line with the background at $0000 and sprites at $1000.
+    fn render_line(mem: &mut Memory) {
+        for _ in 0..64 {
+            mem.read_video_u8(0x0000);
+        }
+        for _ in 0..16 {
+            mem.read_video_u8(0x1000);
+        }
+        for _ in 0..4 {
+            mem.read_video_u8(0x0000);
+        }
+    }
+
+    # [test]
+    fn test_prg_banking() {
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0x8000, 0x06);
+        mem.write_u8(0x8001, 0x03);
+        mem.write_u8(0x8000, 0x07);
+        mem.write_u8(0x8001, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 3);
+        assert_eq!(mem.read_u8(0xa000), 5);
+        assert_eq!(mem.read_u8(0xc000), 14);
+        assert_eq!(mem.read_u8(0xe000), 15);
+
+        // PRG mode 1 swaps $8000 and $C000.
+        mem.write_u8(0x8000, 0x46);
+        assert_eq!(mem.read_u8(0x8000), 14);
+        assert_eq!(mem.read_u8(0xa000), 5);
+        assert_eq!(mem.read_u8(0xc000), 3);
+        assert_eq!(mem.read_u8(0xe000), 15);
+    }
+
+    # [test]
+    fn test_chr_banking() {
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 0, 0x2000, 4, 0x0400, 64, 7))));
+        for (register, bank) in [(0, 0x09), (1, 0x0c), (2, 0x20), (3, 0x21), (4, 0x22), (5, 0x23)] {
+            mem.write_u8(0x8000, register);
+            mem.write_u8(0x8001, bank);
+        }
+        assert_eq!(mem.read_video_u8(0x0000), 0x08);
+        assert_eq!(mem.read_video_u8(0x0400), 0x09);
+        assert_eq!(mem.read_video_u8(0x0800), 0x0c);
+        assert_eq!(mem.read_video_u8(0x0c00), 0x0d);
+        assert_eq!(mem.read_video_u8(0x1000), 0x20);
+        assert_eq!(mem.read_video_u8(0x1c00), 0x23);
+
+        // CHR inversion swaps the 2KB and 1KB halves.
+        mem.write_u8(0x8000, 0x80);
+        assert_eq!(mem.read_video_u8(0x0000), 0x20);
+        assert_eq!(mem.read_video_u8(0x0c00), 0x23);
+        assert_eq!(mem.read_video_u8(0x1000), 0x08);
+        assert_eq!(mem.read_video_u8(0x1c00), 0x0d);
+    }
+
+    # [test]
+    fn test_mirroring() {
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 0, 0x2000, 4, 0x0400, 8, 7))));
+        mem.write_u8(0xa000, 0x01);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Horizontal);
+        mem.write_u8(0xa000, 0x00);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    # [test]
+    fn test_prg_ram_protect() {
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 0, 0x2000, 4, 0x0400, 8, 7))));
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x6000), None);
+
+        mem.write_u8(0xa001, 0x80);
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+
+        mem.write_u8(0xa001, 0xc0);
+        mem.write_u8(0x6000, 0x34);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+    }
+
+    # [test]
+    fn test_mmc6_prg_ram() {
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 1, 0x2000, 4, 0x0400, 8, 7))));
+        mem.write_u8(0x8000, 0x20);
+        mem.write_u8(0xa001, 0xf0);
+        mem.write_u8(0x7000, 0x12);
+        mem.write_u8(0x7200, 0x34);
+        assert_eq!(mem.read_u8(0x7400), 0x12);
+        assert_eq!(mem.read_u8(0x7600), 0x34);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x6000), None);
+
+        // Only the low half remains readable and nothing is writable.
+        mem.write_u8(0xa001, 0x20);
+        mem.write_u8(0x7000, 0x56);
+        assert_eq!(mem.read_u8(0x7000), 0x12);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x7200), None);
+    }
+
+    # [test]
+    fn test_scanline_irq() {
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 0, 0x2000, 4, 0x0400, 8, 7))));
+        mem.write_u8(0xc000, 0x02);
+        mem.write_u8(0xc001, 0x00);
+        mem.write_u8(0xe001, 0x00);
+
+        render_line(&mut mem);
+        render_line(&mut mem);
+        assert!(!mem.mapper.is_irq_pending());
+        render_line(&mut mem);
+        assert!(mem.mapper.is_irq_pending());
+
+        mem.write_u8(0xe000, 0x00);
+        assert!(!mem.mapper.is_irq_pending());
+    }
+
+    # [test]
+    fn test_a12_filter() {
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 0, 0x2000, 4, 0x0400, 8, 7))));
+        mem.write_u8(0xc000, 0x00);
+        mem.write_u8(0xc001, 0x00);
+        mem.write_u8(0xe001, 0x00);
+
+        // Quick A12 toggles, as with 8x16 sprites mixing both tables, are filtered out.
+        for _ in 0..8 {
+            mem.read_video_u8(0x0000);
+            mem.read_video_u8(0x1000);
+        }
+        assert!(mem.mapper.is_irq_pending());
+        mem.write_u8(0xe000, 0x00);
+        mem.write_u8(0xe001, 0x00);
+        for _ in 0..8 {
+            mem.read_video_u8(0x0000);
+            mem.read_video_u8(0x1000);
+        }
+        assert!(!mem.mapper.is_irq_pending());
+    }
+
+    # [test]
+    fn test_old_irq_behavior() {
+        // The MMC3A does not fire when the counter is reloaded with a latch of 0.
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 4, 0x2000, 4, 0x0400, 8, 7))));
+        mem.write_u8(0xc000, 0x00);
+        mem.write_u8(0xe001, 0x00);
+        render_line(&mut mem);
+        render_line(&mut mem);
+        assert!(!mem.mapper.is_irq_pending());
+
+        let mut mem = Memory::new(Box::new(Mmc3::new(&make_rom(4, 0, 0x2000, 4, 0x0400, 8, 7))));
+        mem.write_u8(0xc000, 0x00);
+        mem.write_u8(0xe001, 0x00);
+        render_line(&mut mem);
+        render_line(&mut mem);
+        assert!(mem.mapper.is_irq_pending());
+    }
+}
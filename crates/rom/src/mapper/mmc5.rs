@@ -0,0 +1,603 @@
+use memory::mapper::{FetchPhase, Mapper, Mirroring, RenderingState, NAME_TABLE_SIZE};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const EX_RAM_SIZE: usize = 0x0400;
+const ATTRIBUTE_TABLE_OFFSET: usize = 0x03c0;
+const SPLIT_PAGE_SIZE: usize = 0x1000;
+
+/// Mapper 5: Nintendo MMC5 (ExROM).
+#[derive(Clone)]
+pub struct Mmc5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+    ex_ram: Vec<u8>,
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    ex_ram_mode: u8,
+    name_table_mapping: u8,
+    fill_tile: u8,
+    fill_color: u8,
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    // $5120-$5127 (sprites in 8x16 mode) and $5128-$512B (background in 8x16 mode).
+    chr_banks_a: [usize; 8],
+    chr_banks_b: [usize; 4],
+    chr_upper: u8,
+    is_last_chr_write_b: bool,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_page: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    state: RenderingState,
+    last_name_table_address: Option<u16>,
+    name_table_repeats: u8,
+    tile_x: usize,
+    ex_attribute: Option<u8>,
+    split_y: Option<usize>,
+}
+
+impl Mmc5 {
+    pub fn new(rom: &Rom) -> Self {
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr,
+            is_chr_ram,
+            ex_ram: vec![0; EX_RAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0, 0],
+            ex_ram_mode: 0,
+            name_table_mapping: 0,
+            fill_tile: 0,
+            fill_color: 0,
+            prg_banks: [0, 0, 0, 0, 0xff],
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_upper: 0,
+            is_last_chr_write_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_page: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            state: RenderingState { line: 0, phase: FetchPhase::Idle, is_8x16_sprites: false },
+            last_name_table_address: None,
+            name_table_repeats: 0,
+            tile_x: 0,
+            ex_attribute: None,
+            split_y: None,
+        }
+    }
+
+    /// Returns whether the address maps to PRG-ROM and the index into PRG-ROM or PRG-RAM.
+    fn prg_index(&self, address: u16) -> (bool, usize) {
+        if address < 0x8000 {
+            let bank = usize::from(self.prg_banks[0] & 0x07);
+            return (false, bank * PRG_BANK_SIZE + usize::from(address & 0x1fff));
+        }
+
+        // (register, size in 8KB banks) for each mode and 8KB window.
+        let window = usize::from(address - 0x8000) / PRG_BANK_SIZE;
+        let (register, banks) = match (self.prg_mode, window) {
+            (0, _)     => (4, 4),
+            (1, 0 | 1) => (2, 2),
+            (1, _)     => (4, 2),
+            (2, 0 | 1) => (2, 2),
+            (2, 2)     => (3, 1),
+            (2, _)     => (4, 1),
+            (_, w)     => (w + 1, 1),
+        };
+        let value = self.prg_banks[register];
+        let is_rom = register == 4 || (value & 0x80) == 0x80;
+        let bank = (usize::from(value & 0x7f) & !(banks - 1)) + (window % banks);
+        let bank = if is_rom { bank } else { bank & 0x07 };
+        (is_rom, bank * PRG_BANK_SIZE + usize::from(address & 0x1fff))
+    }
+
+    fn is_prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    fn is_background_fetch(&self) -> bool {
+        matches!(self.state.phase, FetchPhase::Background | FetchPhase::Prefetch)
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        if let Some(y) = self.split_y {
+            if self.is_background_fetch() {
+                let offset = usize::from(address & 0x0ff8) | (y % 8);
+                return (usize::from(self.split_page) * SPLIT_PAGE_SIZE + offset) % self.chr.len();
+            }
+        }
+        if let (Some(ex), true) = (self.ex_attribute, self.is_background_fetch()) {
+            let bank = (usize::from(self.chr_upper & 0x03) << 6) | usize::from(ex & 0x3f);
+            return (bank * 0x1000 + usize::from(address & 0x0fff)) % self.chr.len();
+        }
+
+        let is_b = if self.state.is_8x16_sprites && self.state.phase != FetchPhase::Idle {
+            self.is_background_fetch()
+        } else {
+            self.is_last_chr_write_b
+        };
+        let address = usize::from(address);
+        let (bank, size) = match (self.chr_mode, is_b) {
+            (0, false) => (self.chr_banks_a[7], 0x2000),
+            (1, false) => (self.chr_banks_a[3 + (address / 0x1000) * 4], 0x1000),
+            (2, false) => (self.chr_banks_a[1 + (address / 0x0800) * 2], 0x0800),
+            (_, false) => (self.chr_banks_a[address / 0x0400], 0x0400),
+            (0, true)  => (self.chr_banks_b[3], 0x2000),
+            (1, true)  => (self.chr_banks_b[3], 0x1000),
+            (2, true)  => (self.chr_banks_b[1 + ((address & 0x0fff) / 0x0800) * 2], 0x0800),
+            (_, true)  => (self.chr_banks_b[(address & 0x0fff) / 0x0400], 0x0400),
+        };
+        (bank * size + (address & (size - 1))) % self.chr.len()
+    }
+
+    fn observe_name_table(&mut self, address: u16) {
+        if self.last_name_table_address == Some(address) {
+            self.name_table_repeats += 1;
+            // The PPU reads the same nametable address three times in a row only at the start of a line.
+            if self.name_table_repeats == 2 {
+                self.detect_scanline();
+            }
+        } else {
+            self.last_name_table_address = Some(address);
+            self.name_table_repeats = 0;
+        }
+    }
+
+    fn detect_scanline(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+    }
+
+    fn is_split_tile(&self) -> bool {
+        if (self.split_control & 0x80) == 0 || self.ex_ram_mode > 1 || !self.in_frame {
+            return false;
+        }
+        let count = usize::from(self.split_control & 0x1f);
+        let x = self.tile_x % 32;
+        if (self.split_control & 0x40) == 0x40 { x >= count } else { x < count }
+    }
+
+    fn read_mapped_name_table(&self, address: u16, vram: &[u8]) -> u8 {
+        let table = usize::from(address >> 10) & 0x03;
+        let offset = usize::from(address) & (NAME_TABLE_SIZE - 1);
+        match (self.name_table_mapping >> (table * 2)) & 0x03 {
+            0 => vram[offset],
+            1 => vram[NAME_TABLE_SIZE + offset],
+            2 => if self.ex_ram_mode <= 1 { self.ex_ram[offset] } else { 0 },
+            _ => if offset >= ATTRIBUTE_TABLE_OFFSET { self.fill_color * 0x55 } else { self.fill_tile },
+        }
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cpu_read_u8(&mut self, address: u16) -> Option<u8> {
+        let data = self.cpu_peek_u8(address);
+        // Reading the status acknowledges the IRQ.
+        if address == 0x5204 {
+            self.irq_pending = false;
+        }
+        data
+    }
+
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x5204 => Some((if self.irq_pending { 0x80 } else { 0x00 }) | (if self.in_frame { 0x40 } else { 0x00 })),
+            0x5205 => Some((u16::from(self.multiplicand) * u16::from(self.multiplier)) as u8),
+            0x5206 => Some(((u16::from(self.multiplicand) * u16::from(self.multiplier)) >> 8) as u8),
+            0x5c00..=0x5fff if self.ex_ram_mode >= 2 => Some(self.ex_ram[usize::from(address - 0x5c00)]),
+            0x6000..=0xffff => {
+                let (is_rom, index) = self.prg_index(address);
+                match (is_rom, self.prg_rom.is_empty(), self.prg_ram.is_empty()) {
+                    (true, false, _)  => Some(self.prg_rom[index % self.prg_rom.len()]),
+                    (false, _, false) => Some(self.prg_ram[index % self.prg_ram.len()]),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        match address {
+            0x5100 => self.prg_mode = data & 0x03,
+            0x5101 => self.chr_mode = data & 0x03,
+            0x5102 => self.prg_ram_protect[0] = data & 0x03,
+            0x5103 => self.prg_ram_protect[1] = data & 0x03,
+            0x5104 => self.ex_ram_mode = data & 0x03,
+            0x5105 => self.name_table_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_color = data & 0x03,
+            0x5113..=0x5117 => self.prg_banks[usize::from(address - 0x5113)] = data,
+            0x5120..=0x5127 => {
+                self.chr_banks_a[usize::from(address - 0x5120)] = usize::from(data) | (usize::from(self.chr_upper & 0x03) << 8);
+                self.is_last_chr_write_b = false;
+            }
+            0x5128..=0x512b => {
+                self.chr_banks_b[usize::from(address - 0x5128)] = usize::from(data) | (usize::from(self.chr_upper & 0x03) << 8);
+                self.is_last_chr_write_b = true;
+            }
+            0x5130 => self.chr_upper = data & 0x03,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_page = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = (data & 0x80) == 0x80,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5c00..=0x5fff => {
+                let index = usize::from(address - 0x5c00);
+                match self.ex_ram_mode {
+                    // Outside of rendering, writes in the nametable modes store 0.
+                    0 | 1 => self.ex_ram[index] = if self.in_frame { data } else { 0 },
+                    2 => self.ex_ram[index] = data,
+                    _ => {}
+                }
+            }
+            0x6000..=0xffff => {
+                let (is_rom, index) = self.prg_index(address);
+                if !is_rom && self.is_prg_ram_writable() && !self.prg_ram.is_empty() {
+                    let index = index % self.prg_ram.len();
+                    self.prg_ram[index] = data;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.last_name_table_address = None;
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.name_table_mapping {
+            0x00 => Mirroring::SingleScreenLower,
+            0x55 => Mirroring::SingleScreenUpper,
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            _    => Mirroring::FourScreen,
+        }
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn read_name_table_u8(&mut self, address: u16, vram: &[u8]) -> u8 {
+        self.observe_name_table(address);
+
+        let offset = usize::from(address) & (NAME_TABLE_SIZE - 1);
+        let is_attribute = offset >= ATTRIBUTE_TABLE_OFFSET;
+        if !self.is_background_fetch() || !self.in_frame {
+            return self.read_mapped_name_table(address, vram);
+        }
+
+        if !is_attribute {
+            // A new background tile starts with its nametable fetch.
+            self.split_y = None;
+            self.ex_attribute = None;
+            if self.is_split_tile() {
+                let line = usize::from(self.scanline) + if self.state.phase == FetchPhase::Prefetch { 1 } else { 0 };
+                self.split_y = Some((line + usize::from(self.split_scroll)) % 240);
+            } else if self.ex_ram_mode == 1 {
+                self.ex_attribute = Some(self.ex_ram[offset]);
+            }
+            self.tile_x += 1;
+        }
+
+        match (self.split_y, self.ex_attribute, is_attribute) {
+            (Some(y), _, false) => self.ex_ram[(y / 8) * 32 + (self.tile_x - 1) % 32],
+            (Some(y), _, true)  => self.ex_ram[ATTRIBUTE_TABLE_OFFSET + (y / 32) * 8 + ((self.tile_x - 1) % 32) / 4],
+            (None, Some(ex), true) => (ex >> 6) * 0x55,
+            _ => self.read_mapped_name_table(address, vram),
+        }
+    }
+
+    fn write_name_table_u8(&mut self, address: u16, data: u8, vram: &mut [u8]) {
+        let table = usize::from(address >> 10) & 0x03;
+        let offset = usize::from(address) & (NAME_TABLE_SIZE - 1);
+        match (self.name_table_mapping >> (table * 2)) & 0x03 {
+            0 => vram[offset] = data,
+            1 => vram[NAME_TABLE_SIZE + offset] = data,
+            2 if self.ex_ram_mode <= 1 => self.ex_ram[offset] = data,
+            _ => {}
+        }
+    }
+
+    fn set_rendering_state(&mut self, state: RenderingState) {
+        match state.phase {
+            FetchPhase::Idle => {
+                self.in_frame = false;
+                self.last_name_table_address = None;
+            }
+            FetchPhase::Background => self.tile_x = 2,
+            FetchPhase::Prefetch => self.tile_x = 0,
+            FetchPhase::Sprite => {}
+        }
+        self.split_y = None;
+        self.ex_attribute = None;
+        self.state = state;
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::{FetchPhase, Mirroring, RenderingState};
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::mmc5::Mmc5;
+
+    fn create() -> Memory {
+        Memory::new(Box::new(Mmc5::new(&make_rom(5, 0, 0x2000, 32, 0x0400, 256, 10))))
+    }
+
+    fn set_phase(mem: &mut Memory, phase: FetchPhase, is_8x16_sprites: bool) {
+        mem.set_rendering_state(RenderingState { line: 0, phase, is_8x16_sprites });
+    }
+
+    // Emulates the nametable reads the PPU makes around the boundary of two lines.
+    fn start_line(mem: &mut Memory) {
+        set_phase(mem, FetchPhase::Prefetch, false);
+        mem.read_video_u8(0x2000);
+        mem.read_video_u8(0x23c0);
+        mem.read_video_u8(0x0000);
+        mem.read_video_u8(0x2002);
+        mem.read_video_u8(0x2002);
+        set_phase(mem, FetchPhase::Background, false);
+        mem.read_video_u8(0x2002);
+    }
+
+    # [test]
+    fn test_prg_modes() {
+        let mut mem = create();
+        // Mode 3 at power-on with $5117 = $FF.
+        assert_eq!(mem.read_u8(0xe000), 31);
+
+        mem.write_u8(0x5100, 0x00);
+        mem.write_u8(0x5117, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 4);
+        assert_eq!(mem.read_u8(0xa000), 5);
+        assert_eq!(mem.read_u8(0xc000), 6);
+        assert_eq!(mem.read_u8(0xe000), 7);
+
+        mem.write_u8(0x5100, 0x01);
+        mem.write_u8(0x5115, 0x83);
+        assert_eq!(mem.read_u8(0x8000), 2);
+        assert_eq!(mem.read_u8(0xa000), 3);
+        assert_eq!(mem.read_u8(0xc000), 4);
+        assert_eq!(mem.read_u8(0xe000), 5);
+
+        mem.write_u8(0x5100, 0x02);
+        mem.write_u8(0x5116, 0x89);
+        assert_eq!(mem.read_u8(0x8000), 2);
+        assert_eq!(mem.read_u8(0xc000), 9);
+        assert_eq!(mem.read_u8(0xe000), 5);
+
+        mem.write_u8(0x5100, 0x03);
+        mem.write_u8(0x5114, 0x8a);
+        mem.write_u8(0x5115, 0x8b);
+        mem.write_u8(0x5116, 0x8c);
+        mem.write_u8(0x5117, 0x0d);
+        assert_eq!(mem.read_u8(0x8000), 10);
+        assert_eq!(mem.read_u8(0xa000), 11);
+        assert_eq!(mem.read_u8(0xc000), 12);
+        assert_eq!(mem.read_u8(0xe000), 13);
+    }
+
+    # [test]
+    fn test_prg_ram() {
+        let mut mem = create();
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.read_u8(0x6000), 0x00);
+
+        mem.write_u8(0x5102, 0x02);
+        mem.write_u8(0x5103, 0x01);
+        mem.write_u8(0x5113, 0x01);
+        mem.write_u8(0x6000, 0x12);
+        mem.write_u8(0x5113, 0x00);
+        assert_eq!(mem.read_u8(0x6000), 0x00);
+
+        // PRG-RAM bank 1 mapped at $8000 in mode 3.
+        mem.write_u8(0x5114, 0x01);
+        assert_eq!(mem.read_u8(0x8000), 0x12);
+        mem.write_u8(0x8001, 0x34);
+        mem.write_u8(0x5113, 0x01);
+        assert_eq!(mem.read_u8(0x6001), 0x34);
+    }
+
+    # [test]
+    fn test_chr_modes() {
+        let mut mem = create();
+        mem.write_u8(0x5101, 0x03);
+        for register in 0..8u16 {
+            mem.write_u8(0x5120 + register, 0x10 + register as u8);
+        }
+        assert_eq!(mem.read_video_u8(0x0000), 0x10);
+        assert_eq!(mem.read_video_u8(0x1c00), 0x17);
+
+        mem.write_u8(0x5101, 0x02);
+        assert_eq!(mem.read_video_u8(0x0000), 0x22);
+        assert_eq!(mem.read_video_u8(0x1800), 0x2e);
+
+        mem.write_u8(0x5101, 0x01);
+        assert_eq!(mem.read_video_u8(0x0000), 0x4c);
+        assert_eq!(mem.read_video_u8(0x1000), 0x5c);
+
+        mem.write_u8(0x5101, 0x00);
+        assert_eq!(mem.read_video_u8(0x0000), 0xb8);
+        assert_eq!(mem.read_video_u8(0x1c00), 0xbf);
+    }
+
+    # [test]
+    fn test_8x16_sprite_and_background_banks() {
+        let mut mem = create();
+        mem.write_u8(0x5101, 0x03);
+        mem.write_u8(0x5120, 0x01);
+        mem.write_u8(0x5128, 0x02);
+
+        // 8x8 sprites: the last written set is used everywhere.
+        set_phase(&mut mem, FetchPhase::Sprite, false);
+        assert_eq!(mem.read_video_u8(0x0000), 0x02);
+
+        // 8x16 sprites: set A for sprites, set B for the background.
+        set_phase(&mut mem, FetchPhase::Sprite, true);
+        assert_eq!(mem.read_video_u8(0x0000), 0x01);
+        set_phase(&mut mem, FetchPhase::Background, true);
+        assert_eq!(mem.read_video_u8(0x0000), 0x02);
+        assert_eq!(mem.read_video_u8(0x1000), 0x02);
+    }
+
+    # [test]
+    fn test_name_table_mapping_and_fill_mode() {
+        let mut mem = create();
+        // $2000: CIRAM 0, $2400: CIRAM 1, $2800: ExRAM, $2C00: fill.
+        mem.write_u8(0x5105, 0xe4);
+        mem.write_u8(0x5106, 0x12);
+        mem.write_u8(0x5107, 0x02);
+        mem.write_video_u8(0x2000, 0x01);
+        mem.write_video_u8(0x2400, 0x02);
+        mem.write_video_u8(0x2800, 0x03);
+        assert_eq!(mem.vram[0x0000], 0x01);
+        assert_eq!(mem.vram[0x0400], 0x02);
+        assert_eq!(mem.read_video_u8(0x2800), 0x03);
+        assert_eq!(mem.read_video_u8(0x2c00), 0x12);
+        assert_eq!(mem.read_video_u8(0x2fc0), 0xaa);
+
+        mem.write_u8(0x5105, 0x44);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    # [test]
+    fn test_ex_ram_cpu_access() {
+        let mut mem = create();
+        // Outside of rendering, mode 0 writes store 0 and reads are open.
+        mem.write_u8(0x5c00, 0x12);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x5c00), None);
+
+        mem.write_u8(0x5104, 0x02);
+        mem.write_u8(0x5c00, 0x12);
+        assert_eq!(mem.read_u8(0x5c00), 0x12);
+
+        mem.write_u8(0x5104, 0x03);
+        mem.write_u8(0x5c00, 0x34);
+        assert_eq!(mem.read_u8(0x5c00), 0x12);
+    }
+
+    # [test]
+    fn test_scanline_irq() {
+        let mut mem = create();
+        mem.write_u8(0x5203, 0x02);
+        mem.write_u8(0x5204, 0x80);
+
+        start_line(&mut mem);
+        assert_eq!(mem.read_u8(0x5204), 0x40);
+        start_line(&mut mem);
+        assert!(!mem.mapper.is_irq_pending());
+        start_line(&mut mem);
+        assert!(mem.mapper.is_irq_pending());
+        // Peeking leaves the IRQ pending.
+        assert_eq!(mem.peek_u8(0x5204), 0xc0);
+        assert!(mem.mapper.is_irq_pending());
+        assert_eq!(mem.read_u8(0x5204), 0xc0);
+        assert!(!mem.mapper.is_irq_pending());
+
+        // Leaving rendering clears the in-frame flag.
+        set_phase(&mut mem, FetchPhase::Idle, false);
+        assert_eq!(mem.read_u8(0x5204), 0x00);
+    }
+
+    # [test]
+    fn test_extended_attributes() {
+        let mut mem = create();
+        mem.write_u8(0x5104, 0x01);
+        mem.write_u8(0x5130, 0x01);
+        start_line(&mut mem);
+        mem.write_u8(0x5c03, 0xc5);
+
+        mem.read_video_u8(0x2003);
+        assert_eq!(mem.read_video_u8(0x23c0), 0xff);
+        // Bank (1 << 6) | 5 of 4KB is 1KB bank 0x114, which wraps to 0x14 in 256KB of CHR.
+        assert_eq!(mem.read_video_u8(0x0000), 0x14);
+    }
+
+    # [test]
+    fn test_vertical_split() {
+        let mut mem = create();
+        mem.write_u8(0x5104, 0x01);
+        mem.write_u8(0x5200, 0x84);
+        mem.write_u8(0x5201, 0x08);
+        mem.write_u8(0x5202, 0x03);
+        start_line(&mut mem);
+        mem.write_u8(0x5c23, 0x44);
+        mem.write_u8(0x5fc0, 0x55);
+
+        // Tile 3 is left of the split at tile 4 and is read from row 1 of ExRAM.
+        assert_eq!(mem.read_video_u8(0x2003), 0x44);
+        assert_eq!(mem.read_video_u8(0x23c0), 0x55);
+        assert_eq!(mem.read_video_u8(0x0440), 0x0d);
+
+        // Tile 4 is outside of the split.
+        mem.write_video_u8(0x2004, 0x66);
+        assert_eq!(mem.read_video_u8(0x2004), 0x66);
+    }
+
+    # [test]
+    fn test_multiplier() {
+        let mut mem = create();
+        mem.write_u8(0x5205, 0x12);
+        mem.write_u8(0x5206, 0x34);
+        assert_eq!(mem.read_u8(0x5205), 0xa8);
+        assert_eq!(mem.read_u8(0x5206), 0x03);
+    }
+}
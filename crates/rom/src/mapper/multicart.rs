@@ -0,0 +1,314 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::chr_memory;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Multicart boards that pick a game by latching PRG and CHR banks, mostly from the write address.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Board {
+    /// Mapper 15: 100-in-1 Contra Function 16, four PRG modes selected by the low address bits.
+    Contra100In1,
+    /// Mapper 225: 52-in-1 and 64-in-1, 16KB or 32KB PRG bank and 8KB CHR bank from the address.
+    Bmc64In1,
+    /// Mapper 227: 1200-in-1, NROM or UNROM-like PRG banking from the address.
+    Bmc1200In1,
+    /// Mapper 228: Action 52 and Cheetahmen II, PRG bank from the address and CHR bank from both.
+    Action52,
+}
+
+impl Board {
+    fn from(rom: &Rom) -> Board {
+        match rom.mapper {
+            15 => Board::Contra100In1,
+            225 => Board::Bmc64In1,
+            227 => Board::Bmc1200In1,
+            _ => Board::Action52,
+        }
+    }
+}
+
+/// Mappers 15, 225, 227 and 228.
+#[derive(Clone)]
+pub struct Multicart {
+    board: Board,
+
+    prg_rom: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+    /// Mapper 15 write-protects its CHR-RAM in the NROM-256 and NROM-128 modes.
+    is_chr_write_protected: bool,
+    mirroring: Mirroring,
+
+    /// 8KB banks at $8000, $A000, $C000 and $E000.
+    prg_banks: [usize; 4],
+    chr_bank: usize,
+    /// Four 4-bit registers that menus use to remember the last game, at $5800-$5FFF on mapper 225
+    /// and $4020-$5FFF on mapper 228.
+    nibble_ram: [u8; 4],
+}
+
+impl Multicart {
+    pub fn new(rom: &Rom) -> Self {
+        let (chr, is_chr_ram) = chr_memory(rom);
+        let mut mapper = Self {
+            board: Board::from(rom),
+            prg_rom: rom.prg_rom.clone(),
+            chr,
+            is_chr_ram,
+            is_chr_write_protected: false,
+            mirroring: rom.mirroring,
+            prg_banks: [0, 1, 2, 3],
+            chr_bank: 0,
+            nibble_ram: [0; 4],
+        };
+        // Every board starts on the menu, as if zero was written to $8000.
+        mapper.write_register(0x8000, 0x00);
+        mapper
+    }
+
+    pub fn board(&self) -> Board {
+        self.board
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let bank = self.prg_banks[usize::from((address - 0x8000) >> 13)];
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        (self.chr_bank * CHR_BANK_SIZE + usize::from(address)) % self.chr.len()
+    }
+
+    fn select_prg_16k(&mut self, slot: usize, bank: usize) {
+        self.prg_banks[slot * 2] = bank * 2;
+        self.prg_banks[slot * 2 + 1] = bank * 2 + 1;
+    }
+
+    fn has_nibble_ram(&self, address: u16) -> bool {
+        match self.board {
+            Board::Bmc64In1 => (0x5800..=0x5fff).contains(&address),
+            Board::Action52 => (0x4020..=0x5fff).contains(&address),
+            _ => false,
+        }
+    }
+
+    fn write_register(&mut self, address: u16, data: u8) {
+        match self.board {
+            Board::Contra100In1 => {
+                let sub_bank = usize::from(data >> 7);
+                let bank = usize::from(data & 0x3f) << 1;
+                let mode = address & 0x03;
+                self.prg_banks = match mode {
+                    // NROM-256, the low bit swaps the 8KB halves of each 16KB bank.
+                    0 => [bank ^ sub_bank, (bank + 1) ^ sub_bank, (bank + 2) ^ sub_bank, (bank + 3) ^ sub_bank],
+                    // UNROM, the last 16KB bank of the 128KB block at $C000.
+                    1 => [bank | sub_bank, (bank | sub_bank) + 1, bank | sub_bank | 0x0e, (bank | sub_bank | 0x0e) + 1],
+                    // NROM-64, one 8KB bank mirrored four times.
+                    2 => [bank | sub_bank; 4],
+                    // NROM-128
+                    _ => [bank | sub_bank, (bank | sub_bank) + 1, bank | sub_bank, (bank | sub_bank) + 1],
+                };
+                self.is_chr_write_protected = mode == 0 || mode == 3;
+                self.mirroring = if (data & 0x40) == 0x40 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+            Board::Bmc64In1 => {
+                let high = usize::from((address >> 8) & 0x40);
+                let bank = high | usize::from((address >> 6) & 0x3f);
+                if (address & 0x1000) == 0x1000 {
+                    self.select_prg_16k(0, bank);
+                    self.select_prg_16k(1, bank);
+                } else {
+                    self.select_prg_16k(0, bank & !1);
+                    self.select_prg_16k(1, bank | 1);
+                }
+                self.chr_bank = high | usize::from(address & 0x3f);
+                self.mirroring = if (address & 0x2000) == 0x2000 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+            Board::Bmc1200In1 => {
+                let bank = usize::from(((address >> 2) & 0x1f) | ((address & 0x100) >> 3));
+                let is_32k = (address & 0x01) == 0x01;
+                let is_last_bank = (address & 0x200) == 0x200;
+                let banks = if (address & 0x80) == 0x80 {
+                    if is_32k { [bank & !1, bank | 1] } else { [bank, bank] }
+                } else {
+                    // UNROM-like, $C000 holds the first or last bank of the 128KB block.
+                    let first = if is_32k { bank & 0x3e } else { bank };
+                    [first, if is_last_bank { bank | 0x07 } else { bank & 0x38 }]
+                };
+                self.select_prg_16k(0, banks[0]);
+                self.select_prg_16k(1, banks[1]);
+                self.mirroring = if (address & 0x02) == 0x02 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+            Board::Action52 => {
+                // Action 52 has no chip 2, its dump stores the chip selected as 3 right after chip 1.
+                let chip = match (address >> 11) & 0x03 {
+                    3 => 2,
+                    chip => chip,
+                };
+                let bank = usize::from(((address >> 6) & 0x1f) | (chip << 5));
+                if (address & 0x20) == 0x20 {
+                    self.select_prg_16k(0, bank);
+                    self.select_prg_16k(1, bank);
+                } else {
+                    self.select_prg_16k(0, bank & !1);
+                    self.select_prg_16k(1, bank | 1);
+                }
+                self.chr_bank = usize::from(((address & 0x0f) << 2) as u8 | (data & 0x03));
+                self.mirroring = if (address & 0x2000) == 0x2000 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+        }
+    }
+}
+
+impl Mapper for Multicart {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            _ if self.has_nibble_ram(address) => Some(self.nibble_ram[usize::from(address & 0x03)]),
+            0x8000..=0xffff if !self.prg_rom.is_empty() => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        match address {
+            _ if self.has_nibble_ram(address) => self.nibble_ram[usize::from(address & 0x03)] = data & 0x0f,
+            0x8000..=0xffff => self.write_register(address, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram && !self.is_chr_write_protected {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::multicart::{Board, Multicart};
+
+    // The second 8KB half of each 16KB PRG bank holds the bank number plus 0x80.
+    fn create(mapper: u16, prg_banks: usize, chr_banks: usize) -> Memory {
+        let mut rom = make_rom(mapper, 0, 0x4000, prg_banks, 0x2000, chr_banks, 0);
+        for bank in rom.prg_rom.chunks_mut(0x4000) {
+            bank[0x2000..].iter_mut().for_each(|byte| *byte |= 0x80);
+        }
+        Memory::new(Box::new(Multicart::new(&rom)))
+    }
+
+    fn read_prg(mem: &mut Memory) -> [u8; 4] {
+        [mem.read_u8(0x8000), mem.read_u8(0xa000), mem.read_u8(0xc000), mem.read_u8(0xe000)]
+    }
+
+    # [test]
+    fn test_boards() {
+        for (mapper, board) in [
+            (15, Board::Contra100In1),
+            (225, Board::Bmc64In1),
+            (227, Board::Bmc1200In1),
+            (228, Board::Action52),
+        ] {
+            assert_eq!(Multicart::new(&make_rom(mapper, 0, 0x4000, 2, 0x2000, 1, 0)).board(), board);
+        }
+    }
+
+    # [test]
+    fn test_contra_100_in_1() {
+        let mut mem = create(15, 16, 0);
+        assert_eq!(read_prg(&mut mem), [0x00, 0x80, 0x01, 0x81]);
+
+        for (address, data, banks, mirroring) in [
+            (0x8000, 0x82, [0x82, 0x02, 0x83, 0x03], Mirroring::Vertical),
+            (0x8001, 0x42, [0x02, 0x82, 0x07, 0x87], Mirroring::Horizontal),
+            (0x8002, 0x83, [0x83, 0x83, 0x83, 0x83], Mirroring::Vertical),
+            (0x8003, 0x05, [0x05, 0x85, 0x05, 0x85], Mirroring::Vertical),
+        ] {
+            mem.write_u8(address, data);
+            assert_eq!(read_prg(&mut mem), banks);
+            assert_eq!(mem.mapper.mirroring(), mirroring);
+        }
+
+        // CHR-RAM is only writable in the UNROM and NROM-64 modes.
+        mem.write_video_u8(0x0000, 0x12);
+        assert_eq!(mem.read_video_u8(0x0000), 0x00);
+        mem.write_u8(0x8001, 0x00);
+        mem.write_video_u8(0x0000, 0x12);
+        assert_eq!(mem.read_video_u8(0x0000), 0x12);
+    }
+
+    # [test]
+    fn test_bmc_64_in_1() {
+        let mut mem = create(225, 128, 128);
+        // 32KB mode, PRG bank 2 and CHR bank 5.
+        mem.write_u8(0x8085, 0x00);
+        assert_eq!(read_prg(&mut mem), [0x02, 0x82, 0x03, 0x83]);
+        assert_eq!(mem.read_video_u8(0x0000), 5);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Vertical);
+
+        // 16KB mode, horizontal mirroring and the high bit for both banks.
+        mem.write_u8(0xf0c1, 0x00);
+        assert_eq!(read_prg(&mut mem), [0x43, 0xc3, 0x43, 0xc3]);
+        assert_eq!(mem.read_video_u8(0x0000), 0x41);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Horizontal);
+
+        mem.write_u8(0x5800, 0xff);
+        mem.write_u8(0x5803, 0x12);
+        assert_eq!(mem.read_u8(0x5800), 0x0f);
+        assert_eq!(mem.read_u8(0x5ffb), 0x02);
+    }
+
+    # [test]
+    fn test_bmc_1200_in_1() {
+        let mut mem = create(227, 64, 0);
+        for (address, banks, mirroring) in [
+            // NROM-128
+            (0x8094, [0x05, 0x85, 0x05, 0x85], Mirroring::Vertical),
+            // NROM-256
+            (0x8095, [0x04, 0x84, 0x05, 0x85], Mirroring::Vertical),
+            // UNROM with the first bank of the block at $C000.
+            (0x8016, [0x05, 0x85, 0x00, 0x80], Mirroring::Horizontal),
+            // UNROM with the last bank of the block at $C000, and the PRG bank high bit.
+            (0x8314, [0x25, 0xa5, 0x27, 0xa7], Mirroring::Vertical),
+        ] {
+            mem.write_u8(address, 0x00);
+            assert_eq!(read_prg(&mut mem), banks);
+            assert_eq!(mem.mapper.mirroring(), mirroring);
+        }
+    }
+
+    # [test]
+    fn test_action_52() {
+        let mut mem = create(228, 96, 64);
+        // 32KB mode in the first chip, CHR bank from the address and data.
+        mem.write_u8(0x80c3, 0x02);
+        assert_eq!(read_prg(&mut mem), [0x02, 0x82, 0x03, 0x83]);
+        assert_eq!(mem.read_video_u8(0x0000), 0x0e);
+
+        // 16KB mode in the third chip, selected as chip 3.
+        mem.write_u8(0xb860, 0x00);
+        assert_eq!(read_prg(&mut mem), [0x41, 0xc1, 0x41, 0xc1]);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Horizontal);
+
+        mem.write_u8(0x4020, 0x15);
+        assert_eq!(mem.read_u8(0x5ffc), 0x05);
+    }
+}
@@ -0,0 +1,305 @@
+use memory::mapper::{Mapper, Mirroring, NAME_TABLE_SIZE};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const SOUND_RAM_SIZE: usize = 0x0080;
+/// Bank values from $E0 select a page of the console VRAM instead of CHR-ROM.
+const VRAM_BANK_BASE: u8 = 0xe0;
+const IRQ_COUNTER_MAX: u16 = 0x7fff;
+
+/// Mapper 19: Namco 129 and 163.
+/// The expansion audio channels are left to the APU, which reads their registers from the sound RAM.
+/// Pattern table banks that select the console VRAM fall back to CHR-ROM.
+#[derive(Clone)]
+pub struct Namco163 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+
+    chr_banks: [u8; 8],
+    name_table_banks: [u8; 4],
+    prg_banks: [u8; 3],
+    /// $F800: bits 4-7 must be 0100 to write PRG-RAM and bits 0-3 protect its 2KB quarters.
+    write_protect: u8,
+
+    sound_ram: [u8; SOUND_RAM_SIZE],
+    /// $F800: bits 0-6 address the sound RAM and bit 7 increments the address after each access.
+    sound_address: u8,
+
+    irq_counter: u16,
+    is_irq_enabled: bool,
+    is_irq_pending: bool,
+}
+
+impl Namco163 {
+    pub fn new(rom: &Rom) -> Self {
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr,
+            is_chr_ram,
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            name_table_banks: [VRAM_BANK_BASE, VRAM_BANK_BASE + 1, VRAM_BANK_BASE, VRAM_BANK_BASE + 1],
+            prg_banks: [0, 1, 2],
+            write_protect: 0,
+            sound_ram: [0; SOUND_RAM_SIZE],
+            sound_address: 0,
+            irq_counter: 0,
+            is_irq_enabled: false,
+            is_irq_pending: false,
+        }
+    }
+
+    /// Returns the internal RAM holding the expansion audio registers and wavetables.
+    pub fn sound_ram(&self) -> &[u8] {
+        &self.sound_ram
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match address {
+            0x8000..=0xdfff => usize::from(self.prg_banks[usize::from((address - 0x8000) >> 13)]),
+            _ => banks.saturating_sub(1),
+        };
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, bank: u8, address: u16) -> usize {
+        let offset = usize::from(address) & (CHR_BANK_SIZE - 1);
+        (usize::from(bank) * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+
+    fn is_prg_ram_writable(&self, address: u16) -> bool {
+        let quarter = (address - 0x6000) >> 11;
+        (self.write_protect & 0xf0) == 0x40 && (self.write_protect & (1 << quarter)) == 0
+    }
+
+    /// Returns the sound RAM address and advances it when auto-increment is set.
+    fn next_sound_address(&mut self) -> usize {
+        let address = usize::from(self.sound_address & 0x7f);
+        if (self.sound_address & 0x80) == 0x80 {
+            self.sound_address = 0x80 | (self.sound_address.wrapping_add(1) & 0x7f);
+        }
+        address
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_read_u8(&mut self, address: u16) -> Option<u8> {
+        let data = self.cpu_peek_u8(address);
+        if (0x4800..=0x4fff).contains(&address) {
+            self.next_sound_address();
+        }
+        data
+    }
+
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x4800..=0x4fff => Some(self.sound_ram[usize::from(self.sound_address & 0x7f)]),
+            0x5000..=0x57ff => Some((self.irq_counter & 0xff) as u8),
+            0x5800..=0x5fff => Some(((self.irq_counter >> 8) as u8) | if self.is_irq_enabled { 0x80 } else { 0x00 }),
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => Some(self.prg_ram[usize::from(address - 0x6000) % self.prg_ram.len()]),
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        match address {
+            0x4800..=0x4fff => {
+                let index = self.next_sound_address();
+                self.sound_ram[index] = data;
+            }
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | u16::from(data);
+                self.is_irq_pending = false;
+            }
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | (u16::from(data & 0x7f) << 8);
+                self.is_irq_enabled = (data & 0x80) == 0x80;
+                self.is_irq_pending = false;
+            }
+            0x6000..=0x7fff if !self.prg_ram.is_empty() && self.is_prg_ram_writable(address) => {
+                let index = usize::from(address - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            0x8000..=0xbfff => self.chr_banks[usize::from((address - 0x8000) >> 11)] = data,
+            0xc000..=0xdfff => self.name_table_banks[usize::from((address - 0xc000) >> 11)] = data,
+            0xe000..=0xf7ff => self.prg_banks[usize::from((address - 0xe000) >> 11)] = data & 0x3f,
+            0xf800..=0xffff => {
+                self.write_protect = data;
+                self.sound_address = data;
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        let bank = self.chr_banks[usize::from(address >> 10) & 0x07];
+        self.chr[self.chr_index(bank, address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let bank = self.chr_banks[usize::from(address >> 10) & 0x07];
+            let index = self.chr_index(bank, address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // Only describes layouts that map all four nametables to VRAM, `read_name_table_u8` has the full mapping.
+        match self.name_table_banks.map(|bank| bank & 0x01) {
+            [0, 0, 1, 1] => Mirroring::Horizontal,
+            [0, 0, 0, 0] => Mirroring::SingleScreenLower,
+            [1, 1, 1, 1] => Mirroring::SingleScreenUpper,
+            _ => Mirroring::Vertical,
+        }
+    }
+
+    fn read_name_table_u8(&mut self, address: u16, vram: &[u8]) -> u8 {
+        let bank = self.name_table_banks[usize::from(address >> 10) & 0x03];
+        let offset = usize::from(address) & (NAME_TABLE_SIZE - 1);
+        if bank >= VRAM_BANK_BASE {
+            vram[usize::from(bank & 0x01) * NAME_TABLE_SIZE + offset]
+        } else {
+            self.chr[self.chr_index(bank, address)]
+        }
+    }
+
+    fn write_name_table_u8(&mut self, address: u16, data: u8, vram: &mut [u8]) {
+        let bank = self.name_table_banks[usize::from(address >> 10) & 0x03];
+        let offset = usize::from(address) & (NAME_TABLE_SIZE - 1);
+        if bank >= VRAM_BANK_BASE {
+            vram[usize::from(bank & 0x01) * NAME_TABLE_SIZE + offset] = data;
+        }
+    }
+
+    fn step(&mut self, cpu_cycles: usize) {
+        if !self.is_irq_enabled || self.irq_counter == IRQ_COUNTER_MAX {
+            return;
+        }
+        let cycles = cpu_cycles.min(usize::from(IRQ_COUNTER_MAX - self.irq_counter)) as u16;
+        self.irq_counter += cycles;
+        if self.irq_counter == IRQ_COUNTER_MAX {
+            self.is_irq_pending = true;
+        }
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.is_irq_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::namco163::Namco163;
+
+    # [test]
+    fn test_prg_banking() {
+        let mut mem = Memory::new(Box::new(Namco163::new(&make_rom(19, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0xe000, 0x41);
+        mem.write_u8(0xe800, 0xc2);
+        mem.write_u8(0xf000, 0x03);
+        assert_eq!(mem.read_u8(0x8000), 1);
+        assert_eq!(mem.read_u8(0xa000), 2);
+        assert_eq!(mem.read_u8(0xc000), 3);
+        assert_eq!(mem.read_u8(0xe000), 15);
+    }
+
+    # [test]
+    fn test_prg_ram_write_protect() {
+        let mut mem = Memory::new(Box::new(Namco163::new(&make_rom(19, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.read_u8(0x6000), 0x00);
+
+        // Writes are enabled, except to $6800-$6FFF.
+        mem.write_u8(0xf800, 0x42);
+        mem.write_u8(0x6000, 0x12);
+        mem.write_u8(0x6800, 0x34);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+        assert_eq!(mem.read_u8(0x6800), 0x00);
+    }
+
+    # [test]
+    fn test_chr_and_name_table_banks() {
+        let mut mem = Memory::new(Box::new(Namco163::new(&make_rom(19, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0x8000, 0x10);
+        mem.write_u8(0xb800, 0x17);
+        assert_eq!(mem.read_video_u8(0x0000), 0x10);
+        assert_eq!(mem.read_video_u8(0x1c00), 0x17);
+
+        // Horizontal mirroring from VRAM pages, then the last nametable from CHR-ROM.
+        for (address, bank) in [(0xc000, 0xe0), (0xc800, 0xe0), (0xd000, 0xe1), (0xd800, 0xe1)] {
+            mem.write_u8(address, bank);
+        }
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Horizontal);
+        mem.write_video_u8(0x2000, 0x55);
+        mem.write_video_u8(0x2800, 0x66);
+        assert_eq!(mem.read_video_u8(0x2400), 0x55);
+        assert_eq!(mem.read_video_u8(0x2c00), 0x66);
+
+        mem.write_u8(0xd800, 0x21);
+        assert_eq!(mem.read_video_u8(0x2c00), 0x21);
+        // Nametables in CHR-ROM are read-only.
+        mem.write_video_u8(0x2c00, 0x77);
+        assert_eq!(mem.read_video_u8(0x2c00), 0x21);
+        assert_eq!(mem.read_video_u8(0x2800), 0x66);
+    }
+
+    # [test]
+    fn test_sound_ram() {
+        let mut mem = Memory::new(Box::new(Namco163::new(&make_rom(19, 0, 0x2000, 16, 0x0400, 64, 7))));
+        // Auto-increment from $7E wraps to $00.
+        mem.write_u8(0xf800, 0xfe);
+        for data in [0x11, 0x22, 0x33] {
+            mem.write_u8(0x4800, data);
+        }
+        mem.write_u8(0xf800, 0x7f);
+        assert_eq!(mem.read_u8(0x4800), 0x22);
+        assert_eq!(mem.read_u8(0x4800), 0x22);
+        mem.write_u8(0xf800, 0x80);
+        // Peeking does not advance the address.
+        assert_eq!(mem.peek_u8(0x4800), 0x33);
+        assert_eq!(mem.read_u8(0x4800), 0x33);
+        assert_eq!(mem.read_u8(0x4800), 0x00);
+    }
+
+    # [test]
+    fn test_irq() {
+        let mut mem = Memory::new(Box::new(Namco163::new(&make_rom(19, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0x5000, 0xf0);
+        mem.write_u8(0x5800, 0xff);
+        assert_eq!(mem.read_u8(0x5000), 0xf0);
+        assert_eq!(mem.read_u8(0x5800), 0xff);
+        mem.mapper.step(14);
+        assert!(!mem.mapper.is_irq_pending());
+        mem.mapper.step(1);
+        assert!(mem.mapper.is_irq_pending());
+
+        // The counter stops at $7FFF and writing it acknowledges the IRQ.
+        mem.mapper.step(100);
+        assert_eq!(mem.read_u8(0x5000), 0xff);
+        mem.write_u8(0x5000, 0x00);
+        assert!(!mem.mapper.is_irq_pending());
+    }
+}
@@ -0,0 +1,132 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+
+/// Mapper 0: NROM-128 (16KB PRG mirrored) and NROM-256 (32KB PRG).
+#[derive(Clone)]
+pub struct Nrom {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: &Rom) -> Self {
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr,
+            is_chr_ram,
+            mirroring: rom.mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                Some(self.prg_ram[usize::from(address - 0x6000) % self.prg_ram.len()])
+            }
+            0x8000..=0xffff if !self.prg_rom.is_empty() => {
+                Some(self.prg_rom[usize::from(address - 0x8000) % self.prg_rom.len()])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        if let 0x6000..=0x7fff = address {
+            if !self.prg_ram.is_empty() {
+                let index = usize::from(address - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        if self.chr.is_empty() {
+            return 0;
+        }
+        self.chr[usize::from(address) % self.chr.len()]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let index = usize::from(address) % self.chr.len();
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::nrom::Nrom;
+
+    # [test]
+    fn test_nrom_128() {
+        let mut rom = make_rom(0, 0, 0x4000, 1, 0x2000, 1, 7);
+        rom.mirroring = Mirroring::Vertical;
+        let mut mem = Memory::new(Box::new(Nrom::new(&rom)));
+        assert_eq!(mem.read_u8(0x8000), 0x00);
+        assert_eq!(mem.read_u8(0xc000), 0x00);
+        assert_eq!(mem.read_u8(0xffff), 0x00);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Vertical);
+    }
+
+    # [test]
+    fn test_nrom_256() {
+        let mut mem = Memory::new(Box::new(Nrom::new(&make_rom(0, 0, 0x4000, 2, 0x2000, 1, 7))));
+        assert_eq!(mem.read_u8(0x8000), 0x00);
+        assert_eq!(mem.read_u8(0xbfff), 0x00);
+        assert_eq!(mem.read_u8(0xc000), 0x01);
+        assert_eq!(mem.read_u8(0xffff), 0x01);
+
+        // PRG-ROM is not writable.
+        mem.write_u8(0xc000, 0xff);
+        assert_eq!(mem.read_u8(0xc000), 0x01);
+    }
+
+    # [test]
+    fn test_prg_ram() {
+        let mut mem = Memory::new(Box::new(Nrom::new(&make_rom(0, 0, 0x4000, 1, 0x2000, 1, 7))));
+        mem.write_u8(0x6000, 0x12);
+        mem.write_u8(0x7fff, 0x34);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+        assert_eq!(mem.read_u8(0x7fff), 0x34);
+    }
+
+    # [test]
+    fn test_chr_rom_and_ram() {
+        let mut mem = Memory::new(Box::new(Nrom::new(&make_rom(0, 0, 0x4000, 1, 0x2000, 1, 7))));
+        mem.write_video_u8(0x0000, 0x12);
+        assert_eq!(mem.read_video_u8(0x0000), 0x00);
+
+        let mut mem = Memory::new(Box::new(Nrom::new(&make_rom(0, 0, 0x4000, 1, 0x2000, 0, 7))));
+        mem.write_video_u8(0x0000, 0x12);
+        mem.write_video_u8(0x1fff, 0x34);
+        assert_eq!(mem.read_video_u8(0x0000), 0x12);
+        assert_eq!(mem.read_video_u8(0x1fff), 0x34);
+    }
+}
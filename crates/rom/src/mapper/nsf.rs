@@ -0,0 +1,146 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::nsf::Nsf;
+
+const BANK_SIZE: usize = 0x1000;
+const PRG_RAM_SIZE: usize = 0x2000;
+
+/// Pseudo-cartridge holding NSF data at $8000-$FFFF and 8KB of RAM at $6000-$7FFF.
+/// Bankswitched data is mapped in 4KB banks selected through $5FF8-$5FFF.
+#[derive(Clone)]
+pub struct NsfCartridge {
+    data: Vec<u8>,
+    prg_ram: Vec<u8>,
+    banks: [u8; 8],
+    is_bankswitched: bool,
+}
+
+impl NsfCartridge {
+    pub fn new(nsf: &Nsf) -> Self {
+        let (padding, banks) = match nsf.initial_banks {
+            // Bankswitched data starts at the load address offset within its first bank.
+            Some(banks) => (usize::from(nsf.load_address) & (BANK_SIZE - 1), banks),
+            None => (usize::from(nsf.load_address - 0x8000), [0, 1, 2, 3, 4, 5, 6, 7]),
+        };
+        let mut data = vec![0; padding];
+        data.extend(&nsf.data);
+        let size = match nsf.initial_banks {
+            Some(_) => data.len().div_ceil(BANK_SIZE) * BANK_SIZE,
+            None => BANK_SIZE * 8,
+        };
+        data.resize(size, 0);
+        Self {
+            data,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            banks,
+            is_bankswitched: nsf.initial_banks.is_some(),
+        }
+    }
+
+    fn data_index(&self, address: u16) -> usize {
+        let bank = usize::from(self.banks[usize::from(address >> 12) & 0x07]);
+        let offset = usize::from(address) & (BANK_SIZE - 1);
+        (bank * BANK_SIZE + offset) % self.data.len()
+    }
+}
+
+impl Mapper for NsfCartridge {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff => Some(self.prg_ram[usize::from(address - 0x6000)]),
+            0x8000..=0xffff => Some(self.data[self.data_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        match address {
+            0x5ff8..=0x5fff if self.is_bankswitched => self.banks[usize::from(address - 0x5ff8)] = data,
+            0x6000..=0x7fff => self.prg_ram[usize::from(address - 0x6000)] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, _address: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write_u8(&mut self, _address: u16, _data: u8) {
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::system::SystemBus;
+    use crate::TvSystem;
+    use crate::mapper::nsf::NsfCartridge;
+    use crate::nsf::{Nsf, NsfFormat};
+
+    // Builds an NSF whose data is 4KB banks filled with their bank number.
+    fn make_nsf(load_address: u16, initial_banks: Option<[u8; 8]>, banks: u8) -> Nsf {
+        let mut data = vec![];
+        for bank in 0..banks {
+            data.extend(vec![bank; 0x1000]);
+        }
+        Nsf {
+            format: NsfFormat::Nsf,
+            track_count: 1,
+            starting_track: 0,
+            load_address,
+            init_address: 0x8000,
+            play_address: 0x8000,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: None,
+            ntsc_play_period: 16639,
+            pal_play_period: 19997,
+            tv_system: TvSystem::Ntsc,
+            expansion_chips: 0,
+            initial_banks,
+            track_labels: vec![],
+            track_lengths: vec![],
+            data,
+        }
+    }
+
+    # [test]
+    fn test_plain_data() {
+        let mut mem = Memory::new(Box::new(NsfCartridge::new(&make_nsf(0x8400, None, 2))));
+        assert_eq!(mem.read_u8(0x8000), 0x00);
+        assert_eq!(mem.read_u8(0x8400), 0x00);
+        assert_eq!(mem.read_u8(0x9400), 0x01);
+        assert_eq!(mem.read_u8(0xa400), 0x00);
+
+        // Bank registers are ignored when the data is not bankswitched.
+        mem.write_u8(0x5ff8, 0x01);
+        assert_eq!(mem.read_u8(0x9400), 0x01);
+
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+    }
+
+    # [test]
+    fn test_bankswitching() {
+        let mut mem = Memory::new(Box::new(NsfCartridge::new(&make_nsf(0x8000, Some([0, 1, 2, 3, 4, 5, 6, 7]), 12))));
+        assert_eq!(mem.read_u8(0x8000), 0);
+        assert_eq!(mem.read_u8(0xf000), 7);
+        mem.write_u8(0x5ff8, 0x0b);
+        mem.write_u8(0x5fff, 0x08);
+        assert_eq!(mem.read_u8(0x8000), 11);
+        assert_eq!(mem.read_u8(0xffff), 8);
+    }
+}
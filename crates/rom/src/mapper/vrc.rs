@@ -0,0 +1,437 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// PPU dots per scanline, counted in thirds of a dot per CPU cycle by the prescaler.
+const PRESCALER_PERIOD: i16 = 341;
+
+/// Address lines wired to the register select pins A0 and A1 of a Konami VRC chip.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub(crate) struct Pins {
+    a0: u16,
+    a1: u16,
+}
+
+impl Pins {
+    pub(crate) const fn new(a0: u16, a1: u16) -> Self {
+        Self { a0, a1 }
+    }
+
+    /// Folds a CPU address onto the canonical $x000-$x003 register addresses.
+    pub(crate) fn register(&self, address: u16) -> u16 {
+        let a0 = if (address & self.a0) != 0 { 0x01 } else { 0x00 };
+        let a1 = if (address & self.a1) != 0 { 0x02 } else { 0x00 };
+        (address & 0xf000) | a0 | a1
+    }
+}
+
+/// The CPU cycle based IRQ counter shared by VRC4, VRC6 and VRC7.
+#[derive(Default, Clone)]
+pub(crate) struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    is_enabled: bool,
+    is_enabled_after_ack: bool,
+    is_cycle_mode: bool,
+    pub(crate) is_pending: bool,
+}
+
+impl VrcIrq {
+    pub(crate) fn write_latch(&mut self, data: u8) {
+        self.latch = data;
+    }
+
+    pub(crate) fn write_latch_low(&mut self, data: u8) {
+        self.latch = (self.latch & 0xf0) | (data & 0x0f);
+    }
+
+    pub(crate) fn write_latch_high(&mut self, data: u8) {
+        self.latch = (self.latch & 0x0f) | ((data & 0x0f) << 4);
+    }
+
+    pub(crate) fn write_control(&mut self, data: u8) {
+        self.is_enabled_after_ack = (data & 0x01) == 0x01;
+        self.is_enabled = (data & 0x02) == 0x02;
+        self.is_cycle_mode = (data & 0x04) == 0x04;
+        self.is_pending = false;
+        if self.is_enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub(crate) fn acknowledge(&mut self) {
+        self.is_pending = false;
+        self.is_enabled = self.is_enabled_after_ack;
+    }
+
+    pub(crate) fn step(&mut self, cpu_cycles: usize) {
+        if !self.is_enabled {
+            return;
+        }
+        for _ in 0..cpu_cycles {
+            if self.is_cycle_mode {
+                self.clock();
+            } else {
+                // Scanline mode divides the CPU clock by 113.667.
+                self.prescaler -= 3;
+                if self.prescaler <= 0 {
+                    self.prescaler += PRESCALER_PERIOD;
+                    self.clock();
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.is_pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Chip {
+    Vrc2,
+    Vrc4,
+}
+
+/// Mappers 21, 22, 23 and 25: Konami VRC2 and VRC4.
+/// The submapper picks the address lines wired to A0 and A1. Without one both
+/// candidate lines are combined, and mappers 21, 23 and 25 are treated as VRC4.
+#[derive(Clone)]
+pub struct Vrc {
+    chip: Chip,
+    pins: Pins,
+    // VRC2a ignores the lowest bit of the CHR bank number.
+    chr_shift: u8,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+
+    prg_banks: [u8; 2],
+    chr_banks: [u16; 8],
+    mirroring: Mirroring,
+    is_prg_swapped: bool,
+    is_prg_ram_enabled: bool,
+    // VRC2 boards without PRG-RAM have a one bit latch at $6000-$6FFF.
+    latch: u8,
+
+    irq: VrcIrq,
+}
+
+impl Vrc {
+    pub fn new(rom: &Rom) -> Self {
+        let (chip, pins) = match (rom.mapper, rom.submapper) {
+            (21, 1) => (Chip::Vrc4, Pins::new(0x0002, 0x0004)),
+            (21, 2) => (Chip::Vrc4, Pins::new(0x0040, 0x0080)),
+            (21, _) => (Chip::Vrc4, Pins::new(0x0042, 0x0084)),
+            (22, _) => (Chip::Vrc2, Pins::new(0x0002, 0x0001)),
+            (23, 1) => (Chip::Vrc4, Pins::new(0x0001, 0x0002)),
+            (23, 2) => (Chip::Vrc4, Pins::new(0x0004, 0x0008)),
+            (23, 3) => (Chip::Vrc2, Pins::new(0x0001, 0x0002)),
+            (23, _) => (Chip::Vrc4, Pins::new(0x0005, 0x000a)),
+            (_, 1)  => (Chip::Vrc4, Pins::new(0x0002, 0x0001)),
+            (_, 2)  => (Chip::Vrc4, Pins::new(0x0008, 0x0004)),
+            (_, 3)  => (Chip::Vrc2, Pins::new(0x0002, 0x0001)),
+            (_, _)  => (Chip::Vrc4, Pins::new(0x000a, 0x0005)),
+        };
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            chip,
+            pins,
+            chr_shift: if rom.mapper == 22 { 1 } else { 0 },
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr,
+            is_chr_ram,
+            prg_banks: [0, 1],
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            mirroring: Mirroring::Vertical,
+            is_prg_swapped: false,
+            is_prg_ram_enabled: chip == Chip::Vrc2,
+            latch: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    fn is_prg_ram_enabled(&self) -> bool {
+        self.is_prg_ram_enabled && !self.prg_ram.is_empty()
+    }
+
+    fn has_latch(&self) -> bool {
+        self.chip == Chip::Vrc2 && self.prg_ram.is_empty()
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let second_last = banks.saturating_sub(2);
+        let bank = match ((address >> 13) & 0x03, self.is_prg_swapped) {
+            (0, false) | (2, true) => usize::from(self.prg_banks[0]),
+            (0, true) | (2, false) => second_last,
+            (1, _) => usize::from(self.prg_banks[1]),
+            _ => banks.saturating_sub(1),
+        };
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = usize::from(self.chr_banks[usize::from(address >> 10) & 0x07] >> self.chr_shift);
+        let offset = usize::from(address) & (CHR_BANK_SIZE - 1);
+        (bank * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+
+    fn write_chr_bank(&mut self, register: u16, data: u8) {
+        // $B000-$E003: low and high nibbles of the eight 1KB banks.
+        let index = usize::from(((register >> 12) - 0x0b) * 2 + ((register >> 1) & 0x01));
+        let bank = self.chr_banks[index];
+        let high_mask = if self.chip == Chip::Vrc4 { 0x1f } else { 0x0f };
+        self.chr_banks[index] = if (register & 0x01) == 0x00 {
+            (bank & 0x1f0) | u16::from(data & 0x0f)
+        } else {
+            (bank & 0x0f) | (u16::from(data & high_mask) << 4)
+        };
+    }
+}
+
+impl Mapper for Vrc {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.is_prg_ram_enabled() => Some(self.prg_ram[usize::from(address - 0x6000) % self.prg_ram.len()]),
+            0x6000..=0x6fff if self.has_latch() => Some(self.latch),
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        if address < 0x8000 {
+            match address {
+                0x6000..=0x7fff if self.is_prg_ram_enabled() => {
+                    let index = usize::from(address - 0x6000) % self.prg_ram.len();
+                    self.prg_ram[index] = data;
+                }
+                0x6000..=0x6fff if self.has_latch() => self.latch = data & 0x01,
+                _ => {}
+            }
+            return;
+        }
+
+        let register = self.pins.register(address);
+        match (register, self.chip) {
+            (0x8000..=0x8003, _) => self.prg_banks[0] = data & 0x1f,
+            (0x9000..=0x9003, Chip::Vrc2) => {
+                self.mirroring = if (data & 0x01) == 0x01 { Mirroring::Horizontal } else { Mirroring::Vertical };
+            }
+            (0x9000, Chip::Vrc4) => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                };
+            }
+            (0x9002, Chip::Vrc4) => {
+                self.is_prg_ram_enabled = (data & 0x01) == 0x01;
+                self.is_prg_swapped = (data & 0x02) == 0x02;
+            }
+            (0xa000..=0xa003, _) => self.prg_banks[1] = data & 0x1f,
+            (0xb000..=0xefff, _) => self.write_chr_bank(register, data),
+            (0xf000, Chip::Vrc4) => self.irq.write_latch_low(data),
+            (0xf001, Chip::Vrc4) => self.irq.write_latch_high(data),
+            (0xf002, Chip::Vrc4) => self.irq.write_control(data),
+            (0xf003, Chip::Vrc4) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn step(&mut self, cpu_cycles: usize) {
+        self.irq.step(cpu_cycles);
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.irq.is_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::Rom;
+    use crate::mapper::make_rom;
+    use crate::mapper::vrc::{Chip, Pins, Vrc, VrcIrq};
+
+    # [test]
+    fn test_pins() {
+        let cases = [
+            // (a0, a1, address, register)
+            (0x0001, 0x0002, 0x9003, 0x9003),
+            (0x0002, 0x0001, 0x9001, 0x9002),
+            (0x0040, 0x0080, 0xb0c0, 0xb003),
+            (0x0008, 0x0004, 0xe004, 0xe002),
+            (0x0005, 0x000a, 0xf008, 0xf002),
+        ];
+        for (a0, a1, address, register) in cases {
+            assert_eq!(Pins::new(a0, a1).register(address), register);
+        }
+    }
+
+    # [test]
+    fn test_chip_from_submapper() {
+        let cases = [
+            (21, 0, Chip::Vrc4),
+            (22, 0, Chip::Vrc2),
+            (23, 0, Chip::Vrc4),
+            (23, 3, Chip::Vrc2),
+            (25, 1, Chip::Vrc4),
+            (25, 3, Chip::Vrc2),
+        ];
+        for (mapper, submapper, chip) in cases {
+            assert_eq!(Vrc::new(&make_rom(mapper, submapper, 0x2000, 4, 0x0400, 8, 7)).chip(), chip);
+        }
+    }
+
+    # [test]
+    fn test_prg_banking() {
+        let mut mem = Memory::new(Box::new(Vrc::new(&make_rom(23, 1, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0x8000, 0x03);
+        mem.write_u8(0xa000, 0x05);
+        assert_eq!(mem.read_u8(0x8000), 3);
+        assert_eq!(mem.read_u8(0xa000), 5);
+        assert_eq!(mem.read_u8(0xc000), 14);
+        assert_eq!(mem.read_u8(0xe000), 15);
+
+        mem.write_u8(0x9002, 0x02);
+        assert_eq!(mem.read_u8(0x8000), 14);
+        assert_eq!(mem.read_u8(0xc000), 3);
+    }
+
+    # [test]
+    fn test_chr_banking() {
+        // VRC4e: A0 = A2, A1 = A3.
+        let mut mem = Memory::new(Box::new(Vrc::new(&make_rom(23, 2, 0x2000, 4, 0x0400, 256, 7))));
+        mem.write_u8(0xb000, 0x03);
+        mem.write_u8(0xb004, 0x01);
+        mem.write_u8(0xe008, 0x07);
+        mem.write_u8(0xe00c, 0x00);
+        assert_eq!(mem.read_video_u8(0x0000), 0x13);
+        assert_eq!(mem.read_video_u8(0x1c00), 0x07);
+
+        // VRC2a drops the lowest bit of the bank number.
+        let mut mem = Memory::new(Box::new(Vrc::new(&make_rom(22, 0, 0x2000, 4, 0x0400, 64, 7))));
+        mem.write_u8(0xb000, 0x07);
+        assert_eq!(mem.read_video_u8(0x0000), 0x03);
+    }
+
+    # [test]
+    fn test_mirroring() {
+        let mut mem = Memory::new(Box::new(Vrc::new(&make_rom(21, 1, 0x2000, 4, 0x0400, 8, 7))));
+        let cases = [
+            (0x00, Mirroring::Vertical),
+            (0x01, Mirroring::Horizontal),
+            (0x02, Mirroring::SingleScreenLower),
+            (0x03, Mirroring::SingleScreenUpper),
+        ];
+        for (data, mirroring) in cases {
+            mem.write_u8(0x9000, data);
+            assert_eq!(mem.mapper.mirroring(), mirroring);
+        }
+    }
+
+    # [test]
+    fn test_vrc2_latch() {
+        // NES 2.0 header without PRG-RAM.
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01, 0x60, 0x18, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(vec![0u8; 0x6000]);
+        let mut mem = Memory::new(Box::new(Vrc::new(&Rom::new(&data).unwrap())));
+        mem.write_u8(0x6000, 0x03);
+        assert_eq!(mem.read_u8(0x6000), 0x01);
+    }
+
+    # [test]
+    fn test_irq_cycle_mode() {
+        let mut irq = VrcIrq::default();
+        irq.write_latch(0xfd);
+        irq.write_control(0x07);
+        irq.step(2);
+        assert!(!irq.is_pending);
+        irq.step(1);
+        assert!(irq.is_pending);
+
+        // Acknowledging copies the enable-after-ack bit.
+        irq.acknowledge();
+        assert!(!irq.is_pending);
+        irq.step(3);
+        assert!(irq.is_pending);
+    }
+
+    # [test]
+    fn test_irq_scanline_mode() {
+        let mut irq = VrcIrq::default();
+        irq.write_latch(0xfe);
+        irq.write_control(0x02);
+        // Two scanlines take 2 * 341 / 3 CPU cycles.
+        irq.step(227);
+        assert!(!irq.is_pending);
+        irq.step(1);
+        assert!(irq.is_pending);
+
+        irq.acknowledge();
+        irq.step(1000);
+        assert!(!irq.is_pending);
+    }
+
+    # [test]
+    fn test_irq_through_memory() {
+        let mut mem = Memory::new(Box::new(Vrc::new(&make_rom(25, 1, 0x2000, 4, 0x0400, 8, 7))));
+        // VRC4b swaps A0 and A1: $F002 selects latch high, $F001 selects control.
+        mem.write_u8(0xf000, 0x0f);
+        mem.write_u8(0xf002, 0x0f);
+        mem.write_u8(0xf001, 0x06);
+        mem.mapper.step(1);
+        assert!(mem.mapper.is_irq_pending());
+        mem.write_u8(0xf003, 0x00);
+        assert!(!mem.mapper.is_irq_pending());
+    }
+}
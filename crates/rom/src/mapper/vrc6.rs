@@ -0,0 +1,218 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+use crate::mapper::vrc::{Pins, VrcIrq};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Mappers 24 (VRC6a) and 26 (VRC6b): Konami VRC6.
+/// The expansion audio registers at $9000-$B002 are left to the APU.
+/// Nametables fetched from CHR-ROM ($B003 bit 4) are not supported.
+#[derive(Clone)]
+pub struct Vrc6 {
+    pins: Pins,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+
+    prg_banks: [u8; 2],
+    chr_banks: [u8; 8],
+    banking_mode: u8,
+
+    irq: VrcIrq,
+}
+
+impl Vrc6 {
+    pub fn new(rom: &Rom) -> Self {
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            // VRC6b swaps A0 and A1.
+            pins: if rom.mapper == 26 { Pins::new(0x0002, 0x0001) } else { Pins::new(0x0001, 0x0002) },
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr,
+            is_chr_ram,
+            prg_banks: [0, 0],
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            banking_mode: 0x20,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match address {
+            0x8000..=0xbfff => usize::from(self.prg_banks[0]) * 2 + usize::from((address >> 13) & 0x01),
+            0xc000..=0xdfff => usize::from(self.prg_banks[1]),
+            _ => banks.saturating_sub(1),
+        };
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let slot = usize::from(address >> 10) & 0x07;
+        let a10 = usize::from(address >> 10) & 0x01;
+        // With $B003 bit 5 set the 2KB banks take A10 from the PPU instead of repeating one 1KB bank.
+        let two_kb_bank = |register: u8| {
+            if (self.banking_mode & 0x20) == 0x20 { (usize::from(register) & !0x01) | a10 } else { usize::from(register) }
+        };
+        let bank = match (self.banking_mode & 0x03, slot) {
+            (0, _) => usize::from(self.chr_banks[slot]),
+            (1, _) => two_kb_bank(self.chr_banks[slot / 2]),
+            (_, 0..=3) => usize::from(self.chr_banks[slot]),
+            (_, _) => two_kb_bank(self.chr_banks[4 + (slot - 4) / 2]),
+        };
+        let offset = usize::from(address) & (CHR_BANK_SIZE - 1);
+        (bank * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+
+    fn is_prg_ram_enabled(&self) -> bool {
+        (self.banking_mode & 0x80) == 0x80 && !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.is_prg_ram_enabled() => Some(self.prg_ram[usize::from(address - 0x6000) % self.prg_ram.len()]),
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        if address < 0x8000 {
+            if (0x6000..=0x7fff).contains(&address) && self.is_prg_ram_enabled() {
+                let index = usize::from(address - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            return;
+        }
+
+        match self.pins.register(address) {
+            0x8000..=0x8003 => self.prg_banks[0] = data & 0x0f,
+            0xb003 => self.banking_mode = data,
+            0xc000..=0xc003 => self.prg_banks[1] = data & 0x1f,
+            register @ (0xd000..=0xd003 | 0xe000..=0xe003) => {
+                let index = usize::from(((register >> 12) - 0x0d) * 4 + (register & 0x03));
+                self.chr_banks[index] = data;
+            }
+            0xf000 => self.irq.write_latch(data),
+            0xf001 => self.irq.write_control(data),
+            0xf002 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking_mode >> 2) & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn step(&mut self, cpu_cycles: usize) {
+        self.irq.step(cpu_cycles);
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.irq.is_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::vrc6::Vrc6;
+
+    # [test]
+    fn test_prg_banking() {
+        let mut mem = Memory::new(Box::new(Vrc6::new(&make_rom(24, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0x8000, 0x02);
+        mem.write_u8(0xc000, 0x09);
+        assert_eq!(mem.read_u8(0x8000), 4);
+        assert_eq!(mem.read_u8(0xa000), 5);
+        assert_eq!(mem.read_u8(0xc000), 9);
+        assert_eq!(mem.read_u8(0xe000), 15);
+    }
+
+    # [test]
+    fn test_chr_banking_modes() {
+        // VRC6b swaps A0 and A1, so $D002 selects R1 and $E001 selects R6.
+        let mut mem = Memory::new(Box::new(Vrc6::new(&make_rom(26, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0xd002, 0x11);
+        mem.write_u8(0xe001, 0x16);
+        mem.write_u8(0xb003, 0x00);
+        assert_eq!(mem.read_video_u8(0x0400), 0x11);
+        assert_eq!(mem.read_video_u8(0x1800), 0x16);
+
+        // 2KB banks with A10 taken from the PPU.
+        mem.write_u8(0xb003, 0x21);
+        assert_eq!(mem.read_video_u8(0x0800), 0x10);
+        assert_eq!(mem.read_video_u8(0x0c00), 0x11);
+
+        // Mixed mode: 1KB banks at $0000, 2KB banks R4 and R5 at $1000.
+        mem.write_u8(0xe000, 0x20);
+        mem.write_u8(0xb003, 0x22);
+        assert_eq!(mem.read_video_u8(0x0400), 0x11);
+        assert_eq!(mem.read_video_u8(0x1000), 0x20);
+        assert_eq!(mem.read_video_u8(0x1400), 0x21);
+    }
+
+    # [test]
+    fn test_mirroring_and_prg_ram() {
+        let mut mem = Memory::new(Box::new(Vrc6::new(&make_rom(24, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0xb003, 0x24);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Horizontal);
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.mapper.cpu_peek_u8(0x6000), None);
+
+        mem.write_u8(0xb003, 0xa8);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::SingleScreenLower);
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+    }
+
+    # [test]
+    fn test_irq() {
+        let mut mem = Memory::new(Box::new(Vrc6::new(&make_rom(24, 0, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0xf000, 0xfe);
+        mem.write_u8(0xf001, 0x06);
+        mem.mapper.step(1);
+        assert!(!mem.mapper.is_irq_pending());
+        mem.mapper.step(1);
+        assert!(mem.mapper.is_irq_pending());
+        mem.write_u8(0xf002, 0x00);
+        assert!(!mem.mapper.is_irq_pending());
+    }
+}
@@ -0,0 +1,198 @@
+use memory::mapper::{Mapper, Mirroring};
+
+use crate::Rom;
+use crate::mapper::{chr_memory, prg_ram};
+use crate::mapper::vrc::{Pins, VrcIrq};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// Mapper 85: Konami VRC7.
+/// Submapper 1 (VRC7b) selects registers with A3 and submapper 2 (VRC7a) with A4.
+/// The FM audio registers at $9010 and $9030 are left to the APU.
+#[derive(Clone)]
+pub struct Vrc7 {
+    pins: Pins,
+
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    is_chr_ram: bool,
+
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8,
+
+    irq: VrcIrq,
+}
+
+impl Vrc7 {
+    pub fn new(rom: &Rom) -> Self {
+        let select = match rom.submapper {
+            1 => 0x0008,
+            2 => 0x0010,
+            _ => 0x0018,
+        };
+        let (chr, is_chr_ram) = chr_memory(rom);
+        Self {
+            pins: Pins::new(select, 0x0000),
+            prg_rom: rom.prg_rom.clone(),
+            prg_ram: prg_ram(rom),
+            chr,
+            is_chr_ram,
+            prg_banks: [0, 0, 0],
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            control: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn prg_rom_index(&self, address: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match (address >> 13) & 0x03 {
+            3 => banks.saturating_sub(1),
+            slot => usize::from(self.prg_banks[usize::from(slot)]),
+        };
+        let offset = usize::from(address) & (PRG_BANK_SIZE - 1);
+        (bank * PRG_BANK_SIZE + offset) % self.prg_rom.len()
+    }
+
+    fn chr_index(&self, address: u16) -> usize {
+        let bank = usize::from(self.chr_banks[usize::from(address >> 10) & 0x07]);
+        let offset = usize::from(address) & (CHR_BANK_SIZE - 1);
+        (bank * CHR_BANK_SIZE + offset) % self.chr.len()
+    }
+
+    fn is_prg_ram_enabled(&self) -> bool {
+        (self.control & 0x80) == 0x80 && !self.prg_ram.is_empty()
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+        match address {
+            0x6000..=0x7fff if self.is_prg_ram_enabled() => Some(self.prg_ram[usize::from(address - 0x6000) % self.prg_ram.len()]),
+            0x8000..=0xffff => Some(self.prg_rom[self.prg_rom_index(address)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write_u8(&mut self, address: u16, data: u8) {
+        if address < 0x8000 {
+            if (0x6000..=0x7fff).contains(&address) && self.is_prg_ram_enabled() {
+                let index = usize::from(address - 0x6000) % self.prg_ram.len();
+                self.prg_ram[index] = data;
+            }
+            return;
+        }
+
+        match self.pins.register(address) {
+            0x8000 => self.prg_banks[0] = data & 0x3f,
+            0x8001 => self.prg_banks[1] = data & 0x3f,
+            0x9000 => self.prg_banks[2] = data & 0x3f,
+            register @ 0xa000..=0xd001 => {
+                let index = usize::from(((register >> 12) - 0x0a) * 2 + (register & 0x01));
+                self.chr_banks[index] = data;
+            }
+            0xe000 => self.control = data,
+            0xe001 => self.irq.write_latch(data),
+            0xf000 => self.irq.write_control(data),
+            0xf001 => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    fn ppu_read_u8(&mut self, address: u16) -> u8 {
+        self.chr[self.chr_index(address)]
+    }
+
+    fn ppu_write_u8(&mut self, address: u16, data: u8) {
+        if self.is_chr_ram {
+            let index = self.chr_index(address);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn step(&mut self, cpu_cycles: usize) {
+        self.irq.step(cpu_cycles);
+    }
+
+    fn is_irq_pending(&self) -> bool {
+        self.irq.is_pending
+    }
+
+    fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use memory::Memory;
+    use memory::mapper::Mirroring;
+    use memory::system::SystemBus;
+    use memory::system_video::VideoBus;
+    use crate::mapper::make_rom;
+    use crate::mapper::vrc7::Vrc7;
+
+    # [test]
+    fn test_register_select() {
+        let cases = [
+            // (submapper, address of the second PRG bank register)
+            (1, 0x8008),
+            (2, 0x8010),
+            (0, 0x8010),
+            (0, 0x8008),
+        ];
+        for (submapper, address) in cases {
+            let mut mem = Memory::new(Box::new(Vrc7::new(&make_rom(85, submapper, 0x2000, 16, 0x0400, 64, 7))));
+            mem.write_u8(0x8000, 0x01);
+            mem.write_u8(address, 0x02);
+            mem.write_u8(0x9000, 0x03);
+            assert_eq!(mem.read_u8(0x8000), 1);
+            assert_eq!(mem.read_u8(0xa000), 2);
+            assert_eq!(mem.read_u8(0xc000), 3);
+            assert_eq!(mem.read_u8(0xe000), 15);
+        }
+    }
+
+    # [test]
+    fn test_chr_banking() {
+        let mut mem = Memory::new(Box::new(Vrc7::new(&make_rom(85, 2, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0xa000, 0x10);
+        mem.write_u8(0xa010, 0x11);
+        mem.write_u8(0xd010, 0x17);
+        assert_eq!(mem.read_video_u8(0x0000), 0x10);
+        assert_eq!(mem.read_video_u8(0x0400), 0x11);
+        assert_eq!(mem.read_video_u8(0x1c00), 0x17);
+    }
+
+    # [test]
+    fn test_control_and_irq() {
+        let mut mem = Memory::new(Box::new(Vrc7::new(&make_rom(85, 1, 0x2000, 16, 0x0400, 64, 7))));
+        mem.write_u8(0xe000, 0x81);
+        assert_eq!(mem.mapper.mirroring(), Mirroring::Horizontal);
+        mem.write_u8(0x6000, 0x12);
+        assert_eq!(mem.read_u8(0x6000), 0x12);
+
+        mem.write_u8(0xe008, 0xff);
+        mem.write_u8(0xf000, 0x06);
+        mem.mapper.step(1);
+        assert!(mem.mapper.is_irq_pending());
+        mem.write_u8(0xf008, 0x00);
+        assert!(!mem.mapper.is_irq_pending());
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{RomError, TvSystem};
+
+pub const NSF_MAGIC: [u8; 5] = *b"NESM\x1a";
+pub const NSFE_MAGIC: [u8; 4] = *b"NSFE";
+pub const NSF_HEADER_SIZE: usize = 0x0080;
+const NSFE_CHUNK_HEADER_SIZE: usize = 8;
+
+/// Microseconds between PLAY calls when an NSFe file has no RATE chunk.
+const NTSC_PLAY_PERIOD: u16 = 16639;
+const PAL_PLAY_PERIOD: u16 = 19997;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum NsfFormat {
+    Nsf,
+    Nsfe,
+}
+
+/// A music rip: the game's sound driver and data with the addresses of its INIT and PLAY routines.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Nsf {
+    pub format: NsfFormat,
+    pub track_count: u8,
+    /// The track played first, counted from 0.
+    pub starting_track: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Who ripped the music, only given by NSFe files.
+    pub ripper: Option<String>,
+    /// Microseconds between PLAY calls.
+    pub ntsc_play_period: u16,
+    pub pal_play_period: u16,
+    pub tv_system: TvSystem,
+    /// Expansion audio: bit 0 VRC6, 1 VRC7, 2 FDS, 3 MMC5, 4 Namco 163 and 5 Sunsoft 5B.
+    pub expansion_chips: u8,
+    /// Initial values of the bank registers at $5FF8-$5FFF, `None` when the data is not bankswitched.
+    pub initial_banks: Option<[u8; 8]>,
+    /// Track names from the NSFe tlbl chunk, empty when not given.
+    pub track_labels: Vec<String>,
+    /// Track lengths in milliseconds from the NSFe time chunk, empty when not given.
+    pub track_lengths: Vec<Option<u32>>,
+    pub data: Vec<u8>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// Reads a string padded or terminated with zeros.
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&v| v == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn tv_system(region: u8) -> TvSystem {
+    if (region & 0x02) == 0x02 {
+        TvSystem::MultipleRegion
+    } else if (region & 0x01) == 0x01 {
+        TvSystem::Pal
+    } else {
+        TvSystem::Ntsc
+    }
+}
+
+/// Bank registers that are all zero mean the data is not bankswitched.
+fn initial_banks(banks: [u8; 8]) -> Option<[u8; 8]> {
+    if banks.iter().all(|&v| v == 0) { None } else { Some(banks) }
+}
+
+impl Nsf {
+    pub fn is_valid(data: &[u8]) -> bool {
+        data.starts_with(&NSF_MAGIC) || data.starts_with(&NSFE_MAGIC)
+    }
+
+    /// Parses an NSF or NSFe file.
+    pub fn new(data: &[u8]) -> Result<Self, RomError> {
+        let nsf = if data.starts_with(&NSFE_MAGIC) {
+            Nsf::parse_nsfe(data)?
+        } else {
+            Nsf::parse_nsf(data)?
+        };
+        if nsf.track_count == 0 {
+            return Err(RomError::NoNsfTracks);
+        }
+        // The data is mapped at $8000-$FFFF, the FDS expansion that maps it lower is not supported.
+        if nsf.load_address < 0x8000 {
+            return Err(RomError::BadNsfLoadAddress(nsf.load_address));
+        }
+        Ok(nsf)
+    }
+
+    fn parse_nsf(data: &[u8]) -> Result<Self, RomError> {
+        if data.len() < NSF_HEADER_SIZE {
+            return Err(RomError::TruncatedHeader(data.len()));
+        }
+        if !data.starts_with(&NSF_MAGIC) {
+            return Err(RomError::BadMagic([data[0], data[1], data[2], data[3]]));
+        }
+
+        // NSF2 may give the data length, with metadata following the data.
+        let length = usize::from(data[0x7d]) | (usize::from(data[0x7e]) << 8) | (usize::from(data[0x7f]) << 16);
+        let end = if data[0x05] >= 2 && length > 0 { (NSF_HEADER_SIZE + length).min(data.len()) } else { data.len() };
+        let mut banks = [0u8; 8];
+        banks.copy_from_slice(&data[0x70..0x78]);
+        Ok(Nsf {
+            format: NsfFormat::Nsf,
+            track_count: data[0x06],
+            starting_track: data[0x07].saturating_sub(1),
+            load_address: read_u16(data, 0x08),
+            init_address: read_u16(data, 0x0a),
+            play_address: read_u16(data, 0x0c),
+            title: read_string(&data[0x0e..0x2e]),
+            artist: read_string(&data[0x2e..0x4e]),
+            copyright: read_string(&data[0x4e..0x6e]),
+            ripper: None,
+            ntsc_play_period: read_u16(data, 0x6e),
+            pal_play_period: read_u16(data, 0x78),
+            tv_system: tv_system(data[0x7a]),
+            expansion_chips: data[0x7b],
+            initial_banks: initial_banks(banks),
+            track_labels: vec![],
+            track_lengths: vec![],
+            data: data[NSF_HEADER_SIZE..end].to_vec(),
+        })
+    }
+
+    fn parse_nsfe(data: &[u8]) -> Result<Self, RomError> {
+        let mut nsf = Nsf {
+            format: NsfFormat::Nsfe,
+            track_count: 1,
+            starting_track: 0,
+            load_address: 0,
+            init_address: 0,
+            play_address: 0,
+            title: String::new(),
+            artist: String::new(),
+            copyright: String::new(),
+            ripper: None,
+            ntsc_play_period: NTSC_PLAY_PERIOD,
+            pal_play_period: PAL_PLAY_PERIOD,
+            tv_system: TvSystem::Ntsc,
+            expansion_chips: 0,
+            initial_banks: None,
+            track_labels: vec![],
+            track_lengths: vec![],
+            data: vec![],
+        };
+        let mut has_info = false;
+        let mut has_data = false;
+
+        let mut offset = NSFE_MAGIC.len();
+        while offset < data.len() {
+            let available = data.len() - offset;
+            if available < NSFE_CHUNK_HEADER_SIZE {
+                return Err(RomError::TruncatedNsfeChunk { offset, length: NSFE_CHUNK_HEADER_SIZE, available });
+            }
+            let length = u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as usize;
+            let id = [data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]];
+            let available = available - NSFE_CHUNK_HEADER_SIZE;
+            if available < length {
+                return Err(RomError::TruncatedNsfeChunk { offset, length, available });
+            }
+            let body = &data[offset + NSFE_CHUNK_HEADER_SIZE..offset + NSFE_CHUNK_HEADER_SIZE + length];
+
+            match &id {
+                b"INFO" => {
+                    if body.len() < 9 {
+                        return Err(RomError::TruncatedNsfeChunk { offset, length: 9, available: body.len() });
+                    }
+                    nsf.load_address = read_u16(body, 0);
+                    nsf.init_address = read_u16(body, 2);
+                    nsf.play_address = read_u16(body, 4);
+                    nsf.tv_system = tv_system(body[6]);
+                    nsf.expansion_chips = body[7];
+                    nsf.track_count = body.get(8).copied().unwrap_or(1);
+                    nsf.starting_track = body.get(9).copied().unwrap_or(0);
+                    has_info = true;
+                }
+                b"DATA" => {
+                    nsf.data = body.to_vec();
+                    has_data = true;
+                }
+                b"BANK" => {
+                    let mut banks = [0u8; 8];
+                    for (bank, &value) in banks.iter_mut().zip(body) {
+                        *bank = value;
+                    }
+                    nsf.initial_banks = initial_banks(banks);
+                }
+                b"RATE" => {
+                    if body.len() >= 2 {
+                        nsf.ntsc_play_period = read_u16(body, 0);
+                    }
+                    if body.len() >= 4 {
+                        nsf.pal_play_period = read_u16(body, 2);
+                    }
+                }
+                b"auth" => {
+                    let mut strings = body.split(|&v| v == 0).map(read_string);
+                    nsf.title = strings.next().unwrap_or_default();
+                    nsf.artist = strings.next().unwrap_or_default();
+                    nsf.copyright = strings.next().unwrap_or_default();
+                    nsf.ripper = strings.next().filter(|ripper| !ripper.is_empty());
+                }
+                b"tlbl" => {
+                    let labels = body.strip_suffix(&[0]).unwrap_or(body);
+                    nsf.track_labels = labels.split(|&v| v == 0).map(read_string).collect();
+                }
+                b"time" => {
+                    nsf.track_lengths = body.chunks_exact(4)
+                        .map(|v| i32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+                        .map(|v| u32::try_from(v).ok())
+                        .collect();
+                }
+                b"NEND" => break,
+                // Chunks starting with an upper-case letter are required to play the file.
+                _ if id[0].is_ascii_uppercase() => return Err(RomError::UnsupportedNsfeChunk(id)),
+                _ => {}
+            }
+            offset += NSFE_CHUNK_HEADER_SIZE + length;
+        }
+
+        if !has_info {
+            return Err(RomError::MissingNsfeChunk(*b"INFO"));
+        }
+        if !has_data {
+            return Err(RomError::MissingNsfeChunk(*b"DATA"));
+        }
+        Ok(nsf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{RomError, TvSystem};
+    use crate::nsf::{Nsf, NsfFormat};
+
+    fn make_nsf(banks: [u8; 8]) -> Vec<u8> {
+        let mut data = b"NESM\x1a\x01\x05\x02".to_vec();
+        data.extend([0x00, 0x80, 0x03, 0x80, 0x06, 0x80]);
+        for text in [&b"Title"[..], b"Artist", b"Copyright"] {
+            let mut field = text.to_vec();
+            field.resize(32, 0);
+            data.extend(field);
+        }
+        data.extend(16639u16.to_le_bytes());
+        data.extend(banks);
+        data.extend(19997u16.to_le_bytes());
+        data.extend([0x02, 0x01, 0x00, 0x00, 0x00, 0x00]);
+        data.extend([0x60u8; 0x1000]);
+        data
+    }
+
+    fn make_chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = (body.len() as u32).to_le_bytes().to_vec();
+        chunk.extend(id);
+        chunk.extend(body);
+        chunk
+    }
+
+    # [test]
+    fn test_parse_nsf() {
+        let nsf = Nsf::new(&make_nsf([0; 8])).unwrap();
+        assert_eq!(nsf.format, NsfFormat::Nsf);
+        assert_eq!(nsf.track_count, 5);
+        assert_eq!(nsf.starting_track, 1);
+        assert_eq!((nsf.load_address, nsf.init_address, nsf.play_address), (0x8000, 0x8003, 0x8006));
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!(nsf.copyright, "Copyright");
+        assert_eq!((nsf.ntsc_play_period, nsf.pal_play_period), (16639, 19997));
+        assert_eq!(nsf.tv_system, TvSystem::MultipleRegion);
+        assert_eq!(nsf.expansion_chips, 0x01);
+        assert_eq!(nsf.initial_banks, None);
+        assert_eq!(nsf.data.len(), 0x1000);
+
+        let nsf = Nsf::new(&make_nsf([0, 1, 2, 3, 4, 5, 6, 7])).unwrap();
+        assert_eq!(nsf.initial_banks, Some([0, 1, 2, 3, 4, 5, 6, 7]));
+    }
+
+    # [test]
+    fn test_parse_nsfe() {
+        let mut data = b"NSFE".to_vec();
+        data.extend(make_chunk(b"INFO", &[0x00, 0x80, 0x03, 0x80, 0x06, 0x80, 0x01, 0x00, 0x03, 0x02]));
+        data.extend(make_chunk(b"BANK", &[0x00, 0x01]));
+        data.extend(make_chunk(b"RATE", &[0x0a, 0x41]));
+        data.extend(make_chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0"));
+        data.extend(make_chunk(b"tlbl", b"One\0Two\0Three\0"));
+        data.extend(make_chunk(b"time", &[0x10, 0x27, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]));
+        data.extend(make_chunk(b"DATA", &[0x60u8; 0x100]));
+        data.extend(make_chunk(b"NEND", &[]));
+
+        let nsf = Nsf::new(&data).unwrap();
+        assert_eq!(nsf.format, NsfFormat::Nsfe);
+        assert_eq!(nsf.track_count, 3);
+        assert_eq!(nsf.starting_track, 2);
+        assert_eq!(nsf.tv_system, TvSystem::Pal);
+        assert_eq!(nsf.initial_banks, Some([0, 1, 0, 0, 0, 0, 0, 0]));
+        assert_eq!((nsf.ntsc_play_period, nsf.pal_play_period), (0x410a, 19997));
+        assert_eq!(nsf.ripper.as_deref(), Some("Ripper"));
+        assert_eq!(nsf.track_labels, vec!["One", "Two", "Three"]);
+        assert_eq!(nsf.track_lengths, vec![Some(10000), None]);
+        assert_eq!(nsf.data.len(), 0x100);
+    }
+
+    # [test]
+    fn test_errors() {
+        let mut zero_tracks = make_nsf([0; 8]);
+        zero_tracks[0x06] = 0;
+        let mut low_load = make_nsf([0; 8]);
+        low_load[0x09] = 0x60;
+        let mut truncated = b"NSFE".to_vec();
+        truncated.extend(make_chunk(b"DATA", &[0x60u8; 0x10]));
+        truncated.truncate(0x10);
+        let mut unknown = b"NSFE".to_vec();
+        unknown.extend(make_chunk(b"VRC7", &[]));
+        let mut optional = b"NSFE".to_vec();
+        optional.extend(make_chunk(b"psfx", &[]));
+        optional.extend(make_chunk(b"DATA", &[0x60u8; 0x10]));
+
+        let cases = [
+            (b"NESM\x1a".to_vec(), RomError::TruncatedHeader(5)),
+            (zero_tracks, RomError::NoNsfTracks),
+            (low_load, RomError::BadNsfLoadAddress(0x6000)),
+            (truncated, RomError::TruncatedNsfeChunk { offset: 4, length: 0x10, available: 4 }),
+            (unknown, RomError::UnsupportedNsfeChunk(*b"VRC7")),
+            (optional, RomError::MissingNsfeChunk(*b"INFO")),
+        ];
+        for (data, error) in cases {
+            assert_eq!(Nsf::new(&data).err(), Some(error));
+        }
+    }
+}
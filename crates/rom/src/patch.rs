@@ -0,0 +1,489 @@
+use crate::PatchError;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+/// IPS offsets are 24 bits wide and record sizes 16 bits wide.
+const IPS_MAX_OFFSET: usize = 0xff_ffff;
+const IPS_MAX_RECORD_SIZE: usize = 0xffff;
+/// The record offset that reads as "EOF".
+const IPS_EOF_OFFSET: usize = 0x45_4f46;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+/// Largest image a UPS patch may produce, far above any cartridge. UPS pads the target with zeros,
+/// so its size is only bounded by the number in the patch.
+const UPS_MAX_TARGET_SIZE: usize = 0x1000_0000;
+/// UPS and BPS end with the CRC32 of the source, the target and the patch itself.
+const FOOTER_SIZE: usize = 12;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        if patch.starts_with(IPS_MAGIC) {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(UPS_MAGIC) {
+            Some(PatchFormat::Ups)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+/// Applies an IPS, UPS or BPS patch to `source` and returns the patched copy.
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(source, patch),
+        Some(PatchFormat::Ups) => apply_ups(source, patch),
+        Some(PatchFormat::Bps) => apply_bps(source, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+/// Reads patch bytes while tracking the position for error reports.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        Self { data, position }
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], PatchError> {
+        let end = self.position.checked_add(length)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(PatchError::Truncated(self.position))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, PatchError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_be(&mut self, length: usize) -> Result<usize, PatchError> {
+        Ok(self.read_bytes(length)?.iter().fold(0, |value, &v| (value << 8) | usize::from(v)))
+    }
+
+    /// Reads the variable-length integer shared by UPS and BPS.
+    fn read_number(&mut self) -> Result<usize, PatchError> {
+        let start = self.position;
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let v = self.read_u8()?;
+            value = usize::from(v & 0x7f).checked_mul(shift)
+                .and_then(|v| v.checked_add(value))
+                .ok_or(PatchError::Corrupt(start))?;
+            if (v & 0x80) == 0x80 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::Corrupt(start))?;
+            value = value.checked_add(shift).ok_or(PatchError::Corrupt(start))?;
+        }
+    }
+}
+
+fn apply_ips(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = source.to_vec();
+    let mut reader = Reader::new(patch, IPS_MAGIC.len());
+    loop {
+        if reader.read_bytes(IPS_EOF.len())? == IPS_EOF {
+            break;
+        }
+        reader.position -= IPS_EOF.len();
+        let offset = reader.read_be(3)?;
+        let (length, data) = match reader.read_be(2)? {
+            // A size of 0 marks a run of one repeated byte.
+            0 => {
+                let length = reader.read_be(2)?;
+                (length, None)
+            }
+            length => (length, Some(reader.read_bytes(length)?)),
+        };
+        if target.len() < offset + length {
+            target.resize(offset + length, 0);
+        }
+        match data {
+            Some(data) => target[offset..offset + length].copy_from_slice(data),
+            None => {
+                let value = reader.read_u8()?;
+                target[offset..offset + length].fill(value);
+            }
+        }
+    }
+    // An optional 3-byte size after EOF truncates the target.
+    if reader.position + 3 == patch.len() {
+        let size = reader.read_be(3)?;
+        target.truncate(size);
+    }
+    Ok(target)
+}
+
+/// Builds an IPS patch that turns `source` into `target`.
+/// Fails with `OutOfBounds` when a change lies past the 16MB an IPS offset can address.
+pub fn create_ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut patch = IPS_MAGIC.to_vec();
+    let mut offset = 0;
+    while offset < target.len() {
+        if source.get(offset) == Some(&target[offset]) {
+            offset += 1;
+            continue;
+        }
+        // A record at the offset spelling "EOF" would end the patch, so it starts one byte earlier.
+        let start = if offset == IPS_EOF_OFFSET { offset - 1 } else { offset };
+        if start > IPS_MAX_OFFSET {
+            return Err(PatchError::OutOfBounds(start));
+        }
+        let mut end = offset;
+        while end < target.len() && end - start < IPS_MAX_RECORD_SIZE && source.get(end) != Some(&target[end]) {
+            end += 1;
+        }
+        patch.extend(&(start as u32).to_be_bytes()[1..]);
+        patch.extend(((end - start) as u16).to_be_bytes());
+        patch.extend(&target[start..end]);
+        offset = end;
+    }
+    patch.extend(IPS_EOF);
+    if target.len() < source.len() {
+        if target.len() > IPS_MAX_OFFSET {
+            return Err(PatchError::OutOfBounds(target.len()));
+        }
+        patch.extend(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+/// Reads a little-endian CRC32 from the footer.
+fn footer_crc32(patch: &[u8], index: usize) -> u32 {
+    let offset = patch.len() - FOOTER_SIZE + index * 4;
+    u32::from_le_bytes([patch[offset], patch[offset + 1], patch[offset + 2], patch[offset + 3]])
+}
+
+/// Checks the patch and source checksums of a UPS or BPS patch.
+fn verify_header(source: &[u8], patch: &[u8], magic: &[u8]) -> Result<(), PatchError> {
+    if patch.len() < magic.len() + FOOTER_SIZE {
+        return Err(PatchError::Truncated(patch.len()));
+    }
+    let expected = footer_crc32(patch, 2);
+    let actual = crc32fast::hash(&patch[..patch.len() - 4]);
+    if expected != actual {
+        return Err(PatchError::PatchChecksumMismatch { expected, actual });
+    }
+    let expected = footer_crc32(patch, 0);
+    let actual = crc32fast::hash(source);
+    if expected != actual {
+        return Err(PatchError::SourceChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+fn verify_target(target: &[u8], patch: &[u8]) -> Result<(), PatchError> {
+    let expected = footer_crc32(patch, 1);
+    let actual = crc32fast::hash(target);
+    if expected != actual {
+        return Err(PatchError::TargetChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+fn verify_source_size(source: &[u8], expected: usize) -> Result<(), PatchError> {
+    if source.len() != expected {
+        return Err(PatchError::SourceSizeMismatch { expected, actual: source.len() });
+    }
+    Ok(())
+}
+
+fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    verify_header(source, patch, UPS_MAGIC)?;
+    let end = patch.len() - FOOTER_SIZE;
+    let mut reader = Reader::new(&patch[..end], UPS_MAGIC.len());
+    verify_source_size(source, reader.read_number()?)?;
+    let target_size = reader.read_number()?;
+    if target_size > UPS_MAX_TARGET_SIZE {
+        return Err(PatchError::OutOfBounds(target_size));
+    }
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+    let mut offset: usize = 0;
+    while reader.position < end {
+        offset = offset.checked_add(reader.read_number()?).ok_or(PatchError::Corrupt(reader.position))?;
+        // XOR bytes until a 0 terminator, which also skips one byte.
+        loop {
+            let v = reader.read_u8()?;
+            if v == 0 {
+                offset += 1;
+                break;
+            }
+            let byte = target.get_mut(offset).ok_or(PatchError::OutOfBounds(offset))?;
+            *byte ^= v;
+            offset += 1;
+        }
+    }
+
+    verify_target(&target, patch)?;
+    Ok(target)
+}
+
+fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    verify_header(source, patch, BPS_MAGIC)?;
+    let end = patch.len() - FOOTER_SIZE;
+    let mut reader = Reader::new(&patch[..end], BPS_MAGIC.len());
+    verify_source_size(source, reader.read_number()?)?;
+    let target_size = reader.read_number()?;
+    let metadata_size = reader.read_number()?;
+    reader.read_bytes(metadata_size)?;
+
+    let mut target: Vec<u8> = Vec::with_capacity(target_size.min(patch.len() + source.len()));
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    // Applies a signed relative move encoded with the sign in the lowest bit.
+    let relative = |offset: usize, value: usize, position: usize| {
+        let distance = value >> 1;
+        let moved = if (value & 0x01) == 0x01 { offset.checked_sub(distance) } else { offset.checked_add(distance) };
+        moved.ok_or(PatchError::Corrupt(position))
+    };
+    while reader.position < end {
+        let position = reader.position;
+        let data = reader.read_number()?;
+        let length = (data >> 2) + 1;
+        if target.len() + length > target_size {
+            return Err(PatchError::OutOfBounds(target.len() + length));
+        }
+        match data & 0x03 {
+            // SourceRead: the source byte at the same offset as the output.
+            0 => {
+                let start = target.len();
+                let bytes = source.get(start..start + length).ok_or(PatchError::OutOfBounds(start + length))?;
+                target.extend_from_slice(bytes);
+            }
+            // TargetRead: bytes stored in the patch.
+            1 => target.extend_from_slice(reader.read_bytes(length)?),
+            // SourceCopy: bytes from anywhere in the source.
+            2 => {
+                source_offset = relative(source_offset, reader.read_number()?, position)?;
+                let bytes = source.get(source_offset..source_offset + length).ok_or(PatchError::OutOfBounds(source_offset + length))?;
+                target.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // TargetCopy: bytes already written, which may overlap the bytes being written.
+            _ => {
+                target_offset = relative(target_offset, reader.read_number()?, position)?;
+                if target_offset >= target.len() {
+                    return Err(PatchError::OutOfBounds(target_offset));
+                }
+                for _ in 0..length {
+                    target.push(target[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if target.len() != target_size {
+        return Err(PatchError::Truncated(end));
+    }
+
+    verify_target(&target, patch)?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PatchError;
+    use crate::patch::{apply, create_ips, PatchFormat};
+
+    fn encode_number(mut value: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let v = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(0x80 | v);
+                return bytes;
+            }
+            bytes.push(v);
+            value -= 1;
+        }
+    }
+
+    // Appends the source, target and patch CRC32 footer.
+    fn finish(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32fast::hash(source).to_le_bytes());
+        patch.extend(crc32fast::hash(target).to_le_bytes());
+        let crc32 = crc32fast::hash(&patch);
+        patch.extend(crc32.to_le_bytes());
+        patch
+    }
+
+    # [test]
+    fn test_detect() {
+        assert_eq!(PatchFormat::detect(b"PATCHEOF"), Some(PatchFormat::Ips));
+        assert_eq!(PatchFormat::detect(b"UPS1"), Some(PatchFormat::Ups));
+        assert_eq!(PatchFormat::detect(b"BPS1"), Some(PatchFormat::Bps));
+        assert_eq!(PatchFormat::detect(b"NES\x1a"), None);
+        assert_eq!(apply(&[], b"xdelta"), Err(PatchError::UnknownFormat));
+    }
+
+    # [test]
+    fn test_number_encoding() {
+        for value in [0, 1, 0x7f, 0x80, 0x4080, 0x12345678] {
+            let mut patch = b"UPS1".to_vec();
+            patch.extend(encode_number(value));
+            let mut reader = super::Reader::new(&patch, 4);
+            assert_eq!(reader.read_number(), Ok(value));
+        }
+    }
+
+    # [test]
+    fn test_ips() {
+        let source = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // Two bytes at 1, a run of four $FF at 4 and two bytes growing the file at 9.
+        patch.extend([0x00, 0x00, 0x01, 0x00, 0x02, 0x12, 0x34]);
+        patch.extend([0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x04, 0xff]);
+        patch.extend([0x00, 0x00, 0x09, 0x00, 0x02, 0x56, 0x78]);
+        patch.extend(b"EOF");
+        assert_eq!(apply(&source, &patch).unwrap(), vec![0, 0x12, 0x34, 0, 0xff, 0xff, 0xff, 0xff, 0, 0x56, 0x78]);
+
+        // Truncation to 3 bytes after EOF.
+        patch.extend([0x00, 0x00, 0x03]);
+        assert_eq!(apply(&source, &patch).unwrap(), vec![0, 0x12, 0x34]);
+
+        assert_eq!(apply(&source, b"PATCH\x00\x00\x01\x00\x02\x12"), Err(PatchError::Truncated(10)));
+        assert_eq!(apply(&source, b"PATCH"), Err(PatchError::Truncated(5)));
+    }
+
+    # [test]
+    fn test_create_ips() {
+        let source = vec![0u8; 0x20000];
+        let mut changed = source.clone();
+        changed[3] = 0xff;
+        changed[0x100..0x10200].fill(0xee);
+        let mut grown = source.clone();
+        grown.extend([0x00, 0x00]);
+        let cases = [
+            (source.clone(), 8),
+            (changed, 8 + 6 + 5 + 0xffff + 5 + 0x101),
+            (grown, 8 + 7),
+            (source[..0x1000].to_vec(), 8 + 3),
+        ];
+        for (target, size) in cases {
+            let patch = create_ips(&source, &target).unwrap();
+            assert_eq!(patch.len(), size);
+            assert_eq!(apply(&source, &patch).unwrap(), target);
+        }
+
+        // A change at the offset spelling "EOF" is written from the byte before it.
+        let source = vec![0u8; 0x45_4f50];
+        let mut target = source.clone();
+        target[0x45_4f46] = 0x01;
+        let patch = create_ips(&source, &target).unwrap();
+        assert_eq!(patch[5..10], [0x45, 0x4f, 0x45, 0x00, 0x02]);
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+    }
+
+    # [test]
+    fn test_ups() {
+        let source = vec![0x10u8, 0x20, 0x30, 0x40];
+        let target = vec![0x10u8, 0x21, 0x30, 0x40, 0x50, 0x60];
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(target.len()));
+        // Skip 1 and XOR $01 at 1, then skip 1 after the terminator and write 2 bytes past the source.
+        patch.extend(encode_number(1));
+        patch.extend([0x01, 0x00]);
+        patch.extend(encode_number(1));
+        patch.extend([0x50, 0x60, 0x00]);
+        let patch = finish(patch, &source, &target);
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+
+        let other = vec![0x00u8; 4];
+        assert_eq!(apply(&other, &patch), Err(PatchError::SourceChecksumMismatch {
+            expected: crc32fast::hash(&source),
+            actual: crc32fast::hash(&other),
+        }));
+
+        let mut corrupt = patch.clone();
+        corrupt[8] ^= 0xff;
+        assert!(matches!(apply(&source, &corrupt), Err(PatchError::PatchChecksumMismatch { .. })));
+    }
+
+    # [test]
+    fn test_ups_target_checksum() {
+        let source = vec![0x10u8, 0x20];
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(encode_number(2));
+        patch.extend(encode_number(2));
+        patch.extend(encode_number(0));
+        patch.extend([0x01, 0x00]);
+        let patch = finish(patch, &source, &[0x00, 0x00]);
+        assert_eq!(apply(&source, &patch), Err(PatchError::TargetChecksumMismatch {
+            expected: crc32fast::hash(&[0x00, 0x00]),
+            actual: crc32fast::hash(&[0x11, 0x20]),
+        }));
+    }
+
+    # [test]
+    fn test_ups_target_too_large() {
+        let source = vec![0x10u8, 0x20];
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(encode_number(2));
+        patch.extend(encode_number(1 << 50));
+        let patch = finish(patch, &source, &[]);
+        assert_eq!(apply(&source, &patch), Err(PatchError::OutOfBounds(1 << 50)));
+    }
+
+    # [test]
+    fn test_bps() {
+        let source = b"ABCDEFGH".to_vec();
+        let target = b"ABxyFGHGHGHGAB".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(target.len()));
+        patch.extend(encode_number(3));
+        patch.extend(b"abc");
+        // SourceRead "AB".
+        patch.extend(encode_number(1 << 2));
+        // TargetRead "xy".
+        patch.extend(encode_number((1 << 2) | 1));
+        patch.extend(b"xy");
+        // SourceCopy "FGH" from +5.
+        patch.extend(encode_number((2 << 2) | 2));
+        patch.extend(encode_number(5 << 1));
+        // TargetCopy "GHGHG" from +5, overlapping its own output.
+        patch.extend(encode_number((4 << 2) | 3));
+        patch.extend(encode_number(5 << 1));
+        // SourceCopy "AB" from -8.
+        patch.extend(encode_number((1 << 2) | 2));
+        patch.extend(encode_number((8 << 1) | 1));
+        let patch = finish(patch, &source, &target);
+        assert_eq!(apply(&source, &patch).unwrap(), target);
+
+        let short = b"ABCD".to_vec();
+        assert!(matches!(apply(&short, &patch), Err(PatchError::SourceChecksumMismatch { .. })));
+    }
+
+    # [test]
+    fn test_bps_out_of_bounds() {
+        let source = b"AB".to_vec();
+        let target = b"ABAB".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(encode_number(2));
+        patch.extend(encode_number(4));
+        patch.extend(encode_number(0));
+        // SourceRead of 4 bytes from a 2-byte source.
+        patch.extend(encode_number(3 << 2));
+        let patch = finish(patch, &source, &target);
+        assert_eq!(apply(&source, &patch), Err(PatchError::OutOfBounds(4)));
+    }
+}
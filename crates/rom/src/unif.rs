@@ -0,0 +1,296 @@
+use crate::{ConsoleType, HeaderFormat, Mirroring, Rom, RomError, TvSystem};
+use crate::CHR_ROM_BANK_SIZE;
+
+pub const UNIF_MAGIC: [u8; 4] = *b"UNIF";
+pub const UNIF_HEADER_SIZE: usize = 0x0020;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+/// Prefixes naming the board manufacturer, which do not change the hardware.
+const BOARD_PREFIXES: [&str; 6] = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-", "IREM-"];
+
+/// Board names without their manufacturer prefix, their iNES mapper and submapper and their PRG-RAM size.
+const BOARDS: &[(&str, u16, u8, usize)] = &[
+    ("NROM", 0, 0, 0),
+    ("NROM-128", 0, 0, 0),
+    ("NROM-256", 0, 0, 0),
+    ("RROM", 0, 0, 0),
+    ("RROM-128", 0, 0, 0),
+    ("SAROM", 1, 0, 0x2000),
+    ("SBROM", 1, 0, 0),
+    ("SCROM", 1, 0, 0),
+    ("SEROM", 1, 0, 0),
+    ("SFROM", 1, 0, 0),
+    ("SGROM", 1, 0, 0),
+    ("SHROM", 1, 0, 0),
+    ("SJROM", 1, 0, 0x2000),
+    ("SKROM", 1, 0, 0x2000),
+    ("SLROM", 1, 0, 0),
+    ("SL1ROM", 1, 0, 0),
+    ("SNROM", 1, 0, 0x2000),
+    ("SOROM", 1, 0, 0x4000),
+    ("SUROM", 1, 0, 0x2000),
+    ("SXROM", 1, 0, 0x8000),
+    ("UNROM", 2, 0, 0),
+    ("UOROM", 2, 0, 0),
+    ("CNROM", 3, 0, 0),
+    ("TBROM", 4, 0, 0),
+    ("TEROM", 4, 0, 0),
+    ("TFROM", 4, 0, 0),
+    ("TGROM", 4, 0, 0),
+    ("TKROM", 4, 0, 0x2000),
+    ("TLROM", 4, 0, 0),
+    ("TL1ROM", 4, 0, 0),
+    ("TR1ROM", 4, 0, 0),
+    ("TSROM", 4, 0, 0x2000),
+    ("TVROM", 4, 0, 0),
+    ("HKROM", 4, 1, 0x0400),
+    ("EKROM", 5, 0, 0x2000),
+    ("ELROM", 5, 0, 0),
+    ("ETROM", 5, 0, 0x4000),
+    ("EWROM", 5, 0, 0x8000),
+    ("AMROM", 7, 0, 0),
+    ("ANROM", 7, 0, 0),
+    ("AN1ROM", 7, 0, 0),
+    ("AOROM", 7, 0, 0),
+    ("COLORDREAMS-74*377", 11, 0, 0),
+    ("BNROM", 34, 2, 0),
+    ("AVE-NINA-01", 34, 1, 0x2000),
+    ("GNROM", 66, 0, 0),
+    ("MHROM", 66, 0, 0),
+];
+
+/// Returns the iNES mapper and submapper for a UNIF board name.
+pub fn board_mapper(board: &str) -> Option<(u16, u8)> {
+    find_board(board).map(|(mapper, submapper, _)| (mapper, submapper))
+}
+
+fn find_board(board: &str) -> Option<(u16, u8, usize)> {
+    let name = BOARD_PREFIXES.iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+    BOARDS.iter()
+        .find(|(candidate, _, _, _)| candidate.eq_ignore_ascii_case(name))
+        .map(|&(_, mapper, submapper, prg_ram_bytes)| (mapper, submapper, prg_ram_bytes))
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|&v| v == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// Parses a UNIF image into the same cartridge model an equivalent iNES image produces.
+pub(crate) fn parse(data: &[u8]) -> Result<Rom, RomError> {
+    if data.len() < UNIF_HEADER_SIZE {
+        return Err(RomError::TruncatedHeader(data.len()));
+    }
+
+    let mut board = None;
+    let mut name = None;
+    let mut prg_chunks: [Vec<u8>; 16] = Default::default();
+    let mut chr_chunks: [Vec<u8>; 16] = Default::default();
+    let mut mirroring = Mirroring::Horizontal;
+    let mut has_battery = false;
+    let mut tv_system = TvSystem::Ntsc;
+
+    let mut offset = UNIF_HEADER_SIZE;
+    while offset < data.len() {
+        let available = data.len() - offset;
+        if available < CHUNK_HEADER_SIZE {
+            return Err(RomError::TruncatedUnifChunk { offset, length: CHUNK_HEADER_SIZE, available });
+        }
+        let id = &data[offset..offset + 4];
+        let length = u32::from_le_bytes([data[offset + 4], data[offset + 5], data[offset + 6], data[offset + 7]]) as usize;
+        let available = available - CHUNK_HEADER_SIZE;
+        if available < length {
+            return Err(RomError::TruncatedUnifChunk { offset, length, available });
+        }
+        let body = &data[offset + CHUNK_HEADER_SIZE..offset + CHUNK_HEADER_SIZE + length];
+
+        // The last character of PRGn and CHRn is a hexadecimal chunk index.
+        let index = char::from(id[3]).to_digit(16).map(|v| v as usize);
+        match (&id[..3], id, index) {
+            (b"PRG", _, Some(index)) => prg_chunks[index] = body.to_vec(),
+            (b"CHR", _, Some(index)) => chr_chunks[index] = body.to_vec(),
+            (_, b"MAPR", _) => board = Some(read_string(body)),
+            (_, b"NAME", _) => name = Some(read_string(body)),
+            (_, b"BATR", _) => has_battery = true,
+            (_, b"MIRR", _) => {
+                mirroring = match body.first() {
+                    Some(1) => Mirroring::Vertical,
+                    Some(2) => Mirroring::SingleScreenLower,
+                    Some(3) => Mirroring::SingleScreenUpper,
+                    Some(4) => Mirroring::FourScreen,
+                    // 5 leaves mirroring to the mapper.
+                    _ => Mirroring::Horizontal,
+                }
+            }
+            (_, b"TVCI", _) => {
+                tv_system = match body.first() {
+                    Some(1) => TvSystem::Pal,
+                    Some(2) => TvSystem::MultipleRegion,
+                    _ => TvSystem::Ntsc,
+                }
+            }
+            _ => {}
+        }
+        offset += CHUNK_HEADER_SIZE + length;
+    }
+
+    let board = board.ok_or(RomError::MissingUnifBoard)?;
+    let (mapper, submapper, board_prg_ram_bytes) = find_board(&board).ok_or(RomError::UnsupportedUnifBoard(board))?;
+    let prg_rom = prg_chunks.concat();
+    let chr_rom = chr_chunks.concat();
+    if prg_rom.is_empty() {
+        return Err(RomError::EmptyPrgRom);
+    }
+
+    Ok(Rom {
+        format: HeaderFormat::Unif,
+        prg_rom_bytes: prg_rom.len(),
+        chr_rom_bytes: chr_rom.len(),
+        // The board RAM is battery-backed when the file has a BATR chunk.
+        prg_ram_bytes: if has_battery { 0 } else { board_prg_ram_bytes },
+        prg_nvram_bytes: if has_battery { board_prg_ram_bytes } else { 0 },
+        chr_ram_bytes: if chr_rom.is_empty() { CHR_ROM_BANK_SIZE } else { 0 },
+        chr_nvram_bytes: 0,
+        mapper,
+        submapper,
+        mirroring,
+        has_battery,
+        has_trainer: false,
+        tv_system,
+        console_type: ConsoleType::Nes,
+        expansion_device: 0,
+        name,
+        trainer: vec![],
+        prg_rom,
+        chr_rom,
+        disk_sides: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{HeaderFormat, Mirroring, Rom, RomError, TvSystem};
+    use crate::mapper::mmc1::{Board, Mmc1};
+    use crate::unif::board_mapper;
+
+    fn make_chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((body.len() as u32).to_le_bytes());
+        chunk.extend(body);
+        chunk
+    }
+
+    fn make_unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"UNIF".to_vec();
+        data.extend(7u32.to_le_bytes());
+        data.extend([0u8; 24]);
+        for chunk in chunks {
+            data.extend(chunk);
+        }
+        data
+    }
+
+    # [test]
+    fn test_board_mapper() {
+        let cases = [
+            ("NES-NROM-256", Some((0, 0))),
+            ("HVC-SNROM", Some((1, 0))),
+            ("NES-TLROM", Some((4, 0))),
+            ("NES-HKROM", Some((4, 1))),
+            ("UNL-BNROM", Some((34, 2))),
+            ("AVE-NINA-01", Some((34, 1))),
+            ("NES-UOROM", Some((2, 0))),
+            ("UNL-UNKNOWN", None),
+        ];
+        for (board, mapper) in cases {
+            assert_eq!(board_mapper(board), mapper);
+        }
+    }
+
+    # [test]
+    fn test_parse_chunks() {
+        let data = make_unif(&[
+            make_chunk(b"MAPR", b"NES-SNROM\0"),
+            make_chunk(b"NAME", b"Homebrew\0"),
+            // PRG chunks are joined by index, not by file order.
+            make_chunk(b"PRG1", &[0x22u8; 0x4000]),
+            make_chunk(b"PRG0", &[0x11u8; 0x4000]),
+            make_chunk(b"CHR0", &[0x33u8; 0x2000]),
+            make_chunk(b"MIRR", &[0x01]),
+            make_chunk(b"BATR", &[0x01]),
+            make_chunk(b"TVCI", &[0x01]),
+            make_chunk(b"DINF", &[0x00; 204]),
+        ]);
+        let rom = Rom::new(&data).unwrap();
+        assert_eq!(rom.format, HeaderFormat::Unif);
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.name.as_deref(), Some("Homebrew"));
+        assert_eq!(rom.prg_rom_bytes, 0x8000);
+        assert_eq!(rom.prg_rom[0x3fff], 0x11);
+        assert_eq!(rom.prg_rom[0x4000], 0x22);
+        assert_eq!(rom.chr_rom_bytes, 0x2000);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert!(rom.has_battery);
+        assert_eq!(rom.tv_system, TvSystem::Pal);
+    }
+
+    # [test]
+    fn test_same_model_as_ines() {
+        let unif = make_unif(&[
+            make_chunk(b"MAPR", b"NES-UNROM\0"),
+            make_chunk(b"PRG0", &[0xaau8; 0x20000]),
+            make_chunk(b"MIRR", &[0x01]),
+        ]);
+        // NES 2.0 header, since iNES cannot tell that UNROM has no PRG-RAM.
+        let mut ines = vec![0x4e, 0x45, 0x53, 0x1a, 0x08, 0x00, 0x21, 0x08, 0, 0, 0x00, 0x07, 0, 0, 0, 0];
+        ines.extend(vec![0xaau8; 0x20000]);
+
+        let unif = Rom::new(&unif).unwrap();
+        let ines = Rom::new(&ines).unwrap();
+        assert_eq!(unif.mapper, ines.mapper);
+        assert_eq!(unif.submapper, ines.submapper);
+        assert_eq!(unif.mirroring, ines.mirroring);
+        assert_eq!(unif.prg_ram_bytes, ines.prg_ram_bytes);
+        assert_eq!(unif.chr_ram_bytes, ines.chr_ram_bytes);
+        assert_eq!(unif.prg_rom, ines.prg_rom);
+        assert_eq!(unif.chr_rom, ines.chr_rom);
+    }
+
+    # [test]
+    fn test_mmc1_board_prg_ram() {
+        let cases = [
+            (&b"NES-SNROM\0"[..], 0x40000, Board::Snrom),
+            (b"NES-SOROM\0", 0x40000, Board::Sorom),
+            (b"NES-SUROM\0", 0x80000, Board::Surom),
+            (b"NES-SXROM\0", 0x80000, Board::Sxrom),
+        ];
+        for (board, prg_rom_bytes, expected) in cases {
+            let data = make_unif(&[
+                make_chunk(b"MAPR", board),
+                make_chunk(b"PRG0", &vec![0u8; prg_rom_bytes]),
+                make_chunk(b"BATR", &[0x01]),
+            ]);
+            let rom = Rom::new(&data).unwrap();
+            assert_eq!(rom.prg_ram_bytes, 0);
+            assert_eq!(Mmc1::new(&rom).board(), expected);
+        }
+    }
+
+    # [test]
+    fn test_errors() {
+        let mut truncated = make_unif(&[make_chunk(b"MAPR", b"NES-NROM\0")]);
+        truncated.extend(b"PRG0\x00\x40\x00\x00\x00");
+        let cases = [
+            (b"UNIF".to_vec(), RomError::TruncatedHeader(4)),
+            (make_unif(&[make_chunk(b"PRG0", &[0u8; 0x4000])]), RomError::MissingUnifBoard),
+            (make_unif(&[make_chunk(b"MAPR", b"UNL-UNKNOWN\0")]), RomError::UnsupportedUnifBoard("UNL-UNKNOWN".to_string())),
+            (make_unif(&[make_chunk(b"MAPR", b"NES-NROM\0")]), RomError::EmptyPrgRom),
+            (truncated, RomError::TruncatedUnifChunk { offset: 0x31, length: 0x4000, available: 1 }),
+        ];
+        for (data, error) in cases {
+            assert_eq!(Rom::new(&data).err(), Some(error));
+        }
+    }
+}
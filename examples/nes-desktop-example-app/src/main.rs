@@ -14,9 +14,13 @@ extern crate piston_window;
     arg_required_else_help = true,
 )]
 struct Cli {
-    /// Path of the iNES file to be set in the emulator
+    /// Path of the iNES file, or of a zip or gzip archive holding it, to be set in the emulator
     #[clap(short = 'p', long = "path", value_name = "FILE")]
-    file: String
+    file: String,
+
+    /// Name of the archive entry to load instead of the first ROM found
+    #[clap(short = 'e', long = "entry", value_name = "NAME")]
+    entry: Option<String>,
 }
 
 fn main() {
@@ -29,6 +33,15 @@ fn main() {
     };
     println!("{:?}", f);
 
+    let data = match fs::read(&f) {
+        Ok(data) => data,
+        Err(error) => panic!("{:?}", error)
+    };
+    let _nes = match nes::Nes::from_archive(&data, cli.entry.as_deref()) {
+        Ok(nes) => nes,
+        Err(error) => panic!("{:?}", error)
+    };
+
     let width  = nes::RENDER_SCREEN_AREA_WIDTH as u32;
     let height = nes::RENDER_SCREEN_AREA_HEIGHT as u32;
 
@@ -0,0 +1,64 @@
+use std::io::{Cursor, Read};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::errors::ArchiveError;
+
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Extensions of the images looked for inside a zip archive, compared without case.
+const ROM_EXTENSIONS: [&str; 4] = ["nes", "unf", "unif", "fds"];
+
+fn is_rom_name(name: &str) -> bool {
+    name.rsplit_once('.')
+        .is_some_and(|(_, extension)| ROM_EXTENSIONS.iter().any(|v| v.eq_ignore_ascii_case(extension)))
+}
+
+/// Returns the image inside a zip or gzip archive, or a copy of `data` when it is not compressed.
+/// `entry` selects a zip entry by its path or file name instead of the first one with a ROM extension.
+/// A gzip stream holds a single file, so `entry` is ignored for it.
+pub fn extract(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    if data.starts_with(&ZIP_MAGIC) {
+        extract_zip(data, entry)
+    } else if data.starts_with(&GZIP_MAGIC) {
+        let mut rom = vec![];
+        GzDecoder::new(data).read_to_end(&mut rom)
+            .map_err(|error| ArchiveError::Corrupt(error.to_string()))?;
+        Ok(rom)
+    } else {
+        Ok(data.to_vec())
+    }
+}
+
+fn extract_zip(data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    let corrupt = |error: zip::result::ZipError| ArchiveError::Corrupt(error.to_string());
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(corrupt)?;
+
+    // Entry names are listed in the order they are stored.
+    let mut index = None;
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i).map_err(corrupt)?;
+        let name = file.name();
+        let is_match = match entry {
+            Some(entry) => name == entry || name.rsplit('/').next() == Some(entry),
+            None => file.is_file() && is_rom_name(name),
+        };
+        if is_match {
+            index = Some(i);
+            break;
+        }
+    }
+    let index = match (index, entry) {
+        (Some(index), _) => index,
+        (None, Some(entry)) => return Err(ArchiveError::EntryNotFound(entry.to_string())),
+        (None, None) => return Err(ArchiveError::NoRomEntry),
+    };
+
+    let mut file = archive.by_index(index).map_err(corrupt)?;
+    // The size stored in the archive is not trusted for the allocation.
+    let mut rom = vec![];
+    file.read_to_end(&mut rom).map_err(|error| ArchiveError::Corrupt(error.to_string()))?;
+    Ok(rom)
+}
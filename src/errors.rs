@@ -1,4 +1,37 @@
-#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+use rom::{ConsoleType, PatchError, RomError};
+
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub enum EmulationError {
-    InvalidRom
-}
\ No newline at end of file
+    InvalidRom(RomError),
+    #[cfg(feature = "archive")]
+    InvalidArchive(ArchiveError),
+    /// The patch at `index` in the list given to `Nes::from_patched` could not be applied.
+    InvalidPatch { index: usize, error: PatchError },
+    UnsupportedMapper(u16),
+    UnsupportedConsoleType(ConsoleType),
+    /// The mapper has no PRG-RAM at $7000-$71FF to load the trainer into.
+    UnmappedTrainer,
+    NoBatteryRam,
+    /// The save data does not match the battery RAM size, which is given.
+    InvalidSaveRamSize(usize),
+    /// The disk diff passed to `Nes::from_disk` could not be applied.
+    InvalidDiskDiff(PatchError),
+    NoDiskDrive,
+    /// The disk has fewer sides than the given index.
+    InvalidDiskSide(usize),
+    /// The NSF has fewer tracks than the given index.
+    InvalidNsfTrack(u8),
+}
+
+#[cfg(feature = "archive")]
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ArchiveError {
+    /// The zip or gzip data could not be decoded. The message comes from the decoder.
+    Corrupt(String),
+    /// The zip archive has no entry with a ROM extension.
+    NoRomEntry,
+    /// The zip archive has no entry with the requested name.
+    EntryNotFound(String),
+    /// The archive holds a Famicom Disk System image, which needs `Nes::from_disk_archive`.
+    DiskImage,
+}
@@ -1,59 +1,351 @@
+#[cfg(feature = "archive")]
+pub mod archive;
 mod errors;
+mod nsf;
 
-use cpu::Cpu;
+use std::ops::RangeInclusive;
+
+use cpu::{Cpu, Interrupt};
 use ppu::Ppu;
-use memory::Memory;
-use rom::Rom;
-use errors::EmulationError;
+use memory::{Memory, OAM_DMA_SIZE};
+use memory::system::SystemBus;
+use memory::system_hook::{BusHooks, HookedBus};
+use rom::database::GameDatabase;
+pub use errors::EmulationError;
+#[cfg(feature = "archive")]
+pub use errors::ArchiveError;
+pub use nsf::NsfPlayer;
+pub use memory::mapper::{Mapper, Mirroring};
+pub use memory::system_hook::{AccessKind, BusEvent, BusHook, HookAction, HookId};
+pub use rom::Rom;
+pub use rom::mapper::{MapperConstructor, MapperRegistry};
+pub use rom::{ConsoleType, PatchError, RomError};
+pub use rom::database::HeaderFixup;
+pub use rom::nsf::{Nsf, NsfFormat};
 
 pub const RENDER_SCREEN_AREA_WIDTH:  usize = ppu::RENDER_SCREEN_AREA_WIDTH;
 pub const RENDER_SCREEN_AREA_HEIGHT: usize = ppu::RENDER_SCREEN_AREA_HEIGHT;
 
+const TRAINER_OFFSET: usize = 0x1000;
+/// CPU cycles the CPU is halted for by an OAM DMA, one more when it starts on an odd cycle.
+const OAM_DMA_CYCLES: usize = 513;
+
 #[derive(Clone)]
 pub struct Nes {
     cpu: Cpu,
     ppu: Ppu,
     mem: Memory,
     rom: Rom,
+    header_fixups: Vec<HeaderFixup>,
+    /// CPU cycles run since power-on, for the alignment of OAM DMA.
+    cpu_cycles: u64,
+    /// CPU cycles run in the current frame, which a paused `step` resumes.
+    frame_cycles: usize,
+    hooks: BusHooks,
+    is_paused: bool,
+
+    autosave_interval: u32,
+    frames_since_autosave: u32,
+    saved_battery_ram: Vec<u8>,
+    autosave: Option<Vec<u8>>,
+
+    /// The .fds image as given to `from_disk`, which disk diffs are made against.
+    disk_image: Vec<u8>,
 }
 
 #[derive(Clone)]
 pub struct Snapshot {
     pub prg_rom_bytes: usize,
     pub chr_rom_bytes: usize,
+    pub pc: u16,
 }
 
 impl Nes {
-    pub fn from(data: &mut Vec<u8>) -> Result<Nes, EmulationError> {
-        if !Rom::is_valid(data) {
-            return Err(EmulationError::InvalidRom);
+    pub fn from(data: &[u8]) -> Result<Nes, EmulationError> {
+        Nes::from_patched(data, &[])
+    }
+
+    /// Loads a ROM from a zip or gzip archive, or from an uncompressed image.
+    /// See `archive::extract` for how the entry is chosen.
+    #[cfg(feature = "archive")]
+    pub fn from_archive(data: &[u8], entry: Option<&str>) -> Result<Nes, EmulationError> {
+        let data = archive::extract(data, entry).map_err(EmulationError::InvalidArchive)?;
+        if rom::fds::is_disk_image(&data) {
+            return Err(EmulationError::InvalidArchive(ArchiveError::DiskImage));
         }
+        Nes::from(&data)
+    }
+
+    /// Loads a Famicom Disk System image from an archive as `from_archive` does, then as `from_disk` does.
+    #[cfg(feature = "archive")]
+    pub fn from_disk_archive(data: &[u8], bios: &[u8], entry: Option<&str>, diff: Option<&[u8]>) -> Result<Nes, EmulationError> {
+        let image = archive::extract(data, entry).map_err(EmulationError::InvalidArchive)?;
+        Nes::from_disk(&image, bios, diff)
+    }
+
+    /// Applies IPS, UPS or BPS patches in order to a copy of `data` before loading it.
+    pub fn from_patched(data: &[u8], patches: &[&[u8]]) -> Result<Nes, EmulationError> {
+        Nes::from_patched_with_registry(data, patches, &MapperRegistry::default())
+    }
 
+    /// Loads a ROM whose mapper is built by `registry`, which can hold mappers registered outside this crate.
+    pub fn from_with_registry(data: &[u8], registry: &MapperRegistry) -> Result<Nes, EmulationError> {
+        Nes::from_patched_with_registry(data, &[], registry)
+    }
+
+    /// Applies patches as `from_patched` does, then builds the mapper with `registry`.
+    pub fn from_patched_with_registry(data: &[u8], patches: &[&[u8]], registry: &MapperRegistry) -> Result<Nes, EmulationError> {
+        let mut patched = data.to_vec();
+        for (index, patch) in patches.iter().enumerate() {
+            patched = rom::patch::apply(&patched, patch)
+                .map_err(|error| EmulationError::InvalidPatch { index, error })?;
+        }
+        let mut rom = Rom::new(&patched).map_err(EmulationError::InvalidRom)?;
+        let header_fixups = rom.apply_database(GameDatabase::bundled());
+        Nes::load(rom, header_fixups, registry)
+    }
+
+    /// Loads a Famicom Disk System image and the BIOS, then applies a diff from `export_disk_diff`.
+    /// The image itself is never changed, disk writes are only kept in the diff.
+    pub fn from_disk(image: &[u8], bios: &[u8], diff: Option<&[u8]>) -> Result<Nes, EmulationError> {
+        let rom = match diff {
+            Some(diff) => {
+                let patched = rom::patch::apply(image, diff).map_err(EmulationError::InvalidDiskDiff)?;
+                Rom::from_disk(&patched, bios)
+            }
+            None => Rom::from_disk(image, bios),
+        };
+        let mut nes = Nes::load(rom.map_err(EmulationError::InvalidRom)?, vec![], &MapperRegistry::default())?;
+        nes.disk_image = image.to_vec();
+        Ok(nes)
+    }
+
+    fn load(rom: Rom, header_fixups: Vec<HeaderFixup>, registry: &MapperRegistry) -> Result<Nes, EmulationError> {
+        if !matches!(rom.console_type, ConsoleType::Nes | ConsoleType::Extended(0)) {
+            return Err(EmulationError::UnsupportedConsoleType(rom.console_type));
+        }
+        let mut mapper = match registry.create(&rom) {
+            Some(mapper) => mapper,
+            None => return Err(EmulationError::UnsupportedMapper(rom.mapper)),
+        };
+        // The trainer sits at $7000-$71FF, 0x1000 bytes into the PRG-RAM at $6000.
+        if !rom.trainer.is_empty() {
+            match mapper.prg_ram_mut().get_mut(TRAINER_OFFSET..TRAINER_OFFSET + rom.trainer.len()) {
+                Some(ram) => ram.copy_from_slice(&rom.trainer),
+                None => return Err(EmulationError::UnmappedTrainer),
+            }
+        }
+
+        let saved_battery_ram = mapper.prg_ram().to_vec();
         let nes = Nes {
             cpu: Cpu::default(),
             ppu: Ppu::default(),
-            mem: Memory::default(),
-            rom: Rom::new(data),
+            mem: Memory::new(mapper),
+            rom,
+            header_fixups,
+            cpu_cycles: 0,
+            frame_cycles: 0,
+            hooks: BusHooks::default(),
+            is_paused: false,
+            autosave_interval: 0,
+            frames_since_autosave: 0,
+            saved_battery_ram,
+            autosave: None,
+            disk_image: vec![],
         };
-        return Ok(nes);
+        Ok(nes)
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.ppu.reset();
+        self.interrupt(Interrupt::RESET);
     }
 
+    /// Runs a frame, or until a hook asks to pause, in which case the next call finishes the frame.
     pub fn step(&mut self) {
-        let mut total_cycle: usize = 0;
-        while total_cycle < ppu::CPU_CYCLES_PER_DRAW_FRAME {
-            let cpu_cycle = usize::from(self.cpu.step(&mut self.mem));
+        self.is_paused = false;
+        while self.frame_cycles < ppu::CPU_CYCLES_PER_DRAW_FRAME {
+            let pc = self.cpu.pc;
+            let mut cpu_cycle = usize::from(self.with_cpu_bus(|cpu, bus| cpu.step(bus)));
+            if self.mem.is_oam_dma_requested() {
+                // The PPU copies the page on its next step, while the CPU is halted.
+                self.read_oam_dma_through_hooks(pc);
+                let is_odd_cycle = (self.cpu_cycles + cpu_cycle as u64) % 2 == 1;
+                cpu_cycle += OAM_DMA_CYCLES + usize::from(is_odd_cycle);
+            }
+            self.cpu_cycles += cpu_cycle as u64;
+            self.mem.mapper.step(cpu_cycle);
             if let Some(interrupt) = self.ppu.step(cpu_cycle, &mut self.mem) {
-                self.cpu.interrupt(&mut self.mem, interrupt);
+                self.interrupt(interrupt);
+            }
+            if self.mem.mapper.is_irq_pending() {
+                self.interrupt(Interrupt::IRQ);
+            }
+
+            self.frame_cycles += cpu_cycle;
+            if self.hooks.take_pause_request() {
+                self.is_paused = true;
+                return;
             }
+        }
+        self.frame_cycles = 0;
 
-            total_cycle += cpu_cycle;
+        if self.autosave_interval > 0 {
+            self.frames_since_autosave += 1;
+            if self.frames_since_autosave >= self.autosave_interval {
+                self.frames_since_autosave = 0;
+                self.update_autosave();
+            }
         }
     }
 
+    /// Calls `hook` on every access of `kind` to `addresses` made by the CPU.
+    pub fn add_hook(&mut self, kind: AccessKind, addresses: RangeInclusive<u16>, hook: impl BusHook + 'static) -> HookId {
+        self.hooks.add(kind, addresses, Box::new(hook))
+    }
+
+    /// Removes a hook, returning whether it was installed.
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.hooks.remove(id)
+    }
+
+    /// Returns whether the last `step` stopped early because a hook asked to pause.
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    /// Runs `f` on the CPU bus, which only goes through the hooks while any are installed.
+    fn with_cpu_bus<R>(&mut self, f: impl FnOnce(&mut Cpu, &mut dyn SystemBus) -> R) -> R {
+        if self.hooks.is_empty() {
+            return f(&mut self.cpu, &mut self.mem);
+        }
+        let mut bus = HookedBus::new(&mut self.mem, &mut self.hooks, self.cpu.pc, self.cpu_cycles);
+        f(&mut self.cpu, &mut bus)
+    }
+
+    /// Reads the OAM DMA page through the hooks while any are installed, for the instruction at `pc` that requested it.
+    fn read_oam_dma_through_hooks(&mut self, pc: u16) {
+        let Some(base) = self.mem.oam_dma_address().filter(|_| !self.hooks.is_empty()) else {
+            return;
+        };
+        let mut bus = HookedBus::for_oam_dma(&mut self.mem, &mut self.hooks, pc, self.cpu_cycles);
+        let mut data = [0; OAM_DMA_SIZE];
+        for (offset, byte) in (0..).zip(data.iter_mut()) {
+            *byte = bus.read_u8(base + offset);
+        }
+        self.mem.set_oam_dma_data(data);
+    }
+
+    fn interrupt(&mut self, interrupt: Interrupt) {
+        self.with_cpu_bus(|cpu, bus| cpu.interrupt(bus, interrupt));
+    }
+
+    /// Returns the header values the game database corrected when the ROM was loaded.
+    pub fn header_fixups(&self) -> &[HeaderFixup] {
+        &self.header_fixups
+    }
+
+    /// Returns whether the cartridge has battery-backed PRG-RAM that should be saved.
+    pub fn has_battery(&self) -> bool {
+        self.rom.has_battery && !self.mem.mapper.prg_ram().is_empty()
+    }
+
+    /// Returns the battery-backed PRG-RAM, as stored in a `.sav` file.
+    pub fn export_battery_ram(&self) -> Option<Vec<u8>> {
+        if self.has_battery() { Some(self.mem.mapper.prg_ram().to_vec()) } else { None }
+    }
+
+    /// Restores the battery-backed PRG-RAM from the contents of a `.sav` file.
+    pub fn load_battery_ram(&mut self, data: &[u8]) -> Result<(), EmulationError> {
+        if !self.has_battery() {
+            return Err(EmulationError::NoBatteryRam);
+        }
+        let ram = self.mem.mapper.prg_ram_mut();
+        if ram.len() != data.len() {
+            return Err(EmulationError::InvalidSaveRamSize(ram.len()));
+        }
+        ram.copy_from_slice(data);
+        self.saved_battery_ram = data.to_vec();
+        Ok(())
+    }
+
+    /// Checks the battery-backed PRG-RAM every `frames` frames, 0 disables autosaving.
+    /// When it changed since the last check, the new contents are returned by `take_autosave`.
+    pub fn set_autosave_interval(&mut self, frames: u32) {
+        self.autosave_interval = frames;
+        self.frames_since_autosave = 0;
+    }
+
+    /// Returns the battery-backed PRG-RAM if it changed since the last autosave.
+    pub fn take_autosave(&mut self) -> Option<Vec<u8>> {
+        self.autosave.take()
+    }
+
+    fn update_autosave(&mut self) {
+        if !self.has_battery() {
+            return;
+        }
+        let ram = self.mem.mapper.prg_ram();
+        if self.saved_battery_ram.as_slice() != ram {
+            self.saved_battery_ram = ram.to_vec();
+            self.autosave = Some(self.saved_battery_ram.clone());
+        }
+    }
+
+    /// Returns the number of disk sides, 0 for cartridges.
+    pub fn disk_side_count(&self) -> usize {
+        self.mem.mapper.disk_drive().map_or(0, |drive| drive.side_count())
+    }
+
+    /// Returns the inserted disk side, or `None` for cartridges and while the drive is empty.
+    pub fn disk_side(&self) -> Option<usize> {
+        self.mem.mapper.disk_drive().and_then(|drive| drive.inserted_side())
+    }
+
+    /// Inserts a disk side. Games notice a side switch only after the drive was empty for a while,
+    /// so eject the disk for about a second before inserting another side.
+    pub fn insert_disk_side(&mut self, side: usize) -> Result<(), EmulationError> {
+        let drive = self.mem.mapper.disk_drive_mut().ok_or(EmulationError::NoDiskDrive)?;
+        if side >= drive.side_count() {
+            return Err(EmulationError::InvalidDiskSide(side));
+        }
+        drive.insert_side(Some(side));
+        Ok(())
+    }
+
+    pub fn eject_disk(&mut self) -> Result<(), EmulationError> {
+        let drive = self.mem.mapper.disk_drive_mut().ok_or(EmulationError::NoDiskDrive)?;
+        drive.insert_side(None);
+        Ok(())
+    }
+
+    /// Returns an IPS patch from the image given to `from_disk` to the disk as the game wrote it,
+    /// to be stored next to the image and passed back to `from_disk`.
+    pub fn export_disk_diff(&self) -> Option<Vec<u8>> {
+        let drive = self.mem.mapper.disk_drive()?;
+        let header_size = rom::fds::header_size(&self.disk_image);
+        let mut image = self.disk_image[..header_size].to_vec();
+        image.extend(drive.sides().concat());
+        // .fds images are far smaller than the 16MB an IPS patch can address.
+        rom::patch::create_ips(&self.disk_image, &image).ok()
+    }
+
+    /// Returns the byte the CPU would read at `address`, without the side effects of reading it.
+    pub fn peek_u8(&self, address: u16) -> u8 {
+        self.mem.peek_u8(address)
+    }
+
+    /// Fills `buffer` with the CPU address space from `address` upwards, as `peek_u8` sees it.
+    pub fn peek_range(&self, address: u16, buffer: &mut [u8]) {
+        self.mem.peek_range(address, buffer);
+    }
+
     pub fn snapshot(self) -> Snapshot {
         Snapshot {
             prg_rom_bytes: self.rom.prg_rom_bytes,
             chr_rom_bytes: self.rom.chr_rom_bytes,
+            pc: self.cpu.pc,
         }
     }
 }
\ No newline at end of file
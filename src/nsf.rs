@@ -0,0 +1,154 @@
+use cpu::Cpu;
+use memory::Memory;
+use memory::system::SystemBus;
+use rom::TvSystem;
+use rom::mapper::nsf::NsfCartridge;
+use rom::nsf::Nsf;
+
+use crate::EmulationError;
+
+const NTSC_CPU_CLOCK: usize = 1_789_773;
+const PAL_CPU_CLOCK: usize = 1_662_607;
+/// CPU cycles INIT may take before the player gives up waiting for it to return.
+const INIT_CYCLE_LIMIT: usize = NTSC_CPU_CLOCK;
+/// Address outside the NSF memory map that INIT and PLAY return to.
+const RETURN_ADDRESS: u16 = 0x5ff1;
+
+/// Plays NSF and NSFe music by calling its INIT and PLAY routines on the CPU.
+/// APU register writes go to the console bus like those of a game.
+#[derive(Clone)]
+pub struct NsfPlayer {
+    cpu: Cpu,
+    mem: Memory,
+    nsf: Nsf,
+
+    track: u8,
+    /// CPU cycles between PLAY calls.
+    play_period: usize,
+    cycles_until_play: usize,
+    is_in_routine: bool,
+    play_calls: u64,
+}
+
+impl NsfPlayer {
+    /// Loads an NSF or NSFe file and initializes its starting track.
+    pub fn from(data: &[u8]) -> Result<NsfPlayer, EmulationError> {
+        let nsf = Nsf::new(data).map_err(EmulationError::InvalidRom)?;
+        // Dual-region music runs at the NTSC rate.
+        let (clock, period) = match nsf.tv_system {
+            TvSystem::Pal => (PAL_CPU_CLOCK, nsf.pal_play_period),
+            _ => (NTSC_CPU_CLOCK, nsf.ntsc_play_period),
+        };
+        let mut player = NsfPlayer {
+            cpu: Cpu::default(),
+            mem: Memory::new(Box::new(NsfCartridge::new(&nsf))),
+            track: nsf.starting_track,
+            play_period: (usize::from(period) * clock / 1_000_000).max(1),
+            cycles_until_play: 0,
+            is_in_routine: false,
+            play_calls: 0,
+            nsf,
+        };
+        player.select_track(player.track)?;
+        Ok(player)
+    }
+
+    /// Returns the header and NSFe metadata: titles, track names and lengths.
+    pub fn metadata(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    pub fn track_count(&self) -> u8 {
+        self.nsf.track_count
+    }
+
+    /// Returns the track being played, counted from 0.
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    /// Returns how many times PLAY was called since the track was selected.
+    pub fn play_calls(&self) -> u64 {
+        self.play_calls
+    }
+
+    /// Resets the console and runs INIT for a track counted from 0.
+    pub fn select_track(&mut self, track: u8) -> Result<(), EmulationError> {
+        if track >= self.nsf.track_count {
+            return Err(EmulationError::InvalidNsfTrack(track));
+        }
+        self.track = track;
+
+        self.mem.ram.fill(0);
+        self.mem.mapper.prg_ram_mut().fill(0);
+        for address in 0x4000..=0x4013 {
+            self.mem.write_u8(address, 0x00);
+        }
+        self.mem.write_u8(0x4015, 0x00);
+        self.mem.write_u8(0x4015, 0x0f);
+        self.mem.write_u8(0x4017, 0x40);
+        if let Some(banks) = self.nsf.initial_banks {
+            for (address, bank) in (0x5ff8..=0x5fff).zip(banks) {
+                self.mem.write_u8(address, bank);
+            }
+        }
+
+        self.cpu.reset();
+        self.cpu.a = track;
+        self.cpu.x = if self.nsf.tv_system == TvSystem::Pal { 1 } else { 0 };
+        self.call(self.nsf.init_address);
+        let mut cycles = 0;
+        while self.is_in_routine && cycles < INIT_CYCLE_LIMIT {
+            cycles += self.step_cpu();
+        }
+
+        self.cycles_until_play = self.play_period;
+        self.play_calls = 0;
+        Ok(())
+    }
+
+    /// Runs one video frame worth of CPU cycles, calling PLAY at the rate given in the header.
+    pub fn step(&mut self) {
+        let mut total_cycle: usize = 0;
+        while total_cycle < ppu::CPU_CYCLES_PER_DRAW_FRAME {
+            let cpu_cycle = if self.is_in_routine {
+                self.step_cpu()
+            } else {
+                // The CPU idles until the next PLAY call.
+                let idle = self.cycles_until_play.min(ppu::CPU_CYCLES_PER_DRAW_FRAME - total_cycle).max(1);
+                self.mem.mapper.step(idle);
+                idle
+            };
+            total_cycle += cpu_cycle;
+
+            self.cycles_until_play = self.cycles_until_play.saturating_sub(cpu_cycle);
+            if self.cycles_until_play == 0 {
+                self.cycles_until_play = self.play_period;
+                // A PLAY call that has not returned yet skips the next one.
+                if !self.is_in_routine {
+                    self.call(self.nsf.play_address);
+                    self.play_calls += 1;
+                }
+            }
+        }
+    }
+
+    /// Jumps to a routine as JSR would, returning to `RETURN_ADDRESS`.
+    fn call(&mut self, address: u16) {
+        let [hi, lo] = (RETURN_ADDRESS - 1).to_be_bytes();
+        self.mem.write_u8(self.cpu.sp, hi);
+        self.mem.write_u8(self.cpu.sp - 1, lo);
+        self.cpu.sp -= 2;
+        self.cpu.pc = address;
+        self.is_in_routine = true;
+    }
+
+    fn step_cpu(&mut self) -> usize {
+        let cpu_cycle = usize::from(self.cpu.step(&mut self.mem));
+        self.mem.mapper.step(cpu_cycle);
+        if self.cpu.pc == RETURN_ADDRESS {
+            self.is_in_routine = false;
+        }
+        cpu_cycle
+    }
+}
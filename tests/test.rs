@@ -1,21 +1,516 @@
 #[cfg(test)]
 mod tests {
-    use nes::Nes;
+    use nes::{AccessKind, BusEvent, ConsoleType, EmulationError, HeaderFixup, HookAction, Mapper, MapperRegistry, Mirroring, Nes, NsfPlayer, PatchError, Rom, RomError};
+    use std::cell::{Cell, RefCell};
     use std::fs::File;
     use std::io::Read;
+    use std::rc::Rc;
+
+    // NROM-128 image with 8KB of CHR whose PRG is filled with NOPs and resets to $C234, `patch` edits the 16KB PRG bank.
+    fn make_nrom(flags6: u8, patch: impl FnOnce(&mut [u8])) -> Vec<u8> {
+        let mut contents = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01, flags6, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xeau8; 0x4000];
+        prg[0x3ffc] = 0x34;
+        prg[0x3ffd] = 0xc2;
+        patch(&mut prg);
+        contents.extend(prg);
+        contents.extend(vec![0u8; 0x2000]);
+        contents
+    }
 
     #[test]
     #[ignore]
     fn load_rom() -> std::io::Result<()> {
         let mut file = File::open("roms/nes-test-roms/other/demo.nes")?;
         let mut contents = vec![];
-        file.read_to_end(&mut contents);
+        file.read_to_end(&mut contents)?;
 
-        let nes = Nes::from(&mut contents).unwrap();
+        let mut nes = Nes::from(&contents).unwrap();
+        nes.reset();
 
         let snapshot = nes.snapshot();
-        assert_eq!(snapshot.prg_rom_bytes, 1);
-        assert_eq!(snapshot.chr_rom_bytes, 1);
+        assert_eq!(snapshot.prg_rom_bytes, 0x4000);
+        assert_eq!(snapshot.chr_rom_bytes, 0x2000);
+
+        // The reset vector sits at the end of the (mirrored) 16KB PRG bank.
+        let vector = 0x10 + snapshot.prg_rom_bytes - 4;
+        let reset = u16::from(contents[vector]) | (u16::from(contents[vector + 1]) << 8);
+        assert_eq!(snapshot.pc, reset);
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn boot_nrom() {
+        let mut nes = Nes::from(&make_nrom(0x00, |_| {})).unwrap();
+        assert!(nes.header_fixups().is_empty());
+        nes.reset();
+        assert_eq!(nes.snapshot().pc, 0xc234);
+    }
+
+    #[test]
+    fn bundled_database_fixes_header() {
+        // A battery-backed, horizontally mirrored header. The last four CHR bytes give the PRG and CHR data
+        // the CRC32 of Super Mario Bros. in the bundled database.
+        let mut contents = make_nrom(0x02, |_| {});
+        let length = contents.len();
+        contents[length - 4..].copy_from_slice(&[0x89, 0x04, 0x82, 0x62]);
+
+        let mut nes = Nes::from(&contents).unwrap();
+        assert_eq!(nes.header_fixups(), [
+            HeaderFixup::Mirroring { from: Mirroring::Horizontal, to: Mirroring::Vertical },
+            HeaderFixup::Battery { from: true, to: false },
+            HeaderFixup::PrgRamBytes { from: 0x2000, to: 0 },
+        ]);
+        assert!(!nes.has_battery());
+        nes.reset();
+        assert_eq!(nes.snapshot().pc, 0xc234);
+    }
+
+    #[test]
+    fn boot_unif() {
+        let prg = make_nrom(0x00, |_| {})[0x10..0x4010].to_vec();
+        let mut contents = b"UNIF".to_vec();
+        contents.extend([0u8; 28]);
+        for (id, body) in [(b"MAPR", b"NES-NROM-128\0".to_vec()), (b"PRG0", prg), (b"CHR0", vec![0u8; 0x2000])] {
+            contents.extend(id);
+            contents.extend((body.len() as u32).to_le_bytes());
+            contents.extend(body);
+        }
+
+        let mut nes = Nes::from(&contents).unwrap();
+        nes.reset();
+        assert_eq!(nes.snapshot().pc, 0xc234);
+    }
+
+    // Board from outside the crate whose PRG-ROM is a single mirrored 16KB bank.
+    #[derive(Clone)]
+    struct PluginBoard(Vec<u8>);
+
+    impl Mapper for PluginBoard {
+        fn cpu_peek_u8(&self, address: u16) -> Option<u8> {
+            if address >= 0x8000 { Some(self.0[usize::from(address) & 0x3fff]) } else { None }
+        }
+        fn cpu_write_u8(&mut self, _address: u16, _data: u8) {}
+        fn ppu_read_u8(&mut self, _address: u16) -> u8 { 0 }
+        fn ppu_write_u8(&mut self, _address: u16, _data: u8) {}
+        fn mirroring(&self) -> Mirroring { Mirroring::Vertical }
+    }
+
+    #[test]
+    fn plugin_mapper() {
+        // Mapper 200
+        let mut contents = make_nrom(0x80, |_| {});
+        contents[7] = 0xc0;
+        assert_eq!(Nes::from(&contents).err(), Some(EmulationError::UnsupportedMapper(200)));
+
+        let mut registry = MapperRegistry::default();
+        registry.register(200, |rom: &Rom| Box::new(PluginBoard(rom.prg_rom.clone())));
+        let mut nes = Nes::from_with_registry(&contents, &registry).unwrap();
+        nes.reset();
+        assert_eq!(nes.snapshot().pc, 0xc234);
+    }
+
+    // NROM image that stores $42 at $6000 and loops forever.
+    fn make_saving_rom(flags6: u8) -> Vec<u8> {
+        make_nrom(flags6, |prg| {
+            prg[..8].copy_from_slice(&[0xa9, 0x42, 0x8d, 0x00, 0x60, 0x4c, 0x05, 0xc0]);
+            prg[0x3ffc] = 0x00;
+            prg[0x3ffd] = 0xc0;
+        })
+    }
+
+    #[test]
+    fn battery_ram() {
+        let mut nes = Nes::from(&make_saving_rom(0x02)).unwrap();
+        assert!(nes.has_battery());
+        assert_eq!(nes.load_battery_ram(&[0u8; 0x100]), Err(EmulationError::InvalidSaveRamSize(0x2000)));
+
+        let mut save = vec![0u8; 0x2000];
+        save[1] = 0x11;
+        assert_eq!(nes.load_battery_ram(&save), Ok(()));
+        assert_eq!(nes.export_battery_ram(), Some(save));
+
+        let mut nes = Nes::from(&make_saving_rom(0x00)).unwrap();
+        assert!(!nes.has_battery());
+        assert_eq!(nes.export_battery_ram(), None);
+        assert_eq!(nes.load_battery_ram(&[0u8; 0x2000]), Err(EmulationError::NoBatteryRam));
+    }
+
+    #[test]
+    fn autosave_only_when_dirty() {
+        let mut nes = Nes::from(&make_saving_rom(0x02)).unwrap();
+        nes.set_autosave_interval(2);
+        nes.reset();
+
+        nes.step();
+        assert_eq!(nes.take_autosave(), None);
+        nes.step();
+        let save = nes.take_autosave().unwrap();
+        assert_eq!(save[0], 0x42);
+        assert_eq!(nes.take_autosave(), None);
+
+        nes.step();
+        nes.step();
+        assert_eq!(nes.take_autosave(), None);
+    }
+
+    // NROM image with battery RAM that stores to `address` once, then counts loop iterations at $6000-$6001.
+    fn make_counting_rom(address: u16) -> Vec<u8> {
+        let [lo, hi] = address.to_le_bytes();
+        make_nrom(0x02, |prg| {
+            prg[..16].copy_from_slice(&[
+                0xa9, 0x02, 0x8d, lo, hi, 0xee, 0x00, 0x60, 0xd0, 0x03, 0xee, 0x01, 0x60, 0x4c, 0x05, 0xc0,
+            ]);
+            prg[0x3ffc] = 0x00;
+            prg[0x3ffd] = 0xc0;
+        })
+    }
+
+    #[test]
+    fn oam_dma_stalls_cpu() {
+        let mut counts = vec![];
+        for address in [0x0200, 0x4014] {
+            let mut nes = Nes::from(&make_counting_rom(address)).unwrap();
+            nes.reset();
+            nes.step();
+            let mut ram = [0u8; 2];
+            nes.peek_range(0x6000, &mut ram);
+            assert_eq!(nes.peek_u8(0x6000), ram[0]);
+            counts.push(u16::from_le_bytes(ram));
+        }
+        // The DMA starts on cycle 6, so the 513 stall cycles cost 42 loop iterations of 12 cycles and part of another,
+        // which the run without DMA does not finish before the frame ends either.
+        assert_eq!(counts[0] - counts[1], 42);
+    }
+
+    #[test]
+    fn bus_hooks() {
+        let mut nes = Nes::from(&make_counting_rom(0x0200)).unwrap();
+        nes.reset();
+        let executes = Rc::new(Cell::new(0));
+        let writes = Rc::new(RefCell::new(vec![]));
+        let counter = executes.clone();
+        let execute_hook = nes.add_hook(AccessKind::Execute, 0xc005..=0xc005, move |_: &BusEvent| {
+            counter.set(counter.get() + 1);
+            if counter.get() == 10 { HookAction::Pause } else { HookAction::Continue }
+        });
+        let recorder = writes.clone();
+        nes.add_hook(AccessKind::Write, 0x6000..=0x6001, move |event: &BusEvent| {
+            recorder.borrow_mut().push(*event);
+            HookAction::Continue
+        });
+
+        // The instruction that asked to pause completes.
+        nes.step();
+        assert!(nes.is_paused());
+        assert_eq!(executes.get(), 10);
+        assert_eq!(nes.peek_u8(0x6000), 10);
+        let events = writes.borrow().clone();
+        assert_eq!(events.len(), 10);
+        assert_eq!((events[0].kind, events[0].address, events[0].data, events[0].pc), (AccessKind::Write, 0x6000, 1, 0xc005));
+        assert!(events.windows(2).all(|pair| pair[0].cpu_cycle < pair[1].cpu_cycle));
+
+        // The next step finishes the frame, and hooks do not change what is emulated.
+        assert!(nes.remove_hook(execute_hook));
+        nes.step();
+        assert!(!nes.is_paused());
+        let mut plain = Nes::from(&make_counting_rom(0x0200)).unwrap();
+        plain.reset();
+        plain.step();
+        assert_eq!(nes.peek_u8(0x6000), plain.peek_u8(0x6000));
+        assert_eq!(nes.peek_u8(0x6001), plain.peek_u8(0x6001));
+    }
+
+    #[test]
+    fn bus_hooks_see_oam_dma() {
+        let mut nes = Nes::from(&make_counting_rom(0x4014)).unwrap();
+        nes.reset();
+        let reads = Rc::new(RefCell::new(vec![]));
+        let recorder = reads.clone();
+        nes.add_hook(AccessKind::Read, 0x0200..=0x02ff, move |event: &BusEvent| {
+            recorder.borrow_mut().push(*event);
+            HookAction::Continue
+        });
+        nes.step();
+
+        // The page is read once, for the STA $4014 at $C002.
+        let events = reads.borrow().clone();
+        assert_eq!(events.len(), 0x100);
+        assert!(events.iter().zip(0x0200..).all(|(event, address)| {
+            (event.kind, event.address, event.pc) == (AccessKind::Read, address, 0xc002)
+        }));
+
+        let mut plain = Nes::from(&make_counting_rom(0x4014)).unwrap();
+        plain.reset();
+        plain.step();
+        assert_eq!(nes.peek_u8(0x6000), plain.peek_u8(0x6000));
+    }
+
+    #[test]
+    fn load_trainer() {
+        let mut contents = make_nrom(0x06, |_| {});
+        contents.splice(0x10..0x10, (0..0x200).map(|i| i as u8));
+
+        let nes = Nes::from(&contents).unwrap();
+        let ram = nes.export_battery_ram().unwrap();
+        assert!(ram[..0x1000].iter().all(|&v| v == 0));
+        assert!(ram[0x1000..0x1200].iter().enumerate().all(|(i, &v)| v == i as u8));
+        assert!(ram[0x1200..].iter().all(|&v| v == 0));
+
+        // UxROM has no PRG-RAM of its own but gets some for the trainer.
+        let mut contents = make_nrom(0x24, |_| {});
+        contents.splice(0x10..0x10, (0..0x200).map(|i| i as u8));
+        let nes = Nes::from(&contents).unwrap();
+        assert_eq!(nes.peek_u8(0x6000), 0x00);
+        assert_eq!(nes.peek_u8(0x7000), 0x00);
+        assert_eq!(nes.peek_u8(0x71ff), 0xff);
+
+        // Multicart boards have no PRG-RAM at all.
+        contents[6] = 0xf4;
+        assert_eq!(Nes::from(&contents).err(), Some(EmulationError::UnmappedTrainer));
+    }
+
+    #[test]
+    fn patched_rom() {
+        let contents = make_nrom(0x00, |_| {});
+
+        // Moves the reset vector to $E578.
+        let patch = b"PATCH\x00\x40\x0c\x00\x02\x78\xe5EOF";
+        let mut nes = Nes::from_patched(&contents, &[patch]).unwrap();
+        nes.reset();
+        assert_eq!(nes.snapshot().pc, 0xe578);
+        assert_eq!(contents[0x400c], 0x34);
+
+        let error = EmulationError::InvalidPatch { index: 1, error: PatchError::Truncated(5) };
+        assert_eq!(Nes::from_patched(&contents, &[patch, b"PATCH"]).err(), Some(error));
+        let error = EmulationError::InvalidPatch { index: 0, error: PatchError::UnknownFormat };
+        assert_eq!(Nes::from_patched(&contents, &[b"NOTAPATCH"]).err(), Some(error));
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn load_archives() {
+        use nes::ArchiveError;
+        use std::io::{Cursor, Write};
+
+        let contents = make_nrom(0x00, |_| {});
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = zip::write::FileOptions::default();
+        zip.start_file("readme.txt", options).unwrap();
+        zip.write_all(b"Not a ROM").unwrap();
+        zip.start_file("roms/Game.NES", options).unwrap();
+        zip.write_all(&contents).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(&contents).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        for (data, entry) in [(&zip, None), (&zip, Some("Game.NES")), (&zip, Some("roms/Game.NES")), (&gzip, None), (&contents, None)] {
+            let mut nes = Nes::from_archive(data, entry).unwrap();
+            nes.reset();
+            assert_eq!(nes.snapshot().pc, 0xc234);
+        }
+
+        let error = EmulationError::InvalidArchive(ArchiveError::EntryNotFound("other.nes".to_string()));
+        assert_eq!(Nes::from_archive(&zip, Some("other.nes")).err(), Some(error));
+        // Naming an entry skips the extension check, so the text file is parsed as a ROM.
+        assert!(matches!(Nes::from_archive(&zip, Some("readme.txt")), Err(EmulationError::InvalidRom(_))));
+        assert!(matches!(Nes::from_archive(&zip[..zip.len() - 1], None), Err(EmulationError::InvalidArchive(ArchiveError::Corrupt(_)))));
+
+        let mut empty = zip::ZipWriter::new(Cursor::new(vec![]));
+        empty.start_file("readme.txt", zip::write::FileOptions::default()).unwrap();
+        let empty = empty.finish().unwrap().into_inner();
+        assert_eq!(Nes::from_archive(&empty, None).err(), Some(EmulationError::InvalidArchive(ArchiveError::NoRomEntry)));
+    }
+
+    // Two-sided .fds image with the fwNES header, and a BIOS resetting to $EE24.
+    fn make_disk() -> (Vec<u8>, Vec<u8>) {
+        let mut bios = vec![0xeau8; 0x2000];
+        bios[0x1ffc] = 0x24;
+        bios[0x1ffd] = 0xee;
+        let mut image = b"FDS\x1a\x02".to_vec();
+        image.resize(0x10, 0);
+        for _ in 0..2 {
+            let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+            side.resize(65500, 0);
+            image.extend(side);
+        }
+        (image, bios)
+    }
+
+    #[cfg(feature = "archive")]
+    #[test]
+    fn load_disk_archives() {
+        use nes::ArchiveError;
+        use std::io::{Cursor, Write};
+
+        let (image, bios) = make_disk();
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = zip::write::FileOptions::default();
+        zip.start_file("readme.txt", options).unwrap();
+        zip.write_all(b"Not a disk").unwrap();
+        zip.start_file("Game.fds", options).unwrap();
+        zip.write_all(&image).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        for entry in [None, Some("Game.fds")] {
+            let mut nes = Nes::from_disk_archive(&zip, &bios, entry, None).unwrap();
+            assert_eq!(nes.disk_side_count(), 2);
+            nes.reset();
+            assert_eq!(nes.snapshot().pc, 0xee24);
+        }
+        assert_eq!(Nes::from_archive(&zip, None).err(), Some(EmulationError::InvalidArchive(ArchiveError::DiskImage)));
+    }
+
+    #[test]
+    fn famicom_disk_system() {
+        let (image, bios) = make_disk();
+
+        let mut nes = Nes::from_disk(&image, &bios, None).unwrap();
+        assert_eq!(nes.disk_side_count(), 2);
+        assert_eq!(nes.disk_side(), Some(0));
+        nes.eject_disk().unwrap();
+        assert_eq!(nes.disk_side(), None);
+        nes.insert_disk_side(1).unwrap();
+        assert_eq!(nes.disk_side(), Some(1));
+        assert_eq!(nes.insert_disk_side(2), Err(EmulationError::InvalidDiskSide(2)));
+        assert_eq!(nes.export_disk_diff(), Some(b"PATCHEOF".to_vec()));
+        nes.reset();
+        assert_eq!(nes.snapshot().pc, 0xee24);
+
+        // A diff changing the last byte of the first side is applied on load and exported again.
+        let diff = b"PATCH\x00\xff\xeb\x00\x01\x5aEOF".to_vec();
+        let nes = Nes::from_disk(&image, &bios, Some(&diff)).unwrap();
+        assert_eq!(nes.export_disk_diff(), Some(diff));
+        assert_eq!(Nes::from_disk(&image, &bios, Some(b"PATCH")).err(), Some(EmulationError::InvalidDiskDiff(PatchError::Truncated(5))));
+
+        let mut contents = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        contents.extend(vec![0xeau8; 0x4000]);
+        let mut nes = Nes::from(&contents).unwrap();
+        assert_eq!(nes.disk_side_count(), 0);
+        assert_eq!(nes.eject_disk(), Err(EmulationError::NoDiskDrive));
+        assert_eq!(nes.export_disk_diff(), None);
+    }
+
+    #[test]
+    fn nsf_player() {
+        let mut data = b"NESM\x1a\x01\x04\x03".to_vec();
+        data.extend([0x00, 0x80, 0x00, 0x80, 0x0f, 0x80]);
+        for text in [&b"Title"[..], b"Artist", b"Copyright"] {
+            let mut field = text.to_vec();
+            field.resize(32, 0);
+            data.extend(field);
+        }
+        data.extend(16639u16.to_le_bytes());
+        data.extend([0u8; 8]);
+        data.extend(19997u16.to_le_bytes());
+        data.extend([0u8; 6]);
+        // INIT hangs unless called with track 2 on NTSC, PLAY returns at once.
+        data.extend([0xc9, 0x02, 0xf0, 0x03, 0x4c, 0x04, 0x80]);
+        data.extend([0xe0, 0x00, 0xf0, 0x03, 0x4c, 0x0b, 0x80]);
+        data.extend([0x60, 0x60]);
+
+        let mut player = NsfPlayer::from(&data).unwrap();
+        assert_eq!(player.metadata().title, "Title");
+        assert_eq!(player.track_count(), 4);
+        assert_eq!(player.track(), 2);
+        for _ in 0..60 {
+            player.step();
+        }
+        assert!((59..=61).contains(&player.play_calls()));
+
+        player.select_track(0).unwrap();
+        for _ in 0..10 {
+            player.step();
+        }
+        assert_eq!(player.play_calls(), 0);
+        assert_eq!(player.select_track(4).err(), Some(EmulationError::InvalidNsfTrack(4)));
+        assert_eq!(NsfPlayer::from(&data[..0x40]).err(), Some(EmulationError::InvalidRom(RomError::TruncatedHeader(0x40))));
+    }
+
+    #[test]
+    fn rom_errors() {
+        let mut contents = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        contents.extend(vec![0xeau8; 0x4000]);
+        assert_eq!(Nes::from(&contents[..3]).err(), Some(EmulationError::InvalidRom(RomError::TruncatedHeader(3))));
+
+        contents[6] = 0xf0;
+        contents[7] = 0xf0;
+        assert_eq!(Nes::from(&contents).err(), Some(EmulationError::UnsupportedMapper(255)));
+
+        contents[6] = 0x00;
+        contents[7] = 0x01;
+        assert_eq!(Nes::from(&contents).err(), Some(EmulationError::UnsupportedConsoleType(ConsoleType::VsSystem)));
+    }
+
+    // Feeds truncated, bit-flipped and random images to the loader, which must return instead of panicking.
+    #[test]
+    fn malformed_rom_corpus() {
+        let mut valid = vec![0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x12, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        valid.extend(vec![0xeau8; 0x8000]);
+        valid.extend(vec![0u8; 0x2000]);
+
+        let mut corpus: Vec<Vec<u8>> = vec![];
+        for size in (0..64).chain((0..valid.len()).step_by(0x0fff)) {
+            corpus.push(valid[..size].to_vec());
+        }
+
+        // xorshift32 keeps the corpus the same on every run.
+        let mut seed: u32 = 0x2545_f491;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        for _ in 0..2000 {
+            let mut data = valid.clone();
+            for _ in 0..(next() % 4 + 1) {
+                let index = (next() % 16) as usize;
+                data[index] = next() as u8;
+            }
+            // Keep the magic most of the time so the header fields get exercised.
+            if next() % 8 != 0 {
+                data[..4].copy_from_slice(&[0x4e, 0x45, 0x53, 0x1a]);
+            }
+            let size = (next() as usize) % (data.len() + 1);
+            if next() % 2 == 0 {
+                data.truncate(size);
+            }
+            corpus.push(data);
+        }
+
+        for data in corpus {
+            if let Ok(mut nes) = Nes::from(&data) {
+                nes.reset();
+                nes.step();
+            }
+        }
+    }
+
+    // Boots every supported mapper with each NES 2.0 exponent PRG and CHR size from 1 byte to 16KB and no PRG-RAM.
+    #[test]
+    fn mapper_size_sweep() {
+        let registry = MapperRegistry::default();
+        for mapper in (0..=255u16).filter(|&mapper| registry.is_supported(mapper)) {
+            for prg_exponent in 0..=14u8 {
+                for chr_exponent in 0..=14u8 {
+                    let mut data = vec![
+                        0x4e, 0x45, 0x53, 0x1a, prg_exponent << 2, chr_exponent << 2,
+                        (mapper << 4) as u8, (mapper & 0xf0) as u8 | 0x08, 0x00, 0xff, 0x00, 0x00, 0, 0, 0, 0,
+                    ];
+                    data.extend(vec![0xeau8; 1 << prg_exponent]);
+                    data.extend(vec![0u8; 1 << chr_exponent]);
+
+                    let mut nes = Nes::from(&data).unwrap();
+                    nes.reset();
+                    nes.peek_u8(0x6000);
+                    nes.peek_u8(0x8000);
+                    nes.step();
+                }
+            }
+        }
+    }
+}